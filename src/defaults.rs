@@ -1,4 +1,8 @@
-use std::time::Duration;
+use std::{sync::OnceLock, time::Duration};
+
+use tokio::{fs::File, sync::Mutex};
+
+use crate::probes::{CveDatabase, Technology};
 
 pub const TOP_TCP_PORTS: [u16; 1000] = [
     1, 3, 4, 6, 7, 9, 13, 17, 19, 20, 21, 22, 23, 24, 25, 26, 30, 32, 33, 37, 42, 43, 49, 53, 70,
@@ -68,5 +72,30 @@ pub const TOP_TCP_PORTS: [u16; 1000] = [
 ];
 pub static mut CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
 pub static mut READ_TIMEOUT: Duration = Duration::from_secs(1);
-pub static mut USER_AGENT: &'static str = 
+/// Time a service has to greet us before it is probed (the null probe), not waiting when zero
+pub static mut BANNER_TIMEOUT: Duration = Duration::from_secs(1);
+/// Whether the services of open ports are detected, or the ports only listed
+pub static mut SERVICE_DETECTION: bool = true;
+pub static mut SERVER_NAMES: &[String] = &[];
+/// Known TLS fingerprints (JARM or JA3S hash, label)
+pub static mut TLS_FINGERPRINTS: &[(String, String)] = &[];
+/// Whether the JARM fingerprint of TLS servers is computed (10 handshakes)
+pub static mut TLS_JARM: bool = false;
+/// Whether every known ALPN protocol is offered alone, to list those accepted
+pub static mut TLS_ALPN_SWEEP: bool = false;
+/// Whether the names found in certificates or discovered on the way are tried as SNI
+pub static mut TLS_SNI_ENUMERATION: bool = false;
+pub static mut CERT_EXPIRY_WARN_DAYS: u64 = 30;
+pub static mut HTTP_MAX_REDIRECTS: usize = 5;
+/// Technologies recognized in HTTP responses
+pub static mut HTTP_TECHNOLOGIES: &[Technology] = &[];
+/// Name queried with recursion desired, to find open resolvers
+pub static mut DNS_RECURSION_NAME: &str = "example.com.";
+/// Domains for which a zone transfer is attempted
+pub static mut DNS_AXFR_DOMAINS: &[String] = &[];
+/// Where the fingerprints of the services no probe recognized are appended
+pub static FINGERPRINT_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+/// Known vulnerabilities, looked up for the products found
+pub static CVE_DATABASE: OnceLock<CveDatabase> = OnceLock::new();
+pub static mut USER_AGENT: &str =
     "Mozilla/5.0 (compatible; MSIE 9.0; Windows NT 6.1; WOW64; Trident/5.0; chromeframe/12.0.742.112)";
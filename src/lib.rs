@@ -0,0 +1,8 @@
+//! TCP scanner detecting the services of the open ports. Probes of one's own run along with
+//! the built-in ones once registered in the `probes::ProbeRegistry` of a `probes::ScanConfig`.
+
+pub mod defaults;
+pub mod port;
+pub mod probes;
+pub mod tcp;
+mod utils;
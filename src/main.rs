@@ -1,21 +1,26 @@
-use std::{net::IpAddr, time::Duration};
+use std::{fs::OpenOptions, net::IpAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use clap::Parser;
+use futures::channel::mpsc;
+use tokio::{fs::File, net::lookup_host, sync::Mutex};
 
-mod defaults;
-mod port;
-mod probes;
-mod tcp;
-mod utils;
+use clap::{Parser, Subcommand};
 
-use defaults::*;
+use port_scanner::{defaults::*, port, probes, tcp};
 
 #[derive(Debug, Parser)]
-#[command(name = "tcp-scanner", about = "TCP scanner in async Rust")]
+#[command(
+    name = "tcp-scanner",
+    about = "TCP scanner in async Rust",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Opt {
-    /// Host to scan
-    #[arg(short = 'H', long)]
-    host: IpAddr,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Host to scan (IP address or host name)
+    #[arg(short = 'H', long, required = true)]
+    host: Option<String>,
 
     /// Port range
     #[arg(short, long)]
@@ -37,10 +42,19 @@ struct Opt {
     #[arg(short, long, default_value_t = 5000)]
     connect_timeout: u64,
 
-    /// Sets read timeout (in milliseconds) for banner
+    /// Sets read timeout (in milliseconds) for probes
     #[arg(short, long, default_value_t = 2000)]
     read_timeout: u64,
 
+    /// Time (in milliseconds) a service has to send its banner before being probed, 0 to
+    /// probe without waiting
+    #[arg(long, default_value_t = 2000)]
+    banner_timeout: u64,
+
+    /// Only list the open ports, without reading banners nor probing services
+    #[arg(long, conflicts_with_all = ["no_probes", "all_services"])]
+    no_service_detection: bool,
+
     /// Override User-Agent
     #[arg(
         short,
@@ -48,12 +62,141 @@ struct Opt {
         default_value = "Mozilla/5.0 (compatible; MSIE 9.0; Windows NT 6.1; WOW64; Trident/5.0; chromeframe/12.0.742.112)"
     )]
     user_agent: String,
+
+    /// Flag certificates expiring within this many days
+    #[arg(long, default_value_t = 30)]
+    cert_expiry_warn_days: u64,
+
+    /// Server names to try during TLS handshakes (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    sni: Vec<String>,
+
+    /// File of known JARM/JA3S fingerprints ("<hash> <label>" per line), implies --jarm
+    #[arg(long)]
+    tls_fingerprints: Option<PathBuf>,
+
+    /// Compute the JARM fingerprint of TLS servers (10 more handshakes)
+    #[arg(long)]
+    jarm: bool,
+
+    /// Offer each known ALPN protocol alone to list those TLS servers accept
+    #[arg(long)]
+    alpn_sweep: bool,
+
+    /// Try the names of certificates and other discovered names as SNI, looking for other
+    /// virtual hosts
+    #[arg(long)]
+    sni_enum: bool,
+
+    /// Maximum number of HTTP redirects followed on the same server
+    #[arg(long, default_value_t = 5)]
+    max_redirects: usize,
+
+    /// Wappalyzer-style technologies file (JSON) for HTTP fingerprinting
+    #[arg(long)]
+    http_tech_rules: Option<PathBuf>,
+
+    /// Name resolved with recursion desired to detect open DNS resolvers
+    #[arg(long, default_value = "example.com.")]
+    dns_recursion_name: String,
+
+    /// Domains to try a DNS zone transfer (AXFR) for (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    axfr: Vec<String>,
+
+    /// Probe industrial protocols (Modbus, S7, BACnet, DNP3) on their usual ports
+    #[arg(long)]
+    ot_probes: bool,
+
+    /// Minimum interval (in milliseconds) between two checks of the same industrial probe
+    #[arg(long, default_value_t = 1000)]
+    ot_probe_interval: u64,
+
+    /// Maximum number of open ports whose services are checked at the same time
+    #[arg(long, default_value_t = 32)]
+    probe_concurrency: usize,
+
+    /// Only run these probes (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    probes: Vec<String>,
+
+    /// Never run these probes (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    exclude_probes: Vec<String>,
+
+    /// Do not probe services, only match their banners
+    #[arg(long, conflicts_with_all = ["probes", "exclude_probes"])]
+    no_probes: bool,
+
+    /// How thoroughly services are probed
+    #[arg(long, value_enum, default_value_t = probes::Intensity::Normal)]
+    intensity: probes::Intensity,
+
+    /// Keep probing a port once a service is recognized, to find all the services it
+    /// answers (multiplexers such as sslh)
+    #[arg(long)]
+    all_services: bool,
+
+    /// Append fingerprints of the services no probe recognized to this file
+    #[arg(long)]
+    fingerprint_file: Option<PathBuf>,
+
+    /// Vulnerability database (SQLite, filled by update-db) to look the products found up in
+    #[arg(long)]
+    cve_db: Option<PathBuf>,
+
+    /// Directory of probe scripts (*.rhai) run before the built-in probes
+    #[arg(long)]
+    scripts: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Import vulnerability feeds (NVD JSON 1.1 or 2.0, OSV) into the local database
+    UpdateDb {
+        /// Feed files (JSON) to import
+        #[arg(long, required = true)]
+        from: Vec<PathBuf>,
+
+        /// Database to create or update
+        #[arg(long, default_value = "cves.db")]
+        db: PathBuf,
+    },
 }
 
 #[tokio::main]
 async fn main() {
     let opts = Opt::parse();
 
+    let host_name = match opts.command {
+        Some(Command::UpdateDb { from, db }) => {
+            let count = probes::import_cve_feeds(&db, &from[..])
+                .unwrap_or_else(|e| panic!("Cannot import into {}: {}", db.display(), e));
+            eprintln!("Imported {} vulnerabilities into {}", count, db.display());
+            return;
+        }
+        None => opts.host.expect("Host should be required"),
+    };
+
+    let mut server_names = Vec::new();
+    let host: IpAddr = match host_name.parse() {
+        Ok(ip) => ip,
+        Err(_) => {
+            server_names.push(host_name.clone());
+            lookup_host((host_name.as_str(), 0))
+                .await
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .unwrap_or_else(|| panic!("Cannot resolve {:?}", host_name))
+                .ip()
+        }
+    };
+    for name in opts.sni {
+        if !server_names.contains(&name) {
+            server_names.push(name);
+        }
+    }
+
     let ports_spec = opts.port.unwrap_or_default();
     let mut ports = port::PortsList::new();
 
@@ -63,7 +206,9 @@ async fn main() {
         for ps in ports_spec.split(',') {
             match ps.split_once('-') {
                 None => {
-                    let port = ps.parse::<u16>().expect(&format!("Invalid port {:?}", ps));
+                    let port = ps
+                        .parse::<u16>()
+                        .unwrap_or_else(|_| panic!("Invalid port {:?}", ps));
                     ports.add_port(port);
                 }
                 Some(("", "")) => {
@@ -74,7 +219,7 @@ async fn main() {
                 Some((lower, "")) => {
                     let port = lower
                         .parse::<u16>()
-                        .expect(&format!("Invalid port {:?}", lower));
+                        .unwrap_or_else(|_| panic!("Invalid port {:?}", lower));
                     for p in port..=65535 {
                         ports.add_port(p);
                     }
@@ -82,7 +227,7 @@ async fn main() {
                 Some(("", upper)) => {
                     let port = upper
                         .parse::<u16>()
-                        .expect(&format!("Invalid port {:?}", upper));
+                        .unwrap_or_else(|_| panic!("Invalid port {:?}", upper));
                     for p in 1..=port {
                         ports.add_port(p);
                     }
@@ -90,10 +235,10 @@ async fn main() {
                 Some((lower, upper)) => {
                     let lower_port = lower
                         .parse::<u16>()
-                        .expect(&format!("Invalid port {:?}", upper));
+                        .unwrap_or_else(|_| panic!("Invalid port {:?}", upper));
                     let upper_port = upper
                         .parse::<u16>()
-                        .expect(&format!("Invalid port {:?}", upper));
+                        .unwrap_or_else(|_| panic!("Invalid port {:?}", upper));
                     for p in lower_port..=upper_port {
                         ports.add_port(p);
                     }
@@ -117,6 +262,117 @@ async fn main() {
         }
     }
 
+    // SAFETY: only access in write mode during init
+    unsafe {
+        BANNER_TIMEOUT = Duration::from_millis(opts.banner_timeout);
+        SERVICE_DETECTION = !opts.no_service_detection;
+    }
+
+    // SAFETY: only access in write mode during init
+    unsafe {
+        CERT_EXPIRY_WARN_DAYS = opts.cert_expiry_warn_days;
+    }
+
+    // SAFETY: only access in write mode during init
+    unsafe {
+        SERVER_NAMES = Box::leak(server_names.into_boxed_slice());
+    }
+
+    if let Some(ref path) = opts.tls_fingerprints {
+        let fingerprints = probes::load_fingerprint_database(path)
+            .unwrap_or_else(|e| panic!("Cannot read {}: {}", path.display(), e));
+        // SAFETY: only access in write mode during init
+        unsafe {
+            TLS_FINGERPRINTS = Box::leak(fingerprints.into_boxed_slice());
+        }
+    }
+
+    // SAFETY: only access in write mode during init
+    unsafe {
+        TLS_JARM = opts.jarm || opts.tls_fingerprints.is_some();
+        TLS_ALPN_SWEEP = opts.alpn_sweep;
+        TLS_SNI_ENUMERATION = opts.sni_enum;
+    }
+
+    // SAFETY: only access in write mode during init
+    unsafe {
+        HTTP_MAX_REDIRECTS = opts.max_redirects;
+    }
+
+    if let Some(ref path) = opts.http_tech_rules {
+        let technologies = probes::load_technologies(path)
+            .unwrap_or_else(|e| panic!("Cannot read {}: {}", path.display(), e));
+        // SAFETY: only access in write mode during init
+        unsafe {
+            HTTP_TECHNOLOGIES = Box::leak(technologies.into_boxed_slice());
+        }
+    }
+
+    // SAFETY: only access in write mode during init
+    unsafe {
+        DNS_RECURSION_NAME = Box::leak(opts.dns_recursion_name.into_boxed_str());
+        DNS_AXFR_DOMAINS = Box::leak(opts.axfr.into_boxed_slice());
+    }
+
+    if let Some(ref path) = opts.fingerprint_file {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|e| panic!("Cannot open {}: {}", path.display(), e));
+        FINGERPRINT_FILE
+            .set(Mutex::new(File::from_std(file)))
+            .expect("Fingerprint file should be opened once");
+    }
+
+    if let Some(ref path) = opts.cve_db {
+        let database = probes::CveDatabase::open(path)
+            .unwrap_or_else(|e| panic!("Cannot read {}: {}", path.display(), e));
+        if CVE_DATABASE.set(database).is_err() {
+            panic!("Vulnerability database should be opened once");
+        }
+    }
+
+    let mut registry = probes::ProbeRegistry::default();
+    if let Some(ref path) = opts.scripts {
+        let scripts = probes::load_scripts(path)
+            .unwrap_or_else(|e| panic!("Cannot read {}: {}", path.display(), e));
+        for script in scripts {
+            registry.register_with_priority(script, probes::SCRIPT_PRIORITY);
+        }
+    }
+
+    let probe_names = registry.names();
+    for name in opts.probes.iter().chain(opts.exclude_probes.iter()) {
+        if !probe_names.contains(&name.as_str()) {
+            panic!(
+                "Unknown probe {:?}, expected one of {}",
+                name,
+                probe_names.join(", ")
+            );
+        }
+    }
+    for name in probe_names {
+        if opts.exclude_probes.iter().any(|excluded| excluded == name)
+            || !(opts.probes.is_empty() || opts.probes.iter().any(|selected| selected == name))
+        {
+            registry.disable(name);
+        }
+    }
+    let config = Arc::new(probes::ScanConfig {
+        registry,
+        active_probes: !opts.no_probes,
+        intensity: opts.intensity,
+        all_services: opts.all_services,
+        ot_probes: opts.ot_probes,
+        ot_probe_interval: Duration::from_millis(opts.ot_probe_interval),
+        concurrency: if opts.probe_concurrency > 0 {
+            opts.probe_concurrency
+        } else {
+            probes::DEFAULT_CONCURRENCY
+        },
+    });
+
     let boxed_user_agent = opts.user_agent.into_boxed_str();
 
     // SAFETY: only access in write mode during init
@@ -124,14 +380,35 @@ async fn main() {
         USER_AGENT = Box::leak(boxed_user_agent);
     }
 
-    eprintln!("Got {} ports to scan from {}", ports.len(), &opts.host);
+    // The UDP probes check their port when it is to be scanned, or when no port was given
+    let mut udp_ports = ports.clone();
+    if ports_spec.is_empty() {
+        for probe in config.registry.probes() {
+            udp_ports.add_ports(probe.udp_port().as_slice());
+        }
+        udp_ports.remove_ports(&opts.exclude_ports[..]);
+    }
+
+    eprintln!("Got {} ports to scan from {}", ports.len(), host);
 
     let scanner = tcp::TcpScanner::new(ports);
-    let results = scanner
-        .scan(opts.host, opts.verbose)
-        .await
-        .expect("Cannot scan IP");
-    eprintln!("");
+    let (open_ports_sender, open_ports) = mpsc::unbounded();
+    let service_detection = unsafe { SERVICE_DETECTION };
+    let (results, detected) = if service_detection {
+        // Services are detected while the scan goes on
+        tokio::join!(
+            scanner.scan(host, opts.verbose, open_ports_sender),
+            probes::check_open_ports(Arc::clone(&config), host, open_ports),
+        )
+    } else {
+        drop(open_ports);
+        (
+            scanner.scan(host, opts.verbose, open_ports_sender).await,
+            Vec::new(),
+        )
+    };
+    let results = results.expect("Cannot scan IP");
+    eprintln!();
     for p in &results {
         if p.status == port::PortStatus::Closed {
             continue;
@@ -139,10 +416,15 @@ async fn main() {
         if opts.hide_filtered && p.status == port::PortStatus::Filtered {
             continue;
         }
-        println!("{}", p);
-        if p.is_open() && !p.has_banner() {
-            let peer_addr = (opts.host, p.num).into();
-            probes::check_probes(&peer_addr).await;
+        match detected.iter().find(|(detected, _)| detected.num == p.num) {
+            Some((detected, output)) => {
+                println!("{}", detected);
+                print!("{}", output);
+            }
+            None => println!("{}", p),
         }
     }
+    if service_detection {
+        probes::check_udp_probes(&config, host, &udp_ports).await;
+    }
 }
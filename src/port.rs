@@ -1,6 +1,6 @@
 use std::fmt::{self, Write};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PortStatus {
     Opened { banner: Option<Vec<u8>> },
     Closed,
@@ -59,7 +59,7 @@ impl fmt::Display for PortStatus {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Port {
     pub status: PortStatus,
     pub num: u16,
@@ -67,17 +67,7 @@ pub struct Port {
 
 impl Port {
     pub fn is_open(&self) -> bool {
-        match self.status {
-            PortStatus::Opened { .. } => true,
-            _ => false,
-        }
-    }
-
-    pub fn has_banner(&self) -> bool {
-        match self.status {
-            PortStatus::Opened { ref banner } => banner.is_some(),
-            _ => false,
-        }
+        matches!(self.status, PortStatus::Opened { .. })
     }
 }
 
@@ -92,7 +82,7 @@ pub struct PortsListIterator<'a> {
     current: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PortsList([u8; 8192]);
 
 impl PortsList {
@@ -104,6 +94,10 @@ impl PortsList {
         self.0.iter().map(|b| b.count_ones()).sum::<u32>() as usize
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|b| *b == 0)
+    }
+
     pub fn contains(&self, port: u16) -> bool {
         let (index, bit) = Self::get_index_and_bit(port);
         self.0[index] & (1u8 << bit) != 0
@@ -121,8 +115,7 @@ impl PortsList {
 
     pub fn remove_port(&mut self, port: u16) {
         let (index, bit) = Self::get_index_and_bit(port);
-        let mask = 0xff ^ (1u8 << bit);
-        self.0[index] &= 0xff ^ mask;
+        self.0[index] &= !(1u8 << bit);
     }
 
     pub fn add_ports(&mut self, ports: &[u16]) {
@@ -131,7 +124,6 @@ impl PortsList {
         }
     }
 
-    #[allow(dead_code)]
     pub fn remove_ports(&mut self, ports: &[u16]) {
         for port in ports {
             self.remove_port(*port);
@@ -140,12 +132,18 @@ impl PortsList {
 
     pub fn iter<'a>(&'a self) -> PortsListIterator<'a> {
         PortsListIterator {
-            ports_list: &self,
+            ports_list: self,
             current: 1,
         }
     }
 }
 
+impl Default for PortsList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::iter::Iterator for PortsListIterator<'_> {
     type Item = u16;
 
@@ -1,25 +1,190 @@
 use std::{
+    cell::RefCell,
+    fmt::{self, Write as _},
     future::Future,
     io,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     pin::Pin,
-    ptr,
-    sync::atomic::{AtomicPtr, Ordering},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use crate::defaults::READ_TIMEOUT;
-use crate::utils::run_with_timeout;
+use futures::{channel::mpsc, FutureExt, StreamExt};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
 
+use crate::defaults::{BANNER_TIMEOUT, CONNECT_TIMEOUT, READ_TIMEOUT};
+use crate::port::{Port, PortStatus, PortsList};
+use crate::utils::{run_with_timeout, Semaphore};
+
+tokio::task_local! {
+    /// Output of the probes checking a port, printed at once so ports checked concurrently
+    /// do not mix their lines
+    static OUTPUT: RefCell<String>;
+}
+
+/// Writes to the output of the checked port, or to stdout outside of a port's check
+pub(crate) fn write_output(args: fmt::Arguments) {
+    let buffered = OUTPUT.try_with(|output| {
+        let _ = output.borrow_mut().write_fmt(args);
+    });
+    if buffered.is_err() {
+        print!("{}", args);
+    }
+}
+
+/// Like `println!`, for the probes' output
+macro_rules! outputln {
+    () => {
+        $crate::probes::write_output(format_args!("\n"))
+    };
+    ($($arg:tt)*) => {
+        $crate::probes::write_output(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+mod amqp;
+mod bacnet;
+mod banner;
+mod ber;
+mod consul;
+mod cpe;
+mod cve;
+mod dnp3;
 mod dns;
+mod docker;
+mod epm;
+mod etcd;
+mod fingerprint;
+mod ftp;
+mod grpc;
 mod http;
+mod kafka;
+mod kubernetes;
+mod ldap;
+mod memcached;
+mod modbus;
+mod mongodb;
+mod mqtt;
+mod mssql;
+mod mysql;
+mod nats;
+mod ntlm;
+mod portmapper;
+mod postgres;
+mod rdp;
+mod redis;
+mod registry;
+mod rsync;
+mod s7;
+mod script;
+mod smb;
+mod telnet;
 mod tls;
+mod vnc;
+mod x11;
+
+pub use cve::{import as import_cve_feeds, CveDatabase};
+pub use http::tech::{load as load_technologies, Technology};
+pub use registry::ProbeRegistry;
+pub use script::{load as load_scripts, SCRIPT_PRIORITY};
+pub use tls::load_fingerprint_database;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ProbeStatus {
-    Recognized,
+    /// Recognized, along with what the probe learnt about the service
+    Found(Service),
+    /// Found, along with the services the probe identified inside, the first one completing
+    /// the port's stack such as `tls/http`
+    Layered(Service, Vec<Service>),
     Unknown,
 }
 
+/// How bad a finding is for the scanned host's security
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        })
+    }
+}
+
+/// A fact learnt by a probe about a service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub key: String,
+    pub value: String,
+    /// Set when the fact is a security issue
+    pub severity: Option<Severity>,
+}
+
+impl Finding {
+    pub fn new(key: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+            severity: None,
+        }
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = Some(severity);
+        self
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.value)?;
+        if let Some(severity) = self.severity {
+            write!(f, " [{}]", severity)?;
+        }
+        Ok(())
+    }
+}
+
+/// A service recognized by a probe, with its findings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    pub protocol: String,
+    pub findings: Vec<Finding>,
+}
+
+impl Service {
+    pub fn new(protocol: impl Into<String>, findings: Vec<Finding>) -> Self {
+        Self {
+            protocol: protocol.into(),
+            findings,
+        }
+    }
+}
+
+/// How thoroughly the services of open ports are probed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Intensity {
+    /// Probes only run on their favorite ports
+    Light,
+    /// Probes run on their favorite ports, then the ones not rare on the other ports
+    Normal,
+    /// Every probe runs on every port, even once the banner identified the service
+    All,
+}
+
+/// Rarity from which probes only run off their favorite ports at the `all` intensity
+const RARE: u8 = 8;
+
 pub type ProbeCheckFuture = Pin<Box<dyn Future<Output = io::Result<ProbeStatus>> + Send>>;
 
 /// Probe a probe to recognize protocol
@@ -30,55 +195,440 @@ pub trait Probe {
     /// protocol's favorite ports
     fn is_prefered_port(&self, port: u16) -> bool;
 
+    /// Whether the probe builds upon this banner, sent by the service as soon as we connect
+    fn accepts_banner(&self, _banner: &[u8]) -> bool {
+        false
+    }
+
+    /// The protocol's servers always speak first, the probe is pointless without a banner
+    fn expects_banner(&self) -> bool {
+        false
+    }
+
+    /// How seldom the protocol is met off its favorite ports, from 1 (everywhere) to 9
+    fn rarity(&self) -> u8 {
+        5
+    }
+
+    /// Industrial (OT) protocol, where a wrong packet can matter: only probed when asked
+    /// for, on its favorite ports, and no more often than `ScanConfig::ot_probe_interval`
+    fn is_industrial(&self) -> bool {
+        false
+    }
+
+    /// The protocol runs over UDP on this port, it is probed once per host instead of on
+    /// open TCP ports
+    fn udp_port(&self) -> Option<u16> {
+        None
+    }
+
+    /// Time the whole check may take, longer for probes running several steps each bounded
+    /// by the read timeout
+    fn timeout(&self) -> Duration {
+        unsafe { READ_TIMEOUT }
+    }
+
     /// Checks the remote connection
     fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture;
 }
 
-type BoxedProbe = Box<dyn Probe + Send + Sync>;
-static PROBES: AtomicPtr<Vec<BoxedProbe>> = AtomicPtr::new(ptr::null_mut());
+/// Host names learnt while probing (PTR records, ...), reused by other probes
+static DISCOVERED_NAMES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub(crate) fn add_discovered_name(name: &str) {
+    let name = name.trim_end_matches('.');
+    if name.is_empty() {
+        return;
+    }
+    let mut names = DISCOVERED_NAMES.lock().expect("Dead thread");
+    if !names.iter().any(|n| n == name) {
+        names.push(name.to_owned());
+    }
+}
+
+pub(crate) fn discovered_names() -> Vec<String> {
+    DISCOVERED_NAMES.lock().expect("Dead thread").clone()
+}
 
-fn get_probes() -> &'static [BoxedProbe] {
-    let probes_ptr = PROBES.load(Ordering::Relaxed);
-    if probes_ptr.is_null() {
-        let probes = Box::new(vec![
-            Box::new(http::HttpProbe) as BoxedProbe,
-            Box::new(dns::DnsProbe) as BoxedProbe,
-            Box::new(tls::TlsProbe) as BoxedProbe,
-        ]);
+pub type BoxedProbe = Box<dyn Probe + Send + Sync>;
 
-        PROBES.store(Box::leak(probes), Ordering::Relaxed);
+/// Open ports whose services are checked at the same time, unless configured otherwise
+pub const DEFAULT_CONCURRENCY: usize = 32;
+
+/// What the services of a host are detected with, shared by the checks of its ports
+pub struct ScanConfig {
+    /// Probes run against the open ports, the built-in ones by default
+    pub registry: ProbeRegistry,
+    /// Whether services are probed at all, banners are still matched otherwise
+    pub active_probes: bool,
+    pub intensity: Intensity,
+    /// Whether a port is still probed once a service is recognized, for ports answering
+    /// several
+    pub all_services: bool,
+    /// Whether industrial (OT) protocols are probed
+    pub ot_probes: bool,
+    /// Minimum interval between two checks of the same industrial probe
+    pub ot_probe_interval: Duration,
+    /// Maximum number of open ports whose services are checked at the same time
+    pub concurrency: usize,
+}
+
+impl ScanConfig {
+    /// Whether a registered probe may run, industrial probes only when asked for
+    fn is_enabled(&self, probe: &dyn Probe) -> bool {
+        self.active_probes && (!probe.is_industrial() || self.ot_probes)
     }
-    let probes_ptr = PROBES.load(Ordering::Relaxed);
+}
 
-    unsafe { probes_ptr.as_ref() }
-        .expect("Probes should be initiated")
-        .as_slice()
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            registry: ProbeRegistry::default(),
+            active_probes: true,
+            intensity: Intensity::Normal,
+            all_services: false,
+            ot_probes: false,
+            ot_probe_interval: Duration::from_secs(1),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
 }
 
-async fn check_probe(peer_addr: &SocketAddr, probe: &dyn Probe) -> ProbeStatus {
-    match run_with_timeout(unsafe { READ_TIMEOUT }, probe.check(peer_addr.clone())).await {
-        Some(Ok(s)) => s,
-        Some(Err(_)) => ProbeStatus::Unknown,
-        None => ProbeStatus::Unknown,
+/// Next time each industrial probe may start a check, by probe's name
+static NEXT_CHECKS: Mutex<Vec<(&'static str, Instant)>> = Mutex::new(Vec::new());
+
+/// Waits for the turn of an industrial probe, taking the next one
+async fn wait_turn(name: &'static str, interval: Duration) {
+    let turn = {
+        let mut next_checks = NEXT_CHECKS.lock().expect("Dead thread");
+        let now = Instant::now();
+        match next_checks.iter_mut().find(|(probe, _)| *probe == name) {
+            Some((_, next)) => {
+                let turn = (*next).max(now);
+                *next = turn + interval;
+                turn
+            }
+            None => {
+                next_checks.push((name, now + interval));
+                now
+            }
+        }
+    };
+    tokio::time::sleep_until(turn).await;
+}
+
+/// Prints the service found by a probe, followed by its findings, its product normalized
+async fn report(mut service: Service) {
+    cpe::normalize(&mut service);
+    cve::annotate(&mut service.findings).await;
+    outputln!("      Found protocol {}", service.protocol);
+    for finding in service.findings.iter() {
+        outputln!("      - {}", finding);
     }
 }
 
-pub async fn check_probes(peer_addr: &SocketAddr) {
-    // First pass, only check favorite ports
-    for probe in get_probes() {
-        if probe.is_prefered_port(peer_addr.port()) {
-            if check_probe(&peer_addr, probe.as_ref()).await == ProbeStatus::Recognized {
-                return;
+/// What came out of a probe's check
+enum Outcome {
+    /// The protocols recognized, as a stack (`tls/http`)
+    Found(String),
+    /// The service answered, not in the probe's protocol, or dropped the connection
+    Unknown,
+    /// The probe ran out of time: the service may still speak its protocol
+    TimedOut,
+}
+
+/// Checks a probe and reports what it found. The findings of the banner come first, the
+/// probe being one of the protocol it identified.
+async fn check_probe(
+    config: &ScanConfig,
+    peer_addr: &SocketAddr,
+    probe: &BoxedProbe,
+    passive: Option<&Service>,
+) -> Outcome {
+    if probe.is_industrial() {
+        wait_turn(probe.name(), config.ot_probe_interval).await;
+    }
+    // The probe's findings replace the banner's ones of the same key
+    let merge_passive = |service: &mut Service| {
+        if let Some(passive) = passive {
+            let mut findings: Vec<Finding> = passive
+                .findings
+                .iter()
+                .filter(|finding| !service.findings.iter().any(|f| f.key == finding.key))
+                .cloned()
+                .collect();
+            findings.append(&mut service.findings);
+            service.findings = findings;
+        }
+    };
+    match run_with_timeout(probe.timeout(), probe.check(*peer_addr)).await {
+        Some(Ok(ProbeStatus::Layered(mut service, inner))) => {
+            merge_passive(&mut service);
+            let stack = match inner.first() {
+                Some(first) => format!("{}/{}", probe.name(), first.protocol.to_lowercase()),
+                None => probe.name().to_owned(),
+            };
+            report(service).await;
+            for service in inner {
+                report(service).await;
             }
+            Outcome::Found(stack)
         }
+        Some(Ok(ProbeStatus::Found(mut service))) => {
+            merge_passive(&mut service);
+            let protocol = service.protocol.to_lowercase();
+            report(service).await;
+            Outcome::Found(protocol)
+        }
+        Some(Err(e)) if e.kind() == io::ErrorKind::TimedOut => Outcome::TimedOut,
+        Some(Ok(ProbeStatus::Unknown)) | Some(Err(_)) => Outcome::Unknown,
+        None => Outcome::TimedOut,
+    }
+}
+
+/// Whether a probe may still find a service: it found none yet, and none of the recognized
+/// ones uses its protocol
+fn is_compatible(probe: &BoxedProbe, producers: &[&str], services: &[String]) -> bool {
+    !producers.contains(&probe.name())
+        && !services.iter().any(|stack| {
+            stack
+                .split('/')
+                .any(|protocol| protocol.eq_ignore_ascii_case(probe.name()))
+        })
+}
+
+/// Runs the probes against a service. A banner is first matched against the passive
+/// rules: once identified, only the probes of its protocol building upon the banner run
+/// (unless every probe or service should), otherwise every probe may, those accepting the
+/// banner first. Probing stops at the first service recognized, unless all are wanted.
+async fn check_probes(config: &ScanConfig, peer_addr: &SocketAddr, banner: Option<&[u8]>) {
+    let (intensity, all_services) = (config.intensity, config.all_services);
+    let passive = banner.and_then(banner::match_banner);
+    let mut probes: Vec<&BoxedProbe> = config
+        .registry
+        .probes()
+        .filter(|probe| probe.udp_port().is_none() && config.is_enabled(probe.as_ref()))
+        .filter(|probe| !probe.is_industrial() || probe.is_prefered_port(peer_addr.port()))
+        .filter(|probe| match (banner, &passive) {
+            (Some(banner), Some(passive)) if intensity != Intensity::All && !all_services => {
+                probe.accepts_banner(banner) && probe.name().eq_ignore_ascii_case(&passive.protocol)
+            }
+            (Some(_), _) => true,
+            (None, _) => !probe.expects_banner(),
+        })
+        .collect();
+    if let Some(banner) = banner {
+        probes.sort_by_key(|probe| !probe.accepts_banner(banner));
     }
 
-    // Second pass, only check non-favorite ports
-    for probe in get_probes() {
-        if !probe.is_prefered_port(peer_addr.port()) {
-            if check_probe(&peer_addr, probe.as_ref()).await == ProbeStatus::Recognized {
-                return;
+    let mut services = Vec::new();
+    // Names of the probes which found the services
+    let mut producers = Vec::new();
+    let mut passive_reported = false;
+    // Names of the probes which timed out, recorded along with the fingerprint
+    let mut timed_out = Vec::new();
+    // First pass checks favorite ports, the second one the others
+    'passes: for favorite in [true, false] {
+        if !favorite && intensity == Intensity::Light {
+            break;
+        }
+        for probe in probes.iter() {
+            if !all_services && !services.is_empty() {
+                break 'passes;
+            }
+            if probe.is_prefered_port(peer_addr.port()) != favorite
+                || (!favorite && probe.rarity() >= RARE && intensity != Intensity::All)
+                || !is_compatible(probe, &producers[..], &services[..])
+            {
+                continue;
             }
+            let passive = passive
+                .as_ref()
+                .filter(|passive| probe.name().eq_ignore_ascii_case(&passive.protocol));
+            match check_probe(config, peer_addr, probe, passive).await {
+                Outcome::Found(stack) => {
+                    passive_reported |= passive.is_some();
+                    services.push(stack);
+                    producers.push(probe.name());
+                }
+                Outcome::TimedOut => timed_out.push(probe.name()),
+                Outcome::Unknown => {}
+            }
+        }
+    }
+
+    if let Some(ref passive) = passive {
+        let protocol = passive.protocol.to_lowercase();
+        if !passive_reported && !services.contains(&protocol) {
+            report(passive.clone()).await;
+            services.push(protocol);
         }
     }
+    if services.len() > 1 || services.iter().any(|stack| stack.contains('/')) {
+        outputln!("      Services: {}", services.join(", "));
+    }
+    if services.is_empty() && config.active_probes {
+        fingerprint::record(peer_addr, banner, &timed_out[..]).await;
+    }
+}
+
+/// Runs the probes of UDP protocols against a host, on those of their ports to scan
+pub async fn check_udp_probes(config: &ScanConfig, ip: IpAddr, ports: &PortsList) {
+    for probe in config.registry.probes() {
+        let port = match probe.udp_port() {
+            Some(port) if config.is_enabled(probe.as_ref()) && ports.contains(port) => port,
+            _ => continue,
+        };
+        if probe.is_industrial() {
+            wait_turn(probe.name(), config.ot_probe_interval).await;
+        }
+        let peer_addr = SocketAddr::new(ip, port);
+        let output = OUTPUT
+            .scope(RefCell::new(String::new()), async {
+                if let Some(Ok(ProbeStatus::Found(service))) =
+                    run_with_timeout(probe.timeout(), probe.check(peer_addr)).await
+                {
+                    outputln!("{:5}/udp: opened", port);
+                    report(service).await;
+                }
+                OUTPUT.with(|output| output.take())
+            })
+            .await;
+        print!("{}", output);
+    }
+}
+
+/// Connects to a service and waits for its banner, None when it sends nothing in time
+async fn null_probe(peer_addr: &SocketAddr) -> Option<Vec<u8>> {
+    let banner_timeout = unsafe { BANNER_TIMEOUT };
+    if banner_timeout.is_zero() {
+        return None;
+    }
+    let mut stream = run_with_timeout(unsafe { CONNECT_TIMEOUT }, TcpStream::connect(peer_addr))
+        .await?
+        .ok()?;
+    let mut banner = Vec::with_capacity(1024);
+    match run_with_timeout(banner_timeout, stream.read_buf(&mut banner)).await {
+        Some(Ok(n)) if n > 0 => Some(banner),
+        _ => None,
+    }
+}
+
+/// Detects the services of the open ports sent by the scanner as soon as they are found,
+/// returns each port along with its banner and the output of the probes
+pub async fn check_open_ports(
+    config: Arc<ScanConfig>,
+    ip: IpAddr,
+    mut open_ports: mpsc::UnboundedReceiver<Port>,
+) -> Vec<(Port, String)> {
+    let semaphore = Semaphore::new(config.concurrency);
+    let mut checks = Vec::new();
+
+    while let Some(mut port) = open_ports.next().await {
+        let ticket = semaphore.acquire().await;
+        let num = port.num;
+        let config = Arc::clone(&config);
+        checks.push(
+            tokio::spawn(async move {
+                let peer_addr = SocketAddr::new(ip, port.num);
+                let banner = null_probe(&peer_addr).await;
+                let output = OUTPUT
+                    .scope(RefCell::new(String::new()), async {
+                        check_probes(&config, &peer_addr, banner.as_deref()).await;
+                        OUTPUT.with(|output| output.take())
+                    })
+                    .await;
+                drop(ticket);
+                port.status = PortStatus::Opened { banner };
+                (port, output)
+            })
+            .map(move |check| (num, check)),
+        );
+    }
+    let outputs = futures::future::join_all(checks).await;
+
+    // A check that panicked still reports its port, rather than losing it
+    outputs
+        .into_iter()
+        .map(|(num, check)| {
+            check.unwrap_or_else(|e| {
+                let port = Port {
+                    num,
+                    status: PortStatus::Opened { banner: None },
+                };
+                (port, format!("      Service detection failed: {}\n", e))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    use crate::defaults::FINGERPRINT_FILE;
+
+    /// Probe whose check never ends
+    struct SilentProbe;
+
+    impl Probe for SilentProbe {
+        fn name(&self) -> &'static str {
+            "silent"
+        }
+
+        fn is_prefered_port(&self, _port: u16) -> bool {
+            true
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(100)
+        }
+
+        fn check(&self, _peer_addr: SocketAddr) -> ProbeCheckFuture {
+            Box::pin(futures::future::pending())
+        }
+    }
+
+    /// Answers any request with a line no probe knows, then closes the connection
+    async fn unknown_service_fixture() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = [0u8; 512];
+                    let _ = stream.read(&mut request[..]).await;
+                    let _ = stream.write_all(b"?? unknown\r\n").await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn records_fingerprints_of_unknown_services() {
+        let path = std::env::temp_dir().join(format!("fingerprints-{}.txt", std::process::id()));
+        let file = tokio::fs::File::create(&path).await.unwrap();
+        assert!(FINGERPRINT_FILE.set(tokio::sync::Mutex::new(file)).is_ok());
+
+        let addr = unknown_service_fixture().await;
+        let mut registry = ProbeRegistry::new();
+        registry.register(Box::new(SilentProbe));
+        let config = ScanConfig {
+            registry,
+            ..ScanConfig::default()
+        };
+        check_probes(&config, &addr, Some(b"HELLO\r\n")).await;
+
+        let fingerprints = tokio::fs::read_to_string(&path).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+        let line = fingerprints.lines().next().expect("a fingerprint line");
+        assert!(line.starts_with(&format!("{}/tcp v=", addr.port())));
+        assert!(line.contains(r#"null="HELLO\r\n""#));
+        assert!(line.contains(r#"generic-lines="?? unknown\r\n""#));
+        assert!(line.ends_with(" timed-out=silent"));
+    }
 }
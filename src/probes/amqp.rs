@@ -0,0 +1,208 @@
+//! AMQP 0-9-1 brokers (RabbitMQ, Qpid, ...) answer the protocol header with a
+//! Connection.Start method describing themselves.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const PROTOCOL_HEADER: &[u8] = b"AMQP\x00\x00\x09\x01";
+
+const FRAME_METHOD: u8 = 1;
+const FRAME_END: u8 = 0xce;
+
+/// Class and method ids of Connection.Start
+const CONNECTION_START: [u8; 4] = [0, 10, 0, 10];
+
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Server properties worth reporting, most brokers set them
+const SERVER_PROPERTIES: [&str; 5] = [
+    "product",
+    "version",
+    "platform",
+    "cluster_name",
+    "copyright",
+];
+
+pub struct AmqpProbe;
+
+/// Reads the values of an AMQP 0-9-1 payload, in order
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn short_string(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        self.take(len)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+    }
+
+    fn long_string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+    }
+
+    /// Reads a field table, values are formatted as strings
+    fn table(&mut self) -> Option<Vec<(String, String)>> {
+        let len = self.u32()? as usize;
+        let mut table = Reader {
+            data: self.take(len)?,
+        };
+        let mut fields = Vec::new();
+        while !table.data.is_empty() {
+            let name = table.short_string()?;
+            let value = table.value()?;
+            fields.push((name, value));
+        }
+        Some(fields)
+    }
+
+    fn value(&mut self) -> Option<String> {
+        let kind = self.u8()?;
+        Some(match kind {
+            b't' => (self.u8()? != 0).to_string(),
+            b'b' | b'B' => self.u8()?.to_string(),
+            b's' | b'u' => {
+                let b = self.take(2)?;
+                u16::from_be_bytes([b[0], b[1]]).to_string()
+            }
+            b'I' | b'i' => self.u32()?.to_string(),
+            b'l' | b'L' | b'T' => {
+                let b = self.take(8)?;
+                let mut array = [0u8; 8];
+                array.copy_from_slice(b);
+                u64::from_be_bytes(array).to_string()
+            }
+            b'f' => {
+                self.take(4)?;
+                "float".to_owned()
+            }
+            b'd' => {
+                self.take(8)?;
+                "double".to_owned()
+            }
+            b'D' => {
+                self.take(5)?;
+                "decimal".to_owned()
+            }
+            b'S' | b'x' => self.long_string()?,
+            b'F' => {
+                let fields = self.table()?;
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                fields.join(", ")
+            }
+            b'A' => {
+                let len = self.u32()? as usize;
+                let mut array = Reader {
+                    data: self.take(len)?,
+                };
+                let mut values = Vec::new();
+                while !array.data.is_empty() {
+                    values.push(array.value()?);
+                }
+                values.join(", ")
+            }
+            b'V' => String::new(),
+            _ => return None,
+        })
+    }
+}
+
+impl AmqpProbe {
+    /// Parses a Connection.Start method
+    fn start_findings(payload: &[u8]) -> Option<Vec<Finding>> {
+        let mut reader = Reader { data: payload };
+        if reader.take(4)? != CONNECTION_START {
+            return None;
+        }
+        let major = reader.u8()?;
+        let minor = reader.u8()?;
+        let properties = reader.table()?;
+        let mechanisms = reader.long_string()?;
+        let locales = reader.long_string()?;
+
+        let mut findings = vec![Finding::new("protocol", format!("{}-{}", major, minor))];
+        for name in SERVER_PROPERTIES.iter() {
+            if let Some((_, value)) = properties.iter().find(|(n, _)| n == name) {
+                findings.push(Finding::new(*name, value.clone()));
+            }
+        }
+        if let Some((_, capabilities)) = properties.iter().find(|(n, _)| n == "capabilities") {
+            findings.push(Finding::new("capabilities", capabilities.clone()));
+        }
+        findings.push(Finding::new("mechanisms", mechanisms));
+        findings.push(Finding::new("locales", locales));
+        Some(findings)
+    }
+}
+
+impl Probe for AmqpProbe {
+    fn name(&self) -> &'static str {
+        "amqp"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 5672)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            stream.write_all(PROTOCOL_HEADER).await?;
+
+            let mut header = [0u8; 7];
+            stream.read_exact(&mut header[..]).await?;
+            // Brokers not speaking 0-9-1 answer with the protocol header they support
+            if &header[..4] == b"AMQP" {
+                let mut version = [0u8; 1];
+                stream.read_exact(&mut version[..]).await?;
+                let findings = vec![Finding::new(
+                    "protocol",
+                    format!("{}-{}-{} only", header[5], header[6], version[0]),
+                )];
+                return Ok(ProbeStatus::Found(Service::new("AMQP", findings)));
+            }
+
+            let size = u32::from_be_bytes([header[3], header[4], header[5], header[6]]) as usize;
+            if header[0] != FRAME_METHOD || size > MAX_FRAME_SIZE {
+                return Ok(ProbeStatus::Unknown);
+            }
+            let mut payload = vec![0u8; size + 1];
+            stream.read_exact(&mut payload[..]).await?;
+            if payload.pop() != Some(FRAME_END) {
+                return Ok(ProbeStatus::Unknown);
+            }
+
+            match Self::start_findings(&payload[..]) {
+                Some(findings) => Ok(ProbeStatus::Found(Service::new("AMQP", findings))),
+                None => Ok(ProbeStatus::Unknown),
+            }
+        })
+    }
+}
@@ -0,0 +1,263 @@
+//! BACnet/IP devices answer ReadProperty requests on UDP. The device object, addressed by
+//! the wildcard instance, holds the vendor, model and firmware of the controller.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+use crate::utils::run_with_timeout;
+
+const BACNET_PORT: u16 = 47808;
+
+/// Requests are not retried, a lost datagram costs us this much
+const UDP_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_DATAGRAM_SIZE: usize = 1500;
+
+const BVLC_TYPE: u8 = 0x81;
+const BVLC_ORIGINAL_UNICAST: u8 = 0x0a;
+const NPDU_VERSION: u8 = 0x01;
+const NPDU_EXPECTING_REPLY: u8 = 0x04;
+
+const PDU_CONFIRMED_REQUEST: u8 = 0x00;
+const PDU_COMPLEX_ACK: u8 = 0x30;
+const PDU_ERROR: u8 = 0x50;
+const SERVICE_READ_PROPERTY: u8 = 0x0c;
+/// No segmentation, up to 1476 bytes per APDU
+const MAX_APDU: u8 = 0x05;
+
+const OBJECT_DEVICE: u32 = 8;
+/// Instance any device answers for, as if it were its own
+const WILDCARD_INSTANCE: u32 = 4194303;
+
+const PROPERTY_OBJECT_IDENTIFIER: u8 = 75;
+
+/// Device's properties worth reporting, as (id, finding's key)
+const PROPERTIES: [(u8, &str); 8] = [
+    (121, "vendor"),
+    (120, "vendor id"),
+    (70, "model"),
+    (44, "firmware"),
+    (12, "application software"),
+    (77, "object name"),
+    (58, "location"),
+    (28, "description"),
+];
+
+const TAG_UNSIGNED: u8 = 2;
+const TAG_CHARACTER_STRING: u8 = 7;
+const TAG_OBJECT_IDENTIFIER: u8 = 12;
+/// Character set of UTF-8 strings (formerly ANSI X3.4)
+const CHARSET_UTF8: u8 = 0;
+
+pub struct BacnetProbe;
+
+/// Value of a property, as far as the reported properties go
+enum Value {
+    Unsigned(u32),
+    Text(String),
+    ObjectIdentifier(u32, u32),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Unsigned(value) => write!(f, "{}", value),
+            Value::Text(value) => write!(f, "{}", value),
+            Value::ObjectIdentifier(_, instance) => write!(f, "{}", instance),
+        }
+    }
+}
+
+fn read_property_request(invoke_id: u8, instance: u32, property: u8) -> Vec<u8> {
+    let object_identifier = OBJECT_DEVICE << 22 | instance;
+    let mut request = vec![
+        BVLC_TYPE,
+        BVLC_ORIGINAL_UNICAST,
+        0,
+        0,
+        NPDU_VERSION,
+        NPDU_EXPECTING_REPLY,
+        PDU_CONFIRMED_REQUEST,
+        MAX_APDU,
+        invoke_id,
+        SERVICE_READ_PROPERTY,
+        // context tag 0, 4 bytes
+        0x0c,
+    ];
+    request.extend_from_slice(&object_identifier.to_be_bytes());
+    // context tag 1, 1 byte
+    request.extend_from_slice(&[0x19, property]);
+    let size = request.len() as u16;
+    request[2..4].copy_from_slice(&size.to_be_bytes());
+    request
+}
+
+/// APDU of a BVLC datagram, skipping the NPDU and its optional addresses
+fn apdu(datagram: &[u8]) -> Option<&[u8]> {
+    if datagram.len() < 6 || datagram[0] != BVLC_TYPE || datagram[4] != NPDU_VERSION {
+        return None;
+    }
+    let control = datagram[5];
+    // network layer messages carry no APDU
+    if control & 0x80 != 0 {
+        return None;
+    }
+    let mut offset = 6;
+    if control & 0x20 != 0 {
+        // destination network, address length and address
+        offset += 3 + *datagram.get(offset + 2)? as usize;
+    }
+    if control & 0x08 != 0 {
+        // source network, address length and address
+        offset += 3 + *datagram.get(offset + 2)? as usize;
+    }
+    if control & 0x20 != 0 {
+        // hop count
+        offset += 1;
+    }
+    datagram.get(offset..)
+}
+
+/// Parses an application tagged value, the first one of `data`
+fn application_value(data: &[u8]) -> Option<Value> {
+    let tag = *data.first()?;
+    // context tags and extended tag numbers are not expected here
+    if tag & 0x08 != 0 || tag >> 4 == 0x0f {
+        return None;
+    }
+    let (len, value) = match tag & 0x07 {
+        5 => match *data.get(1)? {
+            254 => (
+                u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) as usize,
+                data.get(4..)?,
+            ),
+            255 => return None,
+            len => (len as usize, data.get(2..)?),
+        },
+        len => (len as usize, data.get(1..)?),
+    };
+    let value = value.get(..len)?;
+    match tag >> 4 {
+        TAG_UNSIGNED if (1..=4).contains(&len) => Some(Value::Unsigned(
+            value.iter().fold(0, |acc, byte| acc << 8 | *byte as u32),
+        )),
+        TAG_CHARACTER_STRING if value.first() == Some(&CHARSET_UTF8) => Some(Value::Text(
+            String::from_utf8_lossy(&value[1..]).trim().to_owned(),
+        )),
+        TAG_OBJECT_IDENTIFIER if len == 4 => {
+            let id = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+            Some(Value::ObjectIdentifier(id >> 22, id & 0x3fffff))
+        }
+        _ => None,
+    }
+}
+
+/// Outcome of a ReadProperty request
+enum Answer {
+    Value(Option<Value>),
+    /// The property is unknown, or reading it is refused
+    Error,
+}
+
+/// Parses the answer to a ReadProperty request, None when it is not one
+fn read_property_answer(apdu: &[u8], invoke_id: u8) -> Option<Answer> {
+    match apdu {
+        [PDU_COMPLEX_ACK, id, SERVICE_READ_PROPERTY, rest @ ..] if *id == invoke_id => {
+            // object identifier and property, with an optional array index, then the
+            // value between opening and closing tags 3
+            let mut offset = 0;
+            while let Some(tag) = rest.get(offset).filter(|tag| **tag != 0x3e) {
+                // context tags 0 to 2, their length in the low bits
+                if tag & 0x08 == 0 || tag & 0x07 > 4 {
+                    return None;
+                }
+                offset += 1 + (tag & 0x07) as usize;
+            }
+            Some(Answer::Value(application_value(rest.get(offset + 1..)?)))
+        }
+        [PDU_ERROR, id, SERVICE_READ_PROPERTY, ..] if *id == invoke_id => Some(Answer::Error),
+        _ => None,
+    }
+}
+
+impl BacnetProbe {
+    async fn read_property(
+        socket: &UdpSocket,
+        invoke_id: u8,
+        instance: u32,
+        property: u8,
+    ) -> std::io::Result<Option<Answer>> {
+        socket
+            .send(&read_property_request(invoke_id, instance, property)[..])
+            .await?;
+        let mut datagram = vec![0u8; MAX_DATAGRAM_SIZE];
+        let size = match run_with_timeout(UDP_TIMEOUT, socket.recv(&mut datagram[..])).await {
+            Some(size) => size?,
+            None => return Ok(None),
+        };
+        Ok(apdu(&datagram[..size]).and_then(|apdu| read_property_answer(apdu, invoke_id)))
+    }
+}
+
+impl Probe for BacnetProbe {
+    fn name(&self) -> &'static str {
+        "bacnet"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, BACNET_PORT)
+    }
+
+    fn is_industrial(&self) -> bool {
+        true
+    }
+
+    fn udp_port(&self) -> Option<u16> {
+        Some(BACNET_PORT)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let local_addr: SocketAddr = match peer_addr {
+                SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+            };
+            let socket = UdpSocket::bind(local_addr).await?;
+            socket.connect(peer_addr).await?;
+
+            let instance = match Self::read_property(
+                &socket,
+                0,
+                WILDCARD_INSTANCE,
+                PROPERTY_OBJECT_IDENTIFIER,
+            )
+            .await?
+            {
+                Some(Answer::Value(Some(Value::ObjectIdentifier(OBJECT_DEVICE, instance)))) => {
+                    instance
+                }
+                Some(_) => WILDCARD_INSTANCE,
+                None => return Ok(ProbeStatus::Unknown),
+            };
+
+            let mut findings = Vec::new();
+            if instance != WILDCARD_INSTANCE {
+                findings.push(Finding::new("device instance", instance.to_string()));
+            }
+            for (invoke_id, (property, key)) in (1..).zip(PROPERTIES.iter()) {
+                if let Some(Answer::Value(Some(value))) =
+                    Self::read_property(&socket, invoke_id, instance, *property).await?
+                {
+                    let value = value.to_string();
+                    if !value.is_empty() {
+                        findings.push(Finding::new(*key, value));
+                    }
+                }
+            }
+
+            Ok(ProbeStatus::Found(Service::new("BACnet/IP", findings)))
+        })
+    }
+}
@@ -0,0 +1,301 @@
+//! Passive identification of the services greeting us: the banner read by the scanner is
+//! matched against rules, whose named groups capture the product, its version and more.
+
+use std::sync::OnceLock;
+
+use regex::bytes::Regex;
+
+use super::{
+    cpe::{self, product, Product},
+    Finding, Service,
+};
+
+/// A banner rule: the protocol, the product with its vendor and CPE when the pattern does
+/// not capture it, and the pattern. Rules are tried in order, specific ones before generic
+/// ones.
+struct Rule {
+    protocol: &'static str,
+    product: Option<Product>,
+    pattern: &'static str,
+}
+
+const fn rule(protocol: &'static str, product: Option<Product>, pattern: &'static str) -> Rule {
+    Rule {
+        protocol,
+        product,
+        pattern,
+    }
+}
+
+/// Named groups of the patterns, as (group, finding's key)
+const GROUPS: [(&str, &str); 4] = [
+    ("product", "product"),
+    ("version", "version"),
+    ("info", "info"),
+    ("hostname", "hostname"),
+];
+
+/// Products of several rules
+const DOVECOT: Product = product("Dovecot", "Dovecot", "dovecot", "dovecot");
+const MICROSOFT_EXCHANGE: Product = product(
+    "Microsoft Exchange",
+    "Microsoft",
+    "microsoft",
+    "exchange_server",
+);
+
+const RULES: &[Rule] = &[
+    // SSH
+    rule(
+        "SSH",
+        Some(product("OpenSSH", "OpenBSD", "openbsd", "openssh")),
+        r"^SSH-[\d.]+-OpenSSH_(?P<version>[\w.]+)(?:[ -](?P<info>[^\r\n]+))?",
+    ),
+    rule(
+        "SSH",
+        Some(product(
+            "Dropbear",
+            "Matt Johnston",
+            "dropbear_ssh_project",
+            "dropbear_ssh",
+        )),
+        r"^SSH-[\d.]+-dropbear_(?P<version>[\w.]+)",
+    ),
+    rule(
+        "SSH",
+        Some(product("libssh", "libssh", "libssh", "libssh")),
+        r"^SSH-[\d.]+-libssh[_-](?P<version>[\w.]+)",
+    ),
+    rule(
+        "SSH",
+        None,
+        r"^SSH-[\d.]+-(?P<product>[^\s_\r\n]+)(?:_(?P<version>[^\s\r\n]+))?",
+    ),
+    // FTP
+    rule(
+        "FTP",
+        Some(product("vsftpd", "Chris Evans", "vsftpd_project", "vsftpd")),
+        r"^220[ -]\(vsFTPd (?P<version>[\w.]+)\)",
+    ),
+    rule(
+        "FTP",
+        Some(product("ProFTPD", "ProFTPD Project", "proftpd", "proftpd")),
+        r"^220[ -]ProFTPD (?:(?P<version>\d[\w.]*) )?Server",
+    ),
+    rule(
+        "FTP",
+        Some(product("Pure-FTPd", "Pure-FTPd", "pureftpd", "pure-ftpd")),
+        r"^220[ -]-+ Welcome to Pure-FTPd",
+    ),
+    rule(
+        "FTP",
+        Some(product(
+            "FileZilla Server",
+            "FileZilla",
+            "filezilla-project",
+            "filezilla_server",
+        )),
+        r"^220[ -]FileZilla Server(?: version)? (?P<version>\d[\w.]*)",
+    ),
+    rule(
+        "FTP",
+        Some(product(
+            "Microsoft FTP Service",
+            "Microsoft",
+            "microsoft",
+            "ftp_service",
+        )),
+        r"^220[ -]Microsoft FTP Service",
+    ),
+    // SMTP
+    rule(
+        "SMTP",
+        Some(product("Postfix", "Postfix", "postfix", "postfix")),
+        r"^220[ -](?P<hostname>\S+) ESMTP Postfix(?: \((?P<info>[^)]+)\))?",
+    ),
+    rule(
+        "SMTP",
+        Some(product("Exim", "Exim", "exim", "exim")),
+        r"^220[ -](?P<hostname>\S+) ESMTP Exim (?P<version>[\w.]+)",
+    ),
+    rule(
+        "SMTP",
+        Some(product("Sendmail", "Proofpoint", "sendmail", "sendmail")),
+        r"^220[ -](?P<hostname>\S+) ESMTP Sendmail (?P<version>[\w.]+)(?:/(?P<info>[\w.]+))?",
+    ),
+    rule(
+        "SMTP",
+        Some(MICROSOFT_EXCHANGE),
+        r"^220[ -](?P<hostname>\S+) Microsoft ESMTP MAIL Service(?:, Version: (?P<version>[\d.]+))?",
+    ),
+    rule(
+        "SMTP",
+        Some(product("OpenSMTPD", "OpenBSD", "openbsd", "opensmtpd")),
+        r"^220[ -](?P<hostname>\S+) ESMTP OpenSMTPD",
+    ),
+    rule(
+        "SMTP",
+        None,
+        r"^220[ -](?P<hostname>\S+) (?:E?SMTP|[^\r\n]*\bE?SMTP\b)",
+    ),
+    // After SMTP, whose host names may start with "ftp."
+    rule("FTP", None, r"(?i)^220[ -][^\r\n]*\bftp"),
+    // POP3
+    rule("POP3", Some(DOVECOT), r"^\+OK (?:\[[^\]]*\] )?Dovecot"),
+    rule(
+        "POP3",
+        Some(product(
+            "Courier",
+            "Courier",
+            "courier-mta",
+            "courier_mail_server",
+        )),
+        r"^\+OK Hello there|^\+OK [^\r\n]*Courier",
+    ),
+    rule("POP3", None, r"(?i)^\+OK [^\r\n]*pop"),
+    // IMAP
+    rule(
+        "IMAP",
+        Some(DOVECOT),
+        r"^\* OK (?:\[[^\]]*\] )?[^\r\n]*Dovecot",
+    ),
+    rule(
+        "IMAP",
+        Some(product(
+            "Courier-IMAP",
+            "Courier",
+            "courier-mta",
+            "courier-imap",
+        )),
+        r"^\* OK [^\r\n]*Courier-IMAP",
+    ),
+    rule(
+        "IMAP",
+        Some(product(
+            "Cyrus IMAP",
+            "Carnegie Mellon University",
+            "cmu",
+            "cyrus_imap_server",
+        )),
+        r"^\* OK [^\r\n]*Cyrus IMAP4? v?(?P<version>\d[\w.-]*)",
+    ),
+    rule(
+        "IMAP",
+        Some(MICROSOFT_EXCHANGE),
+        r"^\* OK [^\r\n]*Microsoft Exchange",
+    ),
+    rule("IMAP", None, r"(?i)^\* OK [^\r\n]*imap"),
+    // MySQL handshake: packet length, sequence number 0, protocol 10, version
+    rule(
+        "MySQL",
+        Some(cpe::MARIADB),
+        r"(?s-u)^.{3}\x00\x0a(?:5\.5\.5-)?(?P<version>[\d.]+)-MariaDB",
+    ),
+    rule(
+        "MySQL",
+        Some(cpe::MYSQL),
+        r"(?s-u)^.{3}\x00\x0a(?P<version>\d[\w.-]*)\x00",
+    ),
+    // NNTP
+    rule(
+        "NNTP",
+        Some(product("INN", "ISC", "isc", "inn")),
+        r"^20[01] [^\r\n]*InterNetNews (?:server )?INN (?P<version>[\w.]+)",
+    ),
+    rule("NNTP", None, r"(?i)^20[01] [^\r\n]*\bnntp"),
+    // Others, whose probes building upon the banner report the protocol version
+    rule("VNC", None, r"^RFB \d{3}\.\d{3}\n"),
+    rule("rsync", None, r"^@RSYNCD: [\d.]+"),
+    rule(
+        "NATS",
+        None,
+        r#"^INFO \{[^\r\n]*"version":"(?P<version>[^"]+)""#,
+    ),
+];
+
+fn compiled_rules() -> &'static [(&'static Rule, Regex)] {
+    static COMPILED: OnceLock<Vec<(&'static Rule, Regex)>> = OnceLock::new();
+    COMPILED.get_or_init(|| {
+        RULES
+            .iter()
+            .map(|rule| (rule, Regex::new(rule.pattern).expect("valid regex")))
+            .collect()
+    })
+}
+
+/// Identifies a service by its banner, None when no rule matches
+pub fn match_banner(banner: &[u8]) -> Option<Service> {
+    compiled_rules().iter().find_map(|(rule, regex)| {
+        let captures = regex.captures(banner)?;
+        let group = |name| {
+            captures
+                .name(name)
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).trim().to_owned())
+                .filter(|value| !value.is_empty())
+        };
+        let mut findings = Vec::new();
+        if let Some(ref product) = rule.product {
+            let version = group("version");
+            let normalized = version.as_deref().and_then(cpe::normalize_version);
+            findings = cpe::product_findings(product, normalized.as_deref());
+            // an unusual version is kept as is, for lack of a normalized one
+            if let (Some(version), None) = (version, normalized) {
+                findings.push(Finding::new("version", version));
+            }
+        }
+        for (name, key) in GROUPS {
+            if rule.product.is_some() && matches!(name, "product" | "version") {
+                continue;
+            }
+            if let Some(value) = group(name) {
+                findings.push(Finding::new(key, value));
+            }
+        }
+        Some(Service::new(rule.protocol, findings))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_product_of_rules() {
+        let service = match_banner(b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6\r\n").unwrap();
+        assert_eq!(
+            service,
+            Service::new(
+                "SSH",
+                vec![
+                    Finding::new("product", "OpenSSH"),
+                    Finding::new("vendor", "OpenBSD"),
+                    Finding::new("version", "8.9p1"),
+                    Finding::new("cpe", "cpe:2.3:a:openbsd:openssh:8.9:p1:*:*:*:*:*:*"),
+                    Finding::new("info", "Ubuntu-3ubuntu0.6"),
+                ]
+            )
+        );
+
+        let service = match_banner(b"220 mail.example.com ESMTP Postfix (Debian/GNU)\r\n").unwrap();
+        assert_eq!(
+            service.findings[..2],
+            [
+                Finding::new("product", "Postfix"),
+                Finding::new("vendor", "Postfix"),
+            ]
+        );
+        assert_eq!(
+            service.findings[2],
+            Finding::new("cpe", "cpe:2.3:a:postfix:postfix:*:*:*:*:*:*:*:*")
+        );
+    }
+
+    #[test]
+    fn captures_the_product_of_generic_rules() {
+        let service = match_banner(b"SSH-2.0-Go\r\n").unwrap();
+        assert_eq!(
+            service,
+            Service::new("SSH", vec![Finding::new("product", "Go")])
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! BER encoding of the ASN.1 structures spoken by LDAP, or wrapping NTLM (SPNEGO,
+//! CredSSP), we only encode the DER subset.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub(super) const BOOLEAN: u8 = 0x01;
+pub(super) const INTEGER: u8 = 0x02;
+pub(super) const OCTET_STRING: u8 = 0x04;
+pub(super) const OID: u8 = 0x06;
+pub(super) const ENUMERATED: u8 = 0x0a;
+pub(super) const SEQUENCE: u8 = 0x30;
+pub(super) const SET: u8 = 0x31;
+
+/// Encodes an element
+pub(super) fn encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        element.push(len as u8);
+    } else if len < 0x100 {
+        element.extend_from_slice(&[0x81, len as u8]);
+    } else {
+        element.push(0x82);
+        element.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+    element.extend_from_slice(content);
+    element
+}
+
+/// Reads an element no larger than `max_size`, returns it whole
+pub(super) async fn read<S>(stream: &mut S, max_size: usize) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut element = vec![0u8; 2];
+    stream.read_exact(&mut element[..]).await?;
+    let length = match element[1] {
+        length if length < 0x80 => length as usize,
+        0x81..=0x84 => {
+            let mut bytes = vec![0u8; (element[1] - 0x80) as usize];
+            stream.read_exact(&mut bytes[..]).await?;
+            element.extend_from_slice(&bytes[..]);
+            bytes
+                .iter()
+                .fold(0, |length, b| (length << 8) | *b as usize)
+        }
+        _ => max_size + 1,
+    };
+    if length > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "BER element too large",
+        ));
+    }
+    let start = element.len();
+    element.resize(start + length, 0);
+    stream.read_exact(&mut element[start..]).await?;
+    Ok(element)
+}
+
+/// Splits the first element of `data` as its tag, its content and the data following it
+pub(super) fn parse(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&first, data) = data.split_first()?;
+    let (length, data) = match first {
+        length if length < 0x80 => (length as usize, data),
+        0x81..=0x84 => {
+            let count = (first - 0x80) as usize;
+            let bytes = data.get(..count)?;
+            let length = bytes
+                .iter()
+                .fold(0, |length, b| (length << 8) | *b as usize);
+            (length, &data[count..])
+        }
+        _ => return None,
+    };
+    let content = data.get(..length)?;
+    Some((tag, content, &data[length..]))
+}
+
+/// Reads the content of an INTEGER or an ENUMERATED
+pub(super) fn integer(content: &[u8]) -> Option<i64> {
+    if content.is_empty() || content.len() > 8 {
+        return None;
+    }
+    let sign = if content[0] & 0x80 != 0 { -1 } else { 0 };
+    Some(
+        content
+            .iter()
+            .fold(sign, |value, b| (value << 8) | *b as i64),
+    )
+}
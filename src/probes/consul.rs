@@ -0,0 +1,143 @@
+//! HashiCorp Consul and Nomad agents share their HTTP API conventions: `/v1/agent/self`
+//! describes the agent unless ACLs deny it, `/v1/status/leader` is always public. Without
+//! ACLs, anyone can register checks or submit jobs, which run commands.
+
+use std::net::SocketAddr;
+
+use super::{
+    http::{self, HttpProbe, Response},
+    Finding, Probe, ProbeCheckFuture, ProbeStatus, Service, Severity,
+};
+use crate::defaults::SERVER_NAMES;
+
+const CONSUL_FIELDS: [(&str, &str); 4] = [
+    ("/Config/Version", "version"),
+    ("/Config/Datacenter", "datacenter"),
+    ("/Config/NodeName", "node name"),
+    ("/Config/Server", "server"),
+];
+
+const NOMAD_FIELDS: [(&str, &str); 4] = [
+    ("/config/Version/Version", "version"),
+    ("/config/Region", "region"),
+    ("/config/Datacenter", "datacenter"),
+    ("/member/Name", "node name"),
+];
+
+const NOMAD_PORT: u16 = 4646;
+
+pub struct ConsulProbe;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Product {
+    Consul,
+    Nomad,
+}
+
+/// Tells Consul from Nomad by the headers they add to their responses
+fn product_from_headers(response: &Response) -> Option<Product> {
+    let has_header = |prefix: &str| {
+        response
+            .headers
+            .iter()
+            .any(|(name, _)| name.to_ascii_lowercase().starts_with(prefix))
+    };
+    if has_header("x-consul-") {
+        Some(Product::Consul)
+    } else if has_header("x-nomad-") {
+        Some(Product::Nomad)
+    } else {
+        None
+    }
+}
+
+impl Probe for ConsulProbe {
+    fn name(&self) -> &'static str {
+        "consul"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 8500 | 8501 | NOMAD_PORT)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let server_name = unsafe { SERVER_NAMES }.first().map(|name| name.as_str());
+            let (scheme, agent) =
+                match HttpProbe::get_any_scheme(peer_addr, server_name, "/v1/agent/self").await? {
+                    Some(answer) => answer,
+                    None => return Ok(ProbeStatus::Unknown),
+                };
+            let agent_json = agent.json().filter(|_| agent.status == 200);
+            let product = match &agent_json {
+                Some(json) if json.pointer("/Config/Datacenter").is_some() => Product::Consul,
+                Some(json) if json.pointer("/config/Region").is_some() => Product::Nomad,
+                _ => {
+                    // The leader's address, or an empty string, is given to anyone
+                    let leader = HttpProbe::request(
+                        peer_addr,
+                        scheme,
+                        server_name,
+                        "GET",
+                        "/v1/status/leader",
+                        None,
+                    )
+                    .await?;
+                    match leader {
+                        Some(leader) if leader.json().is_some_and(|json| json.is_string()) => {
+                            product_from_headers(&leader)
+                                .or_else(|| product_from_headers(&agent))
+                                .unwrap_or(if peer_addr.port() == NOMAD_PORT {
+                                    Product::Nomad
+                                } else {
+                                    Product::Consul
+                                })
+                        }
+                        _ => return Ok(ProbeStatus::Unknown),
+                    }
+                }
+            };
+
+            let mut findings = vec![Finding::new("scheme", scheme.name())];
+            // Consul only runs commands when script checks are enabled, Nomad always does
+            let (fields, listing, listed, severity) = match product {
+                Product::Consul => (
+                    &CONSUL_FIELDS,
+                    "/v1/catalog/services",
+                    "services",
+                    Severity::High,
+                ),
+                Product::Nomad => (&NOMAD_FIELDS, "/v1/jobs", "jobs", Severity::Critical),
+            };
+            match agent_json {
+                Some(json) => {
+                    findings.extend(http::json_findings(&json, fields));
+                    findings.push(
+                        Finding::new("acl", "disabled or allowing anonymous access")
+                            .with_severity(severity),
+                    );
+                    if let Ok(Some(response)) =
+                        HttpProbe::request(peer_addr, scheme, server_name, "GET", listing, None)
+                            .await
+                    {
+                        let count = match response.json() {
+                            Some(serde_json::Value::Array(items)) => Some(items.len()),
+                            Some(serde_json::Value::Object(items)) => Some(items.len()),
+                            _ => None,
+                        };
+                        if let Some(count) = count {
+                            findings.push(Finding::new(listed, count.to_string()));
+                        }
+                    }
+                }
+                None => findings.push(Finding::new("acl", "enabled")),
+            }
+
+            let protocol = match product {
+                Product::Consul => "Consul",
+                Product::Nomad => "Nomad",
+            };
+            Ok(ProbeStatus::Found(Service::new(protocol, findings)))
+        })
+    }
+}
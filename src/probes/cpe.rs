@@ -0,0 +1,264 @@
+//! Normalization of the products found by probes and rules: their usual name, vendor and
+//! version, and their CPE 2.3 identifier, for tools correlating results with advisories.
+
+use super::{Finding, Service};
+
+/// A known product: its name, vendor, and the vendor and product parts of its CPE
+pub(super) struct Product {
+    name: &'static str,
+    vendor: &'static str,
+    cpe_vendor: &'static str,
+    cpe_product: &'static str,
+}
+
+pub(super) const fn product(
+    name: &'static str,
+    vendor: &'static str,
+    cpe_vendor: &'static str,
+    cpe_product: &'static str,
+) -> Product {
+    Product {
+        name,
+        vendor,
+        cpe_vendor,
+        cpe_product,
+    }
+}
+
+/// Servers also identified by their handshake, shared with the banner rules
+pub(super) const MARIADB: Product = product("MariaDB", "MariaDB", "mariadb", "mariadb");
+pub(super) const MYSQL: Product = product("MySQL", "Oracle", "oracle", "mysql");
+
+/// Products reported by the probes, by name or protocol, the banner rules carrying their own
+const PRODUCTS: &[Product] = &[
+    // Databases and caches
+    MARIADB,
+    MYSQL,
+    product("MongoDB", "MongoDB", "mongodb", "mongodb"),
+    product("Redis", "Redis", "redis", "redis"),
+    product("Memcached", "Memcached", "memcached", "memcached"),
+    // DNS, as announced in version.bind
+    product("BIND", "ISC", "isc", "bind"),
+    product("dnsmasq", "Simon Kelley", "thekelleys", "dnsmasq"),
+    product("Unbound", "NLnet Labs", "nlnetlabs", "unbound"),
+    product("PowerDNS Recursor", "PowerDNS", "powerdns", "recursor"),
+    product(
+        "PowerDNS Authoritative Server",
+        "PowerDNS",
+        "powerdns",
+        "authoritative",
+    ),
+    product("Knot DNS", "CZ.NIC", "nic", "knot_dns"),
+    // Others
+    product("OpenLDAP", "OpenLDAP", "openldap", "openldap"),
+    // Web, as named by the Server and X-Powered-By headers
+    product("nginx", "F5", "f5", "nginx"),
+    product("Apache", "Apache", "apache", "http_server"),
+    product(
+        "Microsoft-IIS",
+        "Microsoft",
+        "microsoft",
+        "internet_information_services",
+    ),
+    product("lighttpd", "lighttpd", "lighttpd", "lighttpd"),
+    product("Caddy", "Caddy", "caddyserver", "caddy"),
+    product("Jetty", "Eclipse", "eclipse", "jetty"),
+    product("PHP", "PHP", "php", "php"),
+];
+
+fn find_product(name: &str) -> Option<&'static Product> {
+    PRODUCTS
+        .iter()
+        .find(|product| product.name.eq_ignore_ascii_case(name.trim()))
+}
+
+/// Version without its decorations ("v1.2" is "1.2", "8.0.35-0ubuntu0.22.04.1" is "8.0.35"),
+/// None when it is no version
+pub(super) fn normalize_version(version: &str) -> Option<String> {
+    let version = version
+        .trim()
+        .split(['-', '+', '~', ' '])
+        .next()
+        .unwrap_or_default();
+    let version = version
+        .strip_prefix(['v', 'V'])
+        .filter(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        .unwrap_or(version);
+    version
+        .starts_with(|c: char| c.is_ascii_digit())
+        .then(|| version.to_owned())
+}
+
+/// Escapes a component of a CPE formatted string
+fn escape(component: &str) -> String {
+    let mut escaped = String::with_capacity(component.len());
+    for c in component.chars() {
+        match c {
+            ' ' => escaped.push('_'),
+            c if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') => {
+                escaped.push(c.to_ascii_lowercase())
+            }
+            c => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+        }
+    }
+    escaped
+}
+
+/// CPE 2.3 identifier of an application, OpenSSH's portable releases ("8.9p1") having
+/// their patch level as update
+fn cpe(product: &Product, version: Option<&str>) -> String {
+    let (version, update) = match version {
+        Some(version) if product.cpe_product == "openssh" => match version.split_once('p') {
+            Some((version, patch)) if !patch.is_empty() => {
+                (escape(version), escape(&format!("p{}", patch)))
+            }
+            _ => (escape(version), "*".to_owned()),
+        },
+        Some(version) => (escape(version), "*".to_owned()),
+        None => ("*".to_owned(), "*".to_owned()),
+    };
+    format!(
+        "cpe:2.3:a:{}:{}:{}:{}:*:*:*:*:*:*",
+        product.cpe_vendor, product.cpe_product, version, update
+    )
+}
+
+/// Findings of a known product and its version: product, vendor, version and CPE
+pub(super) fn product_findings(product: &Product, version: Option<&str>) -> Vec<Finding> {
+    let mut findings = vec![
+        Finding::new("product", product.name),
+        Finding::new("vendor", product.vendor),
+    ];
+    if let Some(version) = version {
+        findings.push(Finding::new("version", version));
+    }
+    findings.push(Finding::new("cpe", cpe(product, version)));
+    findings
+}
+
+/// Normalizes the product of a service, named by its `product` finding or its protocol,
+/// replacing its product, vendor and version findings by the normalized ones, in place of
+/// the first of them
+pub fn normalize(service: &mut Service) {
+    let name = match service.findings.iter().find(|f| f.key == "product") {
+        Some(finding) => finding.value.clone(),
+        None => service.protocol.clone(),
+    };
+    let product = match find_product(&name) {
+        Some(product) => product,
+        None => return,
+    };
+    let version = service
+        .findings
+        .iter()
+        .find(|f| f.key == "version")
+        .and_then(|f| normalize_version(&f.value));
+
+    let position = service
+        .findings
+        .iter()
+        .position(|f| matches!(f.key.as_str(), "product" | "vendor" | "version"))
+        .unwrap_or(0);
+    let mut findings = service.findings.split_off(position);
+    // an unusual version is kept as is, for lack of a normalized one
+    findings.retain(|f| match f.key.as_str() {
+        "product" | "vendor" | "cpe" => false,
+        "version" => version.is_none(),
+        _ => true,
+    });
+    service
+        .findings
+        .extend(product_findings(product, version.as_deref()));
+    service.findings.extend(findings);
+}
+
+/// Findings of a known product named by an HTTP header such as `Server`, as
+/// "product/version (comment)", None for unknown products
+pub fn header_findings(value: &str) -> Option<Vec<Finding>> {
+    let token = value.split_whitespace().next()?;
+    let (name, version) = match token.split_once('/') {
+        Some((name, version)) => (name, normalize_version(version)),
+        None => (token, None),
+    };
+    let product = find_product(name)?;
+    Some(product_findings(product, version.as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_versions() {
+        assert_eq!(normalize_version("v1.2").as_deref(), Some("1.2"));
+        assert_eq!(
+            normalize_version("8.0.35-0ubuntu0.22.04.1").as_deref(),
+            Some("8.0.35")
+        );
+        assert_eq!(normalize_version("2.4.1+dfsg").as_deref(), Some("2.4.1"));
+        assert_eq!(normalize_version(" 9.6p1 ").as_deref(), Some("9.6p1"));
+        assert_eq!(normalize_version("version"), None);
+        assert_eq!(normalize_version(""), None);
+    }
+
+    #[test]
+    fn splits_openssh_patch_level() {
+        let openssh = product("OpenSSH", "OpenBSD", "openbsd", "openssh");
+        assert_eq!(
+            cpe(&openssh, Some("8.9p1")),
+            "cpe:2.3:a:openbsd:openssh:8.9:p1:*:*:*:*:*:*"
+        );
+        assert_eq!(
+            cpe(&openssh, Some("9.0")),
+            "cpe:2.3:a:openbsd:openssh:9.0:*:*:*:*:*:*:*"
+        );
+        assert_eq!(
+            cpe(&openssh, None),
+            "cpe:2.3:a:openbsd:openssh:*:*:*:*:*:*:*:*"
+        );
+        // only OpenSSH has a patch level
+        assert_eq!(
+            cpe(&MYSQL, Some("8.0p1")),
+            "cpe:2.3:a:oracle:mysql:8.0p1:*:*:*:*:*:*:*"
+        );
+    }
+
+    #[test]
+    fn escapes_components() {
+        assert_eq!(escape("1.0_Beta-2"), "1.0_beta-2");
+        assert_eq!(escape("Knot DNS"), "knot_dns");
+        assert_eq!(escape("1.0:rc1"), "1.0\\:rc1");
+        assert_eq!(escape("a*b?"), "a\\*b\\?");
+    }
+
+    #[test]
+    fn normalizes_probe_products() {
+        let mut service = Service::new(
+            "DNS",
+            vec![
+                Finding::new("product", "bind"),
+                Finding::new("version", "9.18.24-1-Debian"),
+                Finding::new("recursion", "enabled"),
+            ],
+        );
+        normalize(&mut service);
+        assert_eq!(
+            service.findings,
+            [
+                Finding::new("product", "BIND"),
+                Finding::new("vendor", "ISC"),
+                Finding::new("version", "9.18.24"),
+                Finding::new("cpe", "cpe:2.3:a:isc:bind:9.18.24:*:*:*:*:*:*:*"),
+                Finding::new("recursion", "enabled"),
+            ]
+        );
+
+        // unknown products are left alone
+        let mut service = Service::new("SSH", vec![Finding::new("product", "OpenSSH")]);
+        normalize(&mut service);
+        assert_eq!(service.findings, [Finding::new("product", "OpenSSH")]);
+    }
+}
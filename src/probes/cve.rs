@@ -0,0 +1,811 @@
+//! Offline correlation of the products found with known vulnerabilities. Feeds (NVD JSON
+//! 1.1 and 2.0, OSV) are imported into a SQLite database by `update-db`; scans look the
+//! CPE of each product up in it, without network access.
+
+use std::cmp::Ordering;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde_json::Value;
+
+use super::{Finding, Severity};
+use crate::defaults::CVE_DATABASE;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS cves (
+        id TEXT PRIMARY KEY,
+        score REAL,
+        severity TEXT,
+        summary TEXT
+    );
+    CREATE TABLE IF NOT EXISTS affected (
+        cve TEXT NOT NULL REFERENCES cves (id),
+        vendor TEXT,
+        ecosystem TEXT,
+        product TEXT NOT NULL,
+        version TEXT,
+        version_update TEXT,
+        start_including TEXT,
+        start_excluding TEXT,
+        end_including TEXT,
+        end_excluding TEXT
+    );
+    CREATE INDEX IF NOT EXISTS affected_product ON affected (product, vendor);
+";
+
+/// Ecosystems of distributions, whose packages are servers as much as libraries, unlike the
+/// packages of languages (a PyPI `redis` is a client). Their versions carry a packaging
+/// revision after a '-', and maybe an epoch.
+const DISTRIBUTION_ECOSYSTEMS: [&str; 3] = ["Debian", "Ubuntu", "Alpine"];
+
+/// Pre-release tags, from the earliest: these versions come before the release itself
+const PRE_RELEASES: [&str; 5] = ["dev", "alpha", "beta", "pre", "rc"];
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// A known vulnerability
+struct Cve {
+    id: String,
+    score: Option<f64>,
+    severity: Option<String>,
+    summary: Option<String>,
+    affected: Vec<Affected>,
+}
+
+/// Versions of a product affected by a vulnerability: one version, or a range of them
+#[derive(Debug, Default, PartialEq)]
+struct Affected {
+    vendor: Option<String>,
+    /// Family of the OSV ecosystem ("Debian" for "Debian:12"), None for NVD entries
+    ecosystem: Option<String>,
+    product: String,
+    version: Option<String>,
+    update: Option<String>,
+    start_including: Option<String>,
+    start_excluding: Option<String>,
+    end_including: Option<String>,
+    end_excluding: Option<String>,
+}
+
+impl Affected {
+    /// From the criteria of a CPE match, None when it is no application's
+    fn from_cpe(criteria: &str, bounds: &Value) -> Option<Self> {
+        let parts: Vec<&str> = criteria.split(':').collect();
+        if parts.len() < 7 || parts[0] != "cpe" || parts[2] != "a" {
+            return None;
+        }
+        let bound = |name: &str| bounds.get(name).and_then(Value::as_str).map(str::to_owned);
+        let specific = |part: &str| (!matches!(part, "*" | "-")).then(|| part.replace('\\', ""));
+        Some(Self {
+            vendor: specific(parts[3]),
+            product: specific(parts[4])?,
+            version: specific(parts[5]),
+            update: specific(parts[6]),
+            ecosystem: None,
+            start_including: bound("versionStartIncluding"),
+            start_excluding: bound("versionStartExcluding"),
+            end_including: bound("versionEndIncluding"),
+            end_excluding: bound("versionEndExcluding"),
+        })
+    }
+
+    fn matches(&self, version: &str, update: Option<&str>) -> bool {
+        if let Some(ref affected) = self.version {
+            return affected.eq_ignore_ascii_case(version)
+                && match (&self.update, update) {
+                    (Some(affected), Some(update)) => affected.eq_ignore_ascii_case(update),
+                    (Some(_), None) => false,
+                    (None, _) => true,
+                };
+        }
+        // ranges are compared with the whole version, OpenSSH's "8.9p1" and not "8.9"
+        let full = format!("{}{}", version, update.unwrap_or_default());
+        let compare = |bound: &Option<String>| bound.as_deref().map(|b| compare_versions(&full, b));
+        !matches!(compare(&self.start_including), Some(Ordering::Less))
+            && !matches!(
+                compare(&self.start_excluding),
+                Some(Ordering::Less | Ordering::Equal)
+            )
+            && !matches!(compare(&self.end_including), Some(Ordering::Greater))
+            && !matches!(
+                compare(&self.end_excluding),
+                Some(Ordering::Greater | Ordering::Equal)
+            )
+    }
+
+    /// The affected versions, as "versions >= 1.0, < 1.2"
+    fn describe(&self) -> String {
+        if let Some(ref version) = self.version {
+            return match self.update {
+                Some(ref update) => format!("version {} {}", version, update),
+                None => format!("version {}", version),
+            };
+        }
+        let bounds: Vec<String> = [
+            (">=", &self.start_including),
+            (">", &self.start_excluding),
+            ("<=", &self.end_including),
+            ("<", &self.end_excluding),
+        ]
+        .iter()
+        .filter_map(|(op, bound)| bound.as_ref().map(|bound| format!("{} {}", op, bound)))
+        .collect();
+        if bounds.is_empty() {
+            "all versions".to_owned()
+        } else {
+            format!("versions {}", bounds.join(", "))
+        }
+    }
+}
+
+/// Compares versions part by part, numbers as numbers ("1.10" after "1.9") and after
+/// letters ("1.0p1" before "1.0.1"). A longer version comes after its prefix ("8.9p1" after
+/// "8.9"), unless it is a pre-release ("1.0rc1" before "1.0").
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    enum Part {
+        /// Rank of a pre-release tag in `PRE_RELEASES`
+        PreRelease(usize),
+        End,
+        Text(String),
+        Number(u64),
+    }
+    fn parts(version: &str) -> Vec<Part> {
+        let mut parts = Vec::new();
+        let mut chars = version.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_digit() {
+                let mut number = String::new();
+                while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                    number.push(c);
+                }
+                parts.push(Part::Number(number.parse().unwrap_or(u64::MAX)));
+            } else if c.is_alphabetic() {
+                let mut text = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphabetic()) {
+                    text.push(c.to_ascii_lowercase());
+                }
+                match PRE_RELEASES.iter().position(|tag| *tag == text) {
+                    Some(rank) => parts.push(Part::PreRelease(rank)),
+                    None => parts.push(Part::Text(text)),
+                }
+            } else {
+                chars.next();
+            }
+        }
+        parts
+    }
+    let (a, b) = (parts(a), parts(b));
+    for index in 0..a.len().max(b.len()) {
+        let ordering = a
+            .get(index)
+            .unwrap_or(&Part::End)
+            .cmp(b.get(index).unwrap_or(&Part::End));
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Severity of a CVSS score
+fn score_severity(score: f64) -> Severity {
+    match score {
+        s if s >= 9.0 => Severity::Critical,
+        s if s >= 7.0 => Severity::High,
+        s if s >= 4.0 => Severity::Medium,
+        _ => Severity::Low,
+    }
+}
+
+fn text_severity(severity: &str) -> Option<Severity> {
+    match severity.to_ascii_uppercase().as_str() {
+        "CRITICAL" => Some(Severity::Critical),
+        "HIGH" => Some(Severity::High),
+        "MEDIUM" | "MODERATE" => Some(Severity::Medium),
+        "LOW" => Some(Severity::Low),
+        _ => None,
+    }
+}
+
+fn english_description(descriptions: Option<&Value>) -> Option<String> {
+    descriptions?
+        .as_array()?
+        .iter()
+        .find(|d| d["lang"] == "en")
+        .and_then(|d| d["value"].as_str())
+        .map(str::to_owned)
+}
+
+/// CPE matches of NVD configuration nodes, children included (NVD 1.1)
+fn nvd_matches(nodes: &Value, key: &str, criteria: &str, affected: &mut Vec<Affected>) {
+    for node in nodes.as_array().into_iter().flatten() {
+        for cpe_match in node[key].as_array().into_iter().flatten() {
+            if cpe_match["vulnerable"] == true {
+                if let Some(entry) = cpe_match[criteria]
+                    .as_str()
+                    .and_then(|uri| Affected::from_cpe(uri, cpe_match))
+                {
+                    affected.push(entry);
+                }
+            }
+        }
+        nvd_matches(&node["children"], key, criteria, affected);
+    }
+}
+
+/// Entries of an NVD JSON 2.0 feed
+fn nvd2_cves(feed: &Value) -> Vec<Cve> {
+    let mut cves = Vec::new();
+    for item in feed["vulnerabilities"].as_array().into_iter().flatten() {
+        let cve = &item["cve"];
+        let id = match cve["id"].as_str() {
+            Some(id) => id.to_owned(),
+            None => continue,
+        };
+        let metric = [
+            "cvssMetricV31",
+            "cvssMetricV30",
+            "cvssMetricV40",
+            "cvssMetricV2",
+        ]
+        .iter()
+        .find_map(|name| cve["metrics"][name].get(0));
+        let mut affected = Vec::new();
+        for configuration in cve["configurations"].as_array().into_iter().flatten() {
+            nvd_matches(
+                &configuration["nodes"],
+                "cpeMatch",
+                "criteria",
+                &mut affected,
+            );
+        }
+        cves.push(Cve {
+            id,
+            score: metric.and_then(|m| m["cvssData"]["baseScore"].as_f64()),
+            severity: metric
+                .and_then(|m| {
+                    m["cvssData"]["baseSeverity"]
+                        .as_str()
+                        .or(m["baseSeverity"].as_str())
+                })
+                .map(str::to_owned),
+            summary: english_description(cve.get("descriptions")),
+            affected,
+        });
+    }
+    cves
+}
+
+/// Entries of an NVD JSON 1.1 feed
+fn nvd1_cves(feed: &Value) -> Vec<Cve> {
+    let mut cves = Vec::new();
+    for item in feed["CVE_Items"].as_array().into_iter().flatten() {
+        let id = match item["cve"]["CVE_data_meta"]["ID"].as_str() {
+            Some(id) => id.to_owned(),
+            None => continue,
+        };
+        let (score, severity) = match item["impact"]["baseMetricV3"]["cvssV3"] {
+            Value::Object(ref cvss) => (cvss.get("baseScore"), cvss.get("baseSeverity")),
+            _ => (
+                item["impact"]["baseMetricV2"]["cvssV2"].get("baseScore"),
+                item["impact"]["baseMetricV2"].get("severity"),
+            ),
+        };
+        let mut affected = Vec::new();
+        nvd_matches(
+            &item["configurations"]["nodes"],
+            "cpe_match",
+            "cpe23Uri",
+            &mut affected,
+        );
+        cves.push(Cve {
+            id,
+            score: score.and_then(Value::as_f64),
+            severity: severity.and_then(Value::as_str).map(str::to_owned),
+            summary: english_description(item["cve"]["description"].get("description_data")),
+            affected,
+        });
+    }
+    cves
+}
+
+/// Version of an OSV package, without epoch nor revision for distributions' packages
+fn osv_version(version: &str, ecosystem: &str) -> String {
+    if !DISTRIBUTION_ECOSYSTEMS.contains(&ecosystem) {
+        return version.to_owned();
+    }
+    let version = version
+        .split_once(':')
+        .map_or(version, |(_, version)| version);
+    version.split('-').next().unwrap_or(version).to_owned()
+}
+
+/// An OSV entry, named after its CVE when it has one
+fn osv_cve(entry: &Value) -> Option<Cve> {
+    let id = entry["aliases"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .find(|alias| alias.starts_with("CVE-"))
+        .or(entry["id"].as_str())?
+        .to_owned();
+
+    let mut affected = Vec::new();
+    for package in entry["affected"].as_array().into_iter().flatten() {
+        let (ecosystem, product) = match (
+            package["package"]["ecosystem"].as_str(),
+            package["package"]["name"].as_str(),
+        ) {
+            (Some(ecosystem), Some(name)) => (
+                ecosystem.split(':').next().unwrap_or(ecosystem),
+                name.to_lowercase(),
+            ),
+            _ => continue,
+        };
+        for range in package["ranges"].as_array().into_iter().flatten() {
+            if range["type"] == "GIT" {
+                continue;
+            }
+            let mut current: Option<Affected> = None;
+            for event in range["events"].as_array().into_iter().flatten() {
+                let version = |name: &str| event[name].as_str().map(|v| osv_version(v, ecosystem));
+                if let Some(introduced) = version("introduced") {
+                    affected.extend(current.take());
+                    current = Some(Affected {
+                        ecosystem: Some(ecosystem.to_owned()),
+                        product: product.clone(),
+                        start_including: Some(introduced).filter(|v| v != "0"),
+                        ..Default::default()
+                    });
+                } else if let Some(ref mut range) = current {
+                    range.end_excluding = version("fixed");
+                    range.end_including = version("last_affected");
+                    affected.extend(current.take());
+                }
+            }
+            affected.extend(current);
+        }
+        for version in package["versions"].as_array().into_iter().flatten() {
+            if let Some(version) = version.as_str() {
+                affected.push(Affected {
+                    ecosystem: Some(ecosystem.to_owned()),
+                    product: product.clone(),
+                    version: Some(osv_version(version, ecosystem)),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    Some(Cve {
+        id,
+        score: None,
+        severity: entry["database_specific"]["severity"]
+            .as_str()
+            .map(str::to_owned),
+        summary: entry["summary"]
+            .as_str()
+            .or(entry["details"].as_str())
+            .map(str::to_owned),
+        affected,
+    })
+}
+
+/// Entries of a feed, whatever its format
+fn feed_cves(feed: &Value) -> Option<Vec<Cve>> {
+    if feed.get("vulnerabilities").is_some() {
+        Some(nvd2_cves(feed))
+    } else if feed.get("CVE_Items").is_some() {
+        Some(nvd1_cves(feed))
+    } else if let Some(entries) = feed.as_array() {
+        Some(entries.iter().filter_map(osv_cve).collect())
+    } else if feed.get("affected").is_some() {
+        Some(osv_cve(feed).into_iter().collect())
+    } else {
+        None
+    }
+}
+
+/// Adds the ecosystem column to databases imported before it existed: their OSV entries,
+/// without it, no longer match anything until imported again
+fn migrate(connection: &Connection) -> rusqlite::Result<()> {
+    let has_ecosystem = connection
+        .prepare("SELECT 1 FROM pragma_table_info('affected') WHERE name = 'ecosystem'")?
+        .exists([])?;
+    if !has_ecosystem {
+        connection.execute_batch("ALTER TABLE affected ADD COLUMN ecosystem TEXT")?;
+    }
+    Ok(())
+}
+
+fn store(transaction: &Transaction, cve: &Cve) -> rusqlite::Result<()> {
+    transaction.execute(
+        "INSERT OR REPLACE INTO cves (id, score, severity, summary) VALUES (?1, ?2, ?3, ?4)",
+        params![cve.id, cve.score, cve.severity, cve.summary],
+    )?;
+    transaction.execute("DELETE FROM affected WHERE cve = ?1", params![cve.id])?;
+    let mut insert = transaction.prepare_cached(
+        "INSERT INTO affected (cve, vendor, ecosystem, product, version, version_update,
+             start_including, start_excluding, end_including, end_excluding)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    )?;
+    for affected in cve.affected.iter() {
+        insert.execute(params![
+            cve.id,
+            affected.vendor,
+            affected.ecosystem,
+            affected.product,
+            affected.version,
+            affected.update,
+            affected.start_including,
+            affected.start_excluding,
+            affected.end_including,
+            affected.end_excluding,
+        ])?;
+    }
+    Ok(())
+}
+
+/// Imports feed files into the database, created when missing, returns the number of
+/// vulnerabilities imported. Entries already known are replaced.
+pub fn import(db: &Path, feeds: &[impl AsRef<Path>]) -> io::Result<usize> {
+    let mut connection = Connection::open(db).map_err(sql_error)?;
+    connection.execute_batch(SCHEMA).map_err(sql_error)?;
+    migrate(&connection).map_err(sql_error)?;
+
+    let mut count = 0;
+    for path in feeds {
+        let path = path.as_ref();
+        let feed: Value = serde_json::from_reader(io::BufReader::new(std::fs::File::open(path)?))?;
+        let cves = feed_cves(&feed).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: not a NVD or OSV feed", path.display()),
+            )
+        })?;
+        let transaction = connection.transaction().map_err(sql_error)?;
+        for cve in cves.iter() {
+            store(&transaction, cve).map_err(sql_error)?;
+        }
+        transaction.commit().map_err(sql_error)?;
+        count += cves.len();
+    }
+    Ok(count)
+}
+
+/// The database looked up during scans
+pub struct CveDatabase(Mutex<Connection>);
+
+impl CveDatabase {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let connection = Connection::open(path).map_err(sql_error)?;
+        let known: Option<String> = connection
+            .query_row(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'affected'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(sql_error)?;
+        if known.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no vulnerability imported",
+            ));
+        }
+        migrate(&connection).map_err(sql_error)?;
+        Ok(Self(Mutex::new(connection)))
+    }
+
+    /// Vulnerabilities of a product's version, as given by its CPE, the worst first. Entries
+    /// without vendor only match when they come from a distribution's packages.
+    fn lookup(&self, cpe: &str) -> rusqlite::Result<Vec<Finding>> {
+        let parts: Vec<&str> = cpe.split(':').collect();
+        let (vendor, product, version, update) = match parts[..] {
+            [_, _, _, vendor, product, version, update, ..] if version != "*" => (
+                vendor,
+                product,
+                version.replace('\\', ""),
+                Some(update).filter(|update| *update != "*"),
+            ),
+            _ => return Ok(Vec::new()),
+        };
+
+        let connection = self.0.lock().expect("Dead thread");
+        let mut statement = connection.prepare_cached(
+            "SELECT a.cve, c.score, c.severity, a.version, a.version_update,
+                 a.start_including, a.start_excluding, a.end_including, a.end_excluding
+             FROM affected a JOIN cves c ON c.id = a.cve
+             WHERE a.product = ?1
+                 AND (a.vendor = ?2 OR (a.vendor IS NULL AND a.ecosystem IN (?3, ?4, ?5)))",
+        )?;
+        let [debian, ubuntu, alpine] = DISTRIBUTION_ECOSYSTEMS;
+        let rows = statement
+            .query_map(params![product, vendor, debian, ubuntu, alpine], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<f64>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    Affected {
+                        product: product.to_owned(),
+                        version: row.get(3)?,
+                        update: row.get(4)?,
+                        start_including: row.get(5)?,
+                        start_excluding: row.get(6)?,
+                        end_including: row.get(7)?,
+                        end_excluding: row.get(8)?,
+                        ..Default::default()
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut matches: Vec<(String, Option<f64>, Option<Severity>, String)> = Vec::new();
+        for (id, score, severity, affected) in rows {
+            if matches.iter().any(|(known, ..)| *known == id) || !affected.matches(&version, update)
+            {
+                continue;
+            }
+            let severity = score
+                .map(score_severity)
+                .or_else(|| severity.as_deref().and_then(text_severity));
+            matches.push((id, score, severity, affected.describe()));
+        }
+        matches.sort_by(|a, b| {
+            b.2.cmp(&a.2)
+                .then_with(|| b.1.unwrap_or(0.0).total_cmp(&a.1.unwrap_or(0.0)))
+        });
+
+        Ok(matches
+            .into_iter()
+            .map(|(id, score, severity, versions)| {
+                let value = match score {
+                    Some(score) => format!("{} (CVSS {:.1}, {})", id, score, versions),
+                    None => format!("{} ({})", id, versions),
+                };
+                let finding = Finding::new("vulnerability", value);
+                match severity {
+                    Some(severity) => finding.with_severity(severity),
+                    None => finding,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Adds the known vulnerabilities of the products identified by `cpe` findings, when a
+/// database is set. SQLite blocks, it is queried off the scan's workers.
+pub async fn annotate(findings: &mut Vec<Finding>) {
+    let database = match CVE_DATABASE.get() {
+        Some(database) => database,
+        None => return,
+    };
+    let cpes: Vec<String> = findings
+        .iter()
+        .filter(|finding| finding.key == "cpe")
+        .map(|finding| finding.value.clone())
+        .collect();
+    if cpes.is_empty() {
+        return;
+    }
+    let lookups = tokio::task::spawn_blocking(move || {
+        cpes.iter()
+            .map(|cpe| database.lookup(cpe))
+            .collect::<rusqlite::Result<Vec<_>>>()
+    })
+    .await;
+    match lookups {
+        Ok(Ok(vulnerabilities)) => findings.extend(vulnerabilities.into_iter().flatten()),
+        Ok(Err(e)) => outputln!("      - vulnerabilities: lookup failed ({})", e),
+        Err(e) => outputln!("      - vulnerabilities: lookup failed ({})", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn range(start_including: &str, end_excluding: &str) -> Affected {
+        Affected {
+            product: "openssh".to_owned(),
+            start_including: Some(start_including.to_owned()),
+            end_excluding: Some(end_excluding.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compares_versions() {
+        for (a, b) in [
+            ("1.9", "1.10"),
+            ("8.9", "8.9p1"),
+            ("8.9p1", "9.0"),
+            ("1.0.2", "1.0.2a"),
+            ("1.0rc1", "1.0"),
+            ("1.0alpha", "1.0beta2"),
+            ("1.0beta2", "1.0rc1"),
+            ("1.0rc1", "1.0.1"),
+            ("2.4.57", "2.4.58"),
+        ] {
+            assert_eq!(compare_versions(a, b), Ordering::Less, "{} < {}", a, b);
+            assert_eq!(compare_versions(b, a), Ordering::Greater, "{} > {}", b, a);
+        }
+        assert_eq!(compare_versions("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0RC1", "1.0rc1"), Ordering::Equal);
+    }
+
+    #[test]
+    fn matches_ranges_and_versions() {
+        let affected = range("8.5", "9.8");
+        assert!(affected.matches("8.9", Some("p1")));
+        assert!(affected.matches("8.5", None));
+        assert!(!affected.matches("9.8", None));
+        assert!(!affected.matches("8.4", Some("p1")));
+
+        let before_release = Affected {
+            end_excluding: Some("1.0".to_owned()),
+            ..range("0", "1.0")
+        };
+        assert!(before_release.matches("1.0rc1", None));
+
+        let exact = Affected {
+            product: "openssh".to_owned(),
+            version: Some("8.9".to_owned()),
+            update: Some("p1".to_owned()),
+            ..Default::default()
+        };
+        assert!(exact.matches("8.9", Some("p1")));
+        assert!(!exact.matches("8.9", Some("p2")));
+        assert!(!exact.matches("8.9", None));
+    }
+
+    fn nvd2_feed() -> Value {
+        json!({"vulnerabilities": [{"cve": {
+            "id": "CVE-2024-6387",
+            "descriptions": [{"lang": "en", "value": "Signal handler race condition"}],
+            "metrics": {"cvssMetricV31": [
+                {"cvssData": {"baseScore": 8.1, "baseSeverity": "HIGH"}}
+            ]},
+            "configurations": [{"nodes": [{"cpeMatch": [{
+                "vulnerable": true,
+                "criteria": "cpe:2.3:a:openbsd:openssh:*:*:*:*:*:*:*:*",
+                "versionStartIncluding": "8.5",
+                "versionEndExcluding": "9.8"
+            }]}]}]
+        }}]})
+    }
+
+    fn osv_feed() -> Value {
+        json!([{
+            "id": "DSA-5461-1",
+            "aliases": ["CVE-2023-38408"],
+            "summary": "openssh security update",
+            "affected": [{
+                "package": {"ecosystem": "Debian:12", "name": "openssh"},
+                "ranges": [{"type": "ECOSYSTEM", "events": [
+                    {"introduced": "0"}, {"fixed": "1:9.2p1-2+deb12u1"}
+                ]}]
+            }]
+        }, {
+            "id": "GHSA-8fww-64cx-x8p5",
+            "aliases": ["CVE-2023-28858"],
+            "affected": [{
+                "package": {"ecosystem": "PyPI", "name": "redis"},
+                "ranges": [{"type": "ECOSYSTEM", "events": [
+                    {"introduced": "4.5.0"}, {"fixed": "4.5.3"}
+                ]}]
+            }]
+        }])
+    }
+
+    #[test]
+    fn parses_nvd2_feeds() {
+        let cves = feed_cves(&nvd2_feed()).unwrap();
+        assert_eq!(cves.len(), 1);
+        assert_eq!(cves[0].id, "CVE-2024-6387");
+        assert_eq!(cves[0].score, Some(8.1));
+        assert_eq!(cves[0].severity.as_deref(), Some("HIGH"));
+        assert_eq!(
+            cves[0].summary.as_deref(),
+            Some("Signal handler race condition")
+        );
+        assert_eq!(
+            cves[0].affected,
+            [Affected {
+                vendor: Some("openbsd".to_owned()),
+                ..range("8.5", "9.8")
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_nvd1_feeds() {
+        let feed = json!({"CVE_Items": [{
+            "cve": {
+                "CVE_data_meta": {"ID": "CVE-2011-2523"},
+                "description": {"description_data": [{"lang": "en", "value": "Backdoor"}]}
+            },
+            "impact": {"baseMetricV3": {"cvssV3": {"baseScore": 9.8, "baseSeverity": "CRITICAL"}}},
+            "configurations": {"nodes": [{"cpe_match": [], "children": [{"cpe_match": [{
+                "vulnerable": true,
+                "cpe23Uri": "cpe:2.3:a:vsftpd_project:vsftpd:2.3.4:*:*:*:*:*:*:*"
+            }]}]}]}
+        }]});
+        let cves = feed_cves(&feed).unwrap();
+        assert_eq!(cves.len(), 1);
+        assert_eq!(cves[0].id, "CVE-2011-2523");
+        assert_eq!(cves[0].score, Some(9.8));
+        assert_eq!(cves[0].severity.as_deref(), Some("CRITICAL"));
+        assert_eq!(cves[0].summary.as_deref(), Some("Backdoor"));
+        assert_eq!(
+            cves[0].affected,
+            [Affected {
+                vendor: Some("vsftpd_project".to_owned()),
+                product: "vsftpd".to_owned(),
+                version: Some("2.3.4".to_owned()),
+                ..Default::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_osv_feeds() {
+        let cves = feed_cves(&osv_feed()).unwrap();
+        assert_eq!(cves.len(), 2);
+        assert_eq!(cves[0].id, "CVE-2023-38408");
+        assert_eq!(
+            cves[0].affected,
+            [Affected {
+                ecosystem: Some("Debian".to_owned()),
+                product: "openssh".to_owned(),
+                end_excluding: Some("9.2p1".to_owned()),
+                ..Default::default()
+            }]
+        );
+        assert_eq!(cves[1].id, "CVE-2023-28858");
+        assert_eq!(cves[1].affected[0].ecosystem.as_deref(), Some("PyPI"));
+    }
+
+    #[test]
+    fn looks_up_servers_only() {
+        let dir = std::env::temp_dir();
+        let db = dir.join(format!("port-scanner-cves-{}.db", std::process::id()));
+        let feeds = [
+            dir.join(format!("port-scanner-nvd-{}.json", std::process::id())),
+            dir.join(format!("port-scanner-osv-{}.json", std::process::id())),
+        ];
+        std::fs::write(&feeds[0], nvd2_feed().to_string()).unwrap();
+        std::fs::write(&feeds[1], osv_feed().to_string()).unwrap();
+        assert_eq!(import(&db, &feeds[..]).unwrap(), 3);
+
+        let database = CveDatabase::open(&db).unwrap();
+        let openssh = database
+            .lookup("cpe:2.3:a:openbsd:openssh:8.9:p1:*:*:*:*:*:*")
+            .unwrap();
+        let ids: Vec<&str> = openssh
+            .iter()
+            .map(|finding| finding.value.split(' ').next().unwrap())
+            .collect();
+        assert_eq!(ids, ["CVE-2024-6387", "CVE-2023-38408"]);
+        // the PyPI client library is not the server
+        let redis = database
+            .lookup("cpe:2.3:a:redis:redis:4.5.1:*:*:*:*:*:*:*")
+            .unwrap();
+        assert!(redis.is_empty());
+
+        for path in feeds.iter().chain([&db]) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! DNP3 outstations answer link status requests at the data link layer, which neither
+//! reaches the application nor changes any state, telling us their link address.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+use crate::defaults::READ_TIMEOUT;
+use crate::utils::run_with_timeout;
+
+const START_BYTES: [u8; 2] = [0x05, 0x64];
+/// Header size, without the start bytes, counted in the length field
+const HEADER_LENGTH: u8 = 5;
+
+/// Primary frame from the master (DIR and PRM bits) requesting the link status
+const REQUEST_LINK_STATUS: u8 = 0xc9;
+const FUNCTION_MASK: u8 = 0x0f;
+const FUNCTION_LINK_STATUS: u8 = 11;
+const FUNCTION_NOT_SUPPORTED: u8 = 15;
+
+/// Our address, the usual one of masters
+const MASTER_ADDRESS: u16 = 3;
+/// Outstation addresses tried, their usual defaults
+const OUTSTATION_ADDRESSES: [u16; 3] = [1, 0, 10];
+
+pub struct Dnp3Probe;
+
+/// CRC of DNP3 frames, stored little endian after each block
+fn crc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa6bc
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn link_status_request(destination: u16) -> Vec<u8> {
+    let mut frame = START_BYTES.to_vec();
+    frame.push(HEADER_LENGTH);
+    frame.push(REQUEST_LINK_STATUS);
+    frame.extend_from_slice(&destination.to_le_bytes());
+    frame.extend_from_slice(&MASTER_ADDRESS.to_le_bytes());
+    let crc = crc(&frame[..]);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+/// Describes a frame's control byte
+fn function_name(control: u8) -> &'static str {
+    match control & FUNCTION_MASK {
+        FUNCTION_LINK_STATUS => "link status",
+        FUNCTION_NOT_SUPPORTED => "link service not supported",
+        _ => "other",
+    }
+}
+
+impl Probe for Dnp3Probe {
+    fn name(&self) -> &'static str {
+        "dnp3"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 20000)
+    }
+
+    fn is_industrial(&self) -> bool {
+        true
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            for destination in OUTSTATION_ADDRESSES {
+                stream
+                    .write_all(&link_status_request(destination)[..])
+                    .await?;
+
+                // Outstations silently drop frames not addressed to them
+                let mut header = [0u8; 10];
+                let timeout = unsafe { READ_TIMEOUT } / OUTSTATION_ADDRESSES.len() as u32;
+                match run_with_timeout(timeout, stream.read_exact(&mut header[..])).await {
+                    Some(result) => result?,
+                    None => continue,
+                };
+                if header[..2] != START_BYTES
+                    || header[2] < HEADER_LENGTH
+                    || crc(&header[..8]) != u16::from_le_bytes([header[8], header[9]])
+                {
+                    return Ok(ProbeStatus::Unknown);
+                }
+
+                let findings = vec![
+                    Finding::new(
+                        "outstation address",
+                        u16::from_le_bytes([header[6], header[7]]).to_string(),
+                    ),
+                    Finding::new(
+                        "master address",
+                        u16::from_le_bytes([header[4], header[5]]).to_string(),
+                    ),
+                    Finding::new("response", function_name(header[3])),
+                ];
+                return Ok(ProbeStatus::Found(Service::new("DNP3", findings)));
+            }
+            Ok(ProbeStatus::Unknown)
+        })
+    }
+}
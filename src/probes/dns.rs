@@ -1,16 +1,50 @@
 use std::net::SocketAddr;
+use std::time::Duration;
 
-use tokio::net::TcpStream;
+use futures::StreamExt;
+use tokio::net::{TcpStream, UdpSocket};
 
 use trust_dns_client::client::{AsyncClient, ClientHandle};
+use trust_dns_client::error::ClientError;
+use trust_dns_client::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
 use trust_dns_client::proto::iocompat::AsyncIoTokioAsStd;
-use trust_dns_client::rr::{DNSClass, Name, RData, RecordType};
+use trust_dns_client::proto::xfer::{DnsHandle, DnsResponse};
+use trust_dns_client::rr::{DNSClass, Name, RData, Record, RecordType};
 use trust_dns_client::tcp::TcpClientStream;
+use trust_dns_client::udp::UdpClientStream;
 
-use super::{Probe, ProbeCheckFuture, ProbeStatus};
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+use crate::defaults::{DNS_AXFR_DOMAINS, DNS_RECURSION_NAME};
 
 pub struct DnsProbe;
 
+/// The same checks over UDP, where most servers listen even when their TCP port is filtered
+pub struct DnsUdpProbe;
+
+const DNS_PORT: u16 = 53;
+
+/// Queries over UDP are not retried, a lost datagram costs us this much
+const UDP_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Names answered in the CHAOS class by most servers to identify themselves
+const CHAOS_NAMES: [&str; 3] = ["version.bind.", "hostname.bind.", "id.server."];
+
+/// Records of a zone transfer listed in findings, the others are only counted
+const MAX_AXFR_RECORDS: usize = 20;
+
+/// Products announcing themselves in `version.bind` as "<prefix><version>", BIND giving its
+/// bare version
+const PRODUCTS: [(&str, &str); 5] = [
+    ("dnsmasq-", "dnsmasq"),
+    ("unbound ", "Unbound"),
+    ("PowerDNS Recursor ", "PowerDNS Recursor"),
+    (
+        "PowerDNS Authoritative Server ",
+        "PowerDNS Authoritative Server",
+    ),
+    ("Knot DNS ", "Knot DNS"),
+];
+
 macro_rules! proto_error_to_unknown {
     ($e:expr) => {{
         match $e {
@@ -22,39 +56,374 @@ macro_rules! proto_error_to_unknown {
     }};
 }
 
+impl DnsProbe {
+    async fn tcp_client(peer_addr: SocketAddr) -> Result<AsyncClient, ClientError> {
+        let (stream, sender) = TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::new(peer_addr);
+        let (client, bg) = AsyncClient::new(stream, sender, None).await?;
+
+        // Make sur to run the background task
+        tokio::spawn(bg);
+        Ok(client)
+    }
+
+    async fn udp_client(peer_addr: SocketAddr) -> Result<AsyncClient, ClientError> {
+        let stream = UdpClientStream::<UdpSocket>::with_timeout(peer_addr, UDP_TIMEOUT);
+        let (client, bg) = AsyncClient::connect(stream).await?;
+        tokio::spawn(bg);
+        Ok(client)
+    }
+
+    /// Sends a single query, with the EDNS OPT record when `dnssec_ok` is set
+    async fn send(
+        client: &mut AsyncClient,
+        name: Name,
+        class: DNSClass,
+        record_type: RecordType,
+        recursion_desired: bool,
+        dnssec_ok: Option<bool>,
+    ) -> Option<DnsResponse> {
+        let mut query = Query::query(name, record_type);
+        query.set_query_class(class);
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(recursion_desired)
+            .add_query(query);
+        if let Some(dnssec_ok) = dnssec_ok {
+            let mut edns = Edns::new();
+            edns.set_max_payload(1232).set_dnssec_ok(dnssec_ok);
+            message.set_edns(edns);
+        }
+        client.send(message).next().await?.ok()
+    }
+
+    /// TXT query in the CHAOS class
+    async fn chaos(client: &mut AsyncClient, name: &str) -> Option<DnsResponse> {
+        let name = Name::from_ascii(name).expect("valid name");
+        Self::send(client, name, DNSClass::CH, RecordType::TXT, false, None).await
+    }
+
+    /// Texts of the answer to a CHAOS query
+    fn chaos_text(response: &DnsResponse) -> Option<String> {
+        let texts: Vec<String> = response
+            .answers()
+            .iter()
+            .filter_map(|answer| match answer.data() {
+                Some(RData::TXT(txt)) => Some(txt.to_string()),
+                _ => None,
+            })
+            .collect();
+        (!texts.is_empty()).then(|| texts.join(" "))
+    }
+
+    /// Product and version announced in `version.bind`
+    fn product_findings(version: &str) -> Vec<Finding> {
+        let (product, version) = match PRODUCTS
+            .iter()
+            .find_map(|(prefix, product)| Some((*product, version.strip_prefix(prefix)?)))
+        {
+            Some(found) => found,
+            None if version.starts_with(|c: char| c.is_ascii_digit()) => ("BIND", version),
+            None => return Vec::new(),
+        };
+        let mut findings = vec![Finding::new("product", product)];
+        if let Some(version) = version.split_whitespace().next() {
+            findings.push(Finding::new("version", version));
+        }
+        findings
+    }
+
+    fn recursion_finding(response: Option<&DnsResponse>) -> Finding {
+        let value = match response {
+            Some(response)
+                if response.recursion_available()
+                    && response.response_code() == ResponseCode::NoError
+                    && !response.answers().is_empty() =>
+            {
+                "offered (open resolver)".to_owned()
+            }
+            Some(response) if response.recursion_available() => {
+                format!("advertised but answered {}", response.response_code())
+            }
+            Some(_) => "not offered".to_owned(),
+            None => "no answer".to_owned(),
+        };
+        Finding::new("recursion", value)
+    }
+
+    fn edns_findings(response: Option<&DnsResponse>) -> Vec<Finding> {
+        let response = match response {
+            Some(response) => response,
+            None => {
+                return vec![Finding::new("edns", "no answer")];
+            }
+        };
+        match response.extensions() {
+            Some(edns) => vec![
+                Finding::new(
+                    "edns",
+                    format!(
+                        "version {}, udp payload size {}",
+                        edns.version(),
+                        edns.max_payload()
+                    ),
+                ),
+                // DNSSEC aware servers copy the DO bit back (RFC 3225)
+                Finding::new(
+                    "dnssec",
+                    if edns.dnssec_ok() {
+                        "supported"
+                    } else {
+                        "not supported"
+                    },
+                ),
+            ],
+            None => vec![Finding::new(
+                "edns",
+                format!("not supported ({})", response.response_code()),
+            )],
+        }
+    }
+
+    /// Attempts a full zone transfer, which is always done over TCP
+    async fn axfr_findings(client: &mut AsyncClient, domain: &str) -> Vec<Finding> {
+        let key = format!("axfr {}", domain);
+        let zone = match Name::from_ascii(domain) {
+            Ok(zone) => zone,
+            Err(_) => {
+                return vec![Finding::new(key, "invalid domain name")];
+            }
+        };
+
+        let mut transfer = client.zone_transfer(zone, None);
+        let mut records: Vec<Record> = Vec::new();
+        while let Some(response) = transfer.next().await {
+            match response {
+                Ok(response) if response.response_code() == ResponseCode::NoError => {
+                    records.extend(response.answers().iter().cloned());
+                }
+                Ok(response) => {
+                    return vec![Finding::new(
+                        key,
+                        format!("refused ({})", response.response_code()),
+                    )];
+                }
+                Err(_) if records.is_empty() => {
+                    return vec![Finding::new(key, "refused")];
+                }
+                Err(_) => break,
+            }
+        }
+        if records.is_empty() {
+            return vec![Finding::new(key, "refused (empty answer)")];
+        }
+
+        let mut findings = vec![Finding::new(
+            key.clone(),
+            format!("allowed ({} records)", records.len()),
+        )];
+        for record in records.iter().take(MAX_AXFR_RECORDS) {
+            let data = record.data().map(|d| d.to_string()).unwrap_or_default();
+            findings.push(Finding::new(
+                key.clone(),
+                format!("{} {} {}", record.name(), record.record_type(), data),
+            ));
+        }
+        findings
+    }
+}
+
+impl DnsProbe {
+    /// Identifies the server and what it offers, through `client` over TCP or UDP: None
+    /// when it does not answer
+    async fn server_findings(client: &mut AsyncClient) -> Option<Vec<Finding>> {
+        let mut findings = Vec::new();
+        let version = Self::chaos(client, CHAOS_NAMES[0]).await?;
+        if let Some(version) = Self::chaos_text(&version) {
+            findings.extend(Self::product_findings(&version));
+            findings.push(Finding::new(
+                CHAOS_NAMES[0].trim_end_matches('.'),
+                format!("{:?}", version),
+            ));
+        }
+        for chaos_name in CHAOS_NAMES[1..].iter() {
+            let response = Self::chaos(client, chaos_name).await;
+            if let Some(text) = response.as_ref().and_then(Self::chaos_text) {
+                findings.push(Finding::new(
+                    chaos_name.trim_end_matches('.'),
+                    format!("{:?}", text),
+                ));
+            }
+        }
+
+        let recursion_name = Name::from_ascii(unsafe { DNS_RECURSION_NAME });
+        if let Ok(recursion_name) = recursion_name {
+            let response = Self::send(
+                client,
+                recursion_name,
+                DNSClass::IN,
+                RecordType::A,
+                true,
+                None,
+            )
+            .await;
+            findings.push(Self::recursion_finding(response.as_ref()));
+        }
+
+        let response = Self::send(
+            client,
+            Name::root(),
+            DNSClass::IN,
+            RecordType::SOA,
+            false,
+            Some(true),
+        )
+        .await;
+        findings.extend(Self::edns_findings(response.as_ref()));
+        Some(findings)
+    }
+}
+
 impl Probe for DnsProbe {
     fn name(&self) -> &'static str {
         "dns"
     }
 
     fn is_prefered_port(&self, port: u16) -> bool {
-        match port {
-            53 | 5353 => true,
-            _ => false,
-        }
+        matches!(port, DNS_PORT | 5353)
     }
 
     fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
         Box::pin(async move {
-            let (stream, sender) =
-                TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::new(peer_addr.clone());
-
-            let client = AsyncClient::new(stream, sender, None);
-
-            let (mut client, bg) = proto_error_to_unknown!(client.await);
-
-            // Make sur to run the background task
-            tokio::spawn(bg);
+            let mut client = proto_error_to_unknown!(Self::tcp_client(peer_addr).await);
 
             let name = Name::from(peer_addr.ip());
             let response =
-                proto_error_to_unknown!(client.query(name, DNSClass::IN, RecordType::PTR,).await);
+                proto_error_to_unknown!(client.query(name, DNSClass::IN, RecordType::PTR).await);
 
+            let mut findings = Vec::new();
             if let Some(RData::PTR(name)) = response.answers().first().and_then(|a| a.data()) {
-                println!("      - PTR({}) = {}", peer_addr.ip(), name.to_utf8());
+                findings.push(Finding::new(
+                    format!("PTR({})", peer_addr.ip()),
+                    name.to_utf8(),
+                ));
+                super::add_discovered_name(&name.to_utf8());
             }
+            findings.extend(Self::server_findings(&mut client).await.unwrap_or_default());
+            for domain in unsafe { DNS_AXFR_DOMAINS }.iter() {
+                findings.extend(Self::axfr_findings(&mut client, domain).await);
+            }
+
+            Ok(ProbeStatus::Found(Service::new("DNS", findings)))
+        })
+    }
+}
+
+impl Probe for DnsUdpProbe {
+    fn name(&self) -> &'static str {
+        "dns-udp"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        port == DNS_PORT
+    }
+
+    fn udp_port(&self) -> Option<u16> {
+        Some(DNS_PORT)
+    }
 
-            Ok(ProbeStatus::Recognized)
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut client = proto_error_to_unknown!(DnsProbe::udp_client(peer_addr).await);
+            Ok(match DnsProbe::server_findings(&mut client).await {
+                Some(findings) => ProbeStatus::Found(Service::new("DNS", findings)),
+                None => ProbeStatus::Unknown,
+            })
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    use trust_dns_client::rr::rdata::TXT;
+    use trust_dns_client::serialize::binary::{BinDecodable, BinEncodable};
+
+    /// Answers over UDP like dnsmasq, resolving the recursion name when `open`
+    async fn resolver_fixture(open: bool) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 512];
+            while let Ok((size, peer)) = socket.recv_from(&mut buffer[..]).await {
+                let query = Message::from_bytes(&buffer[..size]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(query.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query)
+                    .set_recursion_available(open)
+                    .add_queries(query.queries().to_vec());
+                let question = &query.queries()[0];
+                let name = question.name().clone();
+                match (question.query_class(), question.query_type()) {
+                    (DNSClass::CH, RecordType::TXT) if name.to_ascii() == CHAOS_NAMES[0] => {
+                        let txt = RData::TXT(TXT::new(vec!["dnsmasq-2.89".to_owned()]));
+                        let mut record = Record::from_rdata(name, 0, txt);
+                        record.set_dns_class(DNSClass::CH);
+                        response.add_answer(record);
+                    }
+                    (DNSClass::IN, RecordType::A) if open => {
+                        let a = RData::A(Ipv4Addr::new(192, 0, 2, 1));
+                        response.add_answer(Record::from_rdata(name, 60, a));
+                    }
+                    (DNSClass::IN, RecordType::A) => {
+                        response.set_response_code(ResponseCode::Refused);
+                    }
+                    _ => {}
+                }
+                let _ = socket
+                    .send_to(&response.to_bytes().unwrap()[..], peer)
+                    .await;
+            }
+        });
+        addr
+    }
+
+    async fn udp_findings(open: bool) -> Vec<Finding> {
+        let addr = resolver_fixture(open).await;
+        match DnsUdpProbe.check(addr).await.unwrap() {
+            ProbeStatus::Found(service) => {
+                assert_eq!(service.protocol, "DNS");
+                service.findings
+            }
+            status => panic!("unexpected {:?}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_open_resolvers_over_udp() {
+        let findings = udp_findings(true).await;
+        assert!(findings.contains(&Finding::new("product", "dnsmasq")));
+        assert!(findings.contains(&Finding::new("version", "2.89")));
+        assert!(findings.contains(&Finding::new("recursion", "offered (open resolver)")));
+
+        let findings = udp_findings(false).await;
+        assert!(findings.contains(&Finding::new("recursion", "not offered")));
+    }
+
+    #[tokio::test]
+    async fn ignores_silent_udp_ports() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let status = DnsUdpProbe
+            .check(socket.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(status, ProbeStatus::Unknown);
+    }
+}
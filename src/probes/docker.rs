@@ -0,0 +1,84 @@
+//! Docker Engine serves its whole API, running containers included, to anyone reaching its
+//! TCP socket, unless TLS client certificates or an authorization plugin protect it.
+
+use std::net::SocketAddr;
+
+use super::{
+    http::{self, HttpProbe},
+    Finding, Probe, ProbeCheckFuture, ProbeStatus, Service, Severity,
+};
+use crate::defaults::SERVER_NAMES;
+
+/// Fields of `/version` worth reporting
+const VERSION_FIELDS: [(&str, &str); 7] = [
+    ("/Version", "version"),
+    ("/ApiVersion", "api version"),
+    ("/MinAPIVersion", "min api version"),
+    ("/Platform/Name", "platform"),
+    ("/Os", "os"),
+    ("/Arch", "arch"),
+    ("/KernelVersion", "kernel version"),
+];
+
+pub struct DockerProbe;
+
+impl Probe for DockerProbe {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 2375 | 2376)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let server_name = unsafe { SERVER_NAMES }.first().map(|name| name.as_str());
+            let (scheme, response) =
+                match HttpProbe::get_any_scheme(peer_addr, server_name, "/version").await? {
+                    Some(answer) => answer,
+                    None => return Ok(ProbeStatus::Unknown),
+                };
+            let version = response
+                .json()
+                .filter(|json| json.get("ApiVersion").is_some());
+            // Errors from an authorization plugin still carry the API version header
+            if version.is_none() && response.header("Api-Version").is_none() {
+                return Ok(ProbeStatus::Unknown);
+            }
+
+            let mut findings = vec![Finding::new("scheme", scheme.name())];
+            match version {
+                Some(version) if response.status == 200 => {
+                    findings.extend(http::json_findings(&version, &VERSION_FIELDS));
+                    findings.push(
+                        Finding::new("auth", "not required").with_severity(Severity::Critical),
+                    );
+                    if let Ok(Some(containers)) = HttpProbe::request(
+                        peer_addr,
+                        scheme,
+                        server_name,
+                        "GET",
+                        "/containers/json?all=1",
+                        None,
+                    )
+                    .await
+                    {
+                        if let Some(serde_json::Value::Array(containers)) = containers.json() {
+                            findings.push(Finding::new("containers", containers.len().to_string()));
+                        }
+                    }
+                }
+                _ => {
+                    findings.push(Finding::new("status", response.status_line.clone()));
+                    findings.push(Finding::new("auth", "required"));
+                }
+            }
+
+            Ok(ProbeStatus::Found(Service::new(
+                "Docker Engine API",
+                findings,
+            )))
+        })
+    }
+}
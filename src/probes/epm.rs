@@ -0,0 +1,410 @@
+//! The MS-RPC endpoint mapper lists the interfaces registered on a Windows host, and
+//! where they listen, to anyone asking with `ept_lookup`.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const PTYPE_REQUEST: u8 = 0;
+const PTYPE_RESPONSE: u8 = 2;
+const PTYPE_BIND: u8 = 11;
+const PTYPE_BIND_ACK: u8 = 12;
+
+const PFC_FIRST_FRAG: u8 = 0x01;
+const PFC_LAST_FRAG: u8 = 0x02;
+
+/// Little endian integers, ASCII characters and IEEE floats
+const DATA_REPRESENTATION: [u8; 4] = [0x10, 0, 0, 0];
+
+/// Endpoint mapper interface, version 3.0
+const EPM_UUID: [u8; 16] = [
+    0x08, 0x83, 0xaf, 0xe1, 0x1f, 0x5d, 0xc9, 0x11, 0x91, 0xa4, 0x08, 0x00, 0x2b, 0x14, 0xa0, 0xfa,
+];
+const EPM_VERSION: u32 = 3;
+
+/// NDR transfer syntax, version 2
+const NDR_UUID: [u8; 16] = [
+    0x04, 0x5d, 0x88, 0x8a, 0xeb, 0x1c, 0xc9, 0x11, 0x9f, 0xe8, 0x08, 0x00, 0x2b, 0x10, 0x48, 0x60,
+];
+const NDR_VERSION: u32 = 2;
+
+const OPNUM_EPT_LOOKUP: u16 = 2;
+
+/// All the elements, for all the versions of the interfaces
+const RPC_C_EP_ALL_ELTS: u32 = 0;
+const RPC_C_VERS_ALL: u32 = 1;
+
+const MAX_FRAGMENT_SIZE: u16 = 4280;
+
+/// Entries asked by lookup, and lookups made before giving up
+const ENTRIES_PER_LOOKUP: u32 = 100;
+const MAX_LOOKUPS: usize = 10;
+
+/// Well known interfaces, which are not always annotated
+const INTERFACES: [(&str, &str); 14] = [
+    ("12345778-1234-abcd-ef00-0123456789ab", "LSA (lsarpc)"),
+    ("12345778-1234-abcd-ef00-0123456789ac", "SAM (samr)"),
+    ("12345678-1234-abcd-ef00-01234567cffb", "Netlogon"),
+    (
+        "12345678-1234-abcd-ef00-0123456789ab",
+        "Print spooler (MS-RPRN)",
+    ),
+    (
+        "76f03f96-cdfd-44fc-a22c-64950a001209",
+        "Remote print (MS-PAR)",
+    ),
+    (
+        "4b324fc8-1670-01d3-1278-5a47bf6ee188",
+        "Server service (srvsvc)",
+    ),
+    (
+        "6bffd098-a112-3610-9833-46c3f87e345a",
+        "Workstation service (wkssvc)",
+    ),
+    (
+        "367abb81-9844-35f1-ad32-98f038001003",
+        "Service control manager (svcctl)",
+    ),
+    (
+        "338cd001-2244-31f1-aaaa-900038001003",
+        "Remote registry (winreg)",
+    ),
+    (
+        "86d35949-83c9-4044-b424-db363231fd0c",
+        "Task scheduler (ITaskSchedulerService)",
+    ),
+    (
+        "1ff70682-0a51-30e8-076d-740be8cee98b",
+        "Task scheduler (atsvc)",
+    ),
+    (
+        "e3514235-4b06-11d1-ab04-00c04fc2dcd2",
+        "Directory replication (drsuapi)",
+    ),
+    (
+        "c681d488-d850-11d0-8c52-00c04fd90f7e",
+        "Encrypting file system (efsrpc)",
+    ),
+    (
+        "df1941c5-fe89-4e79-bf10-463657acf44d",
+        "Encrypting file system (efsrpc)",
+    ),
+];
+
+pub struct EpmProbe;
+
+/// Formats a GUID, whose first three fields are little endian
+pub(super) fn format_guid(bytes: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{}",
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        u16::from_be_bytes([bytes[8], bytes[9]]),
+        bytes[10..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+fn pdu(ptype: u8, call_id: u32, body: &[u8]) -> Vec<u8> {
+    let mut pdu = vec![5, 0, ptype, PFC_FIRST_FRAG | PFC_LAST_FRAG];
+    pdu.extend_from_slice(&DATA_REPRESENTATION);
+    pdu.extend_from_slice(&((body.len() + 16) as u16).to_le_bytes());
+    // authentication length
+    pdu.extend_from_slice(&[0, 0]);
+    pdu.extend_from_slice(&call_id.to_le_bytes());
+    pdu.extend_from_slice(body);
+    pdu
+}
+
+fn bind_request() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&MAX_FRAGMENT_SIZE.to_le_bytes());
+    body.extend_from_slice(&MAX_FRAGMENT_SIZE.to_le_bytes());
+    // association group
+    body.extend_from_slice(&[0u8; 4]);
+    // a single presentation context, with a single transfer syntax
+    body.extend_from_slice(&[1, 0, 0, 0]);
+    body.extend_from_slice(&[0, 0, 1, 0]);
+    body.extend_from_slice(&EPM_UUID);
+    body.extend_from_slice(&EPM_VERSION.to_le_bytes());
+    body.extend_from_slice(&NDR_UUID);
+    body.extend_from_slice(&NDR_VERSION.to_le_bytes());
+    pdu(PTYPE_BIND, 1, &body[..])
+}
+
+fn lookup_request(call_id: u32, entry_handle: &[u8]) -> Vec<u8> {
+    let mut stub = Vec::new();
+    stub.extend_from_slice(&RPC_C_EP_ALL_ELTS.to_le_bytes());
+    // no object, no interface
+    stub.extend_from_slice(&[0u8; 8]);
+    stub.extend_from_slice(&RPC_C_VERS_ALL.to_le_bytes());
+    stub.extend_from_slice(entry_handle);
+    stub.extend_from_slice(&ENTRIES_PER_LOOKUP.to_le_bytes());
+
+    let mut body = (stub.len() as u32).to_le_bytes().to_vec();
+    // presentation context
+    body.extend_from_slice(&[0, 0]);
+    body.extend_from_slice(&OPNUM_EPT_LOOKUP.to_le_bytes());
+    body.extend_from_slice(&stub[..]);
+    pdu(PTYPE_REQUEST, call_id, &body[..])
+}
+
+/// Reads NDR data, keeping track of the alignment
+struct Ndr<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Ndr<'a> {
+    fn align(&mut self, alignment: usize) {
+        self.index = self.index.next_multiple_of(alignment);
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.index..self.index + len)?;
+        self.index += len;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        self.take(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+/// A registered interface, with where it can be reached
+struct Entry {
+    uuid: String,
+    version: (u16, u16),
+    annotation: String,
+    binding: Option<String>,
+}
+
+/// Describes the binding of a protocol tower, like "ncacn_ip_tcp:49664"
+fn tower_binding(tower: &[u8]) -> Option<(String, (u16, u16), Option<String>)> {
+    let floors = u16::from_le_bytes([*tower.first()?, *tower.get(1)?]);
+    let mut index = 2;
+    let mut interface = None;
+    let mut binding = None;
+    for floor in 0..floors {
+        let lhs_len = u16::from_le_bytes([*tower.get(index)?, *tower.get(index + 1)?]) as usize;
+        let lhs = tower.get(index + 2..index + 2 + lhs_len)?;
+        index += 2 + lhs_len;
+        let rhs_len = u16::from_le_bytes([*tower.get(index)?, *tower.get(index + 1)?]) as usize;
+        let rhs = tower.get(index + 2..index + 2 + rhs_len)?;
+        index += 2 + rhs_len;
+
+        let string = || {
+            String::from_utf8_lossy(rhs)
+                .trim_end_matches('\0')
+                .to_owned()
+        };
+        match (floor, lhs.first()) {
+            (0, Some(0x0d)) if lhs.len() >= 19 && rhs.len() >= 2 => {
+                interface = Some((
+                    format_guid(&lhs[1..17]),
+                    (
+                        u16::from_le_bytes([lhs[17], lhs[18]]),
+                        u16::from_le_bytes([rhs[0], rhs[1]]),
+                    ),
+                ));
+            }
+            (_, Some(0x07)) if rhs.len() == 2 => {
+                binding = Some(format!(
+                    "ncacn_ip_tcp:{}",
+                    u16::from_be_bytes([rhs[0], rhs[1]])
+                ));
+            }
+            (_, Some(0x08)) if rhs.len() == 2 => {
+                binding = Some(format!(
+                    "ncadg_ip_udp:{}",
+                    u16::from_be_bytes([rhs[0], rhs[1]])
+                ));
+            }
+            (_, Some(0x0f)) => binding = Some(format!("ncacn_np:{}", string())),
+            (_, Some(0x10)) => binding = Some(format!("ncalrpc:{}", string())),
+            (_, Some(0x1f)) if rhs.len() == 2 => {
+                binding = Some(format!(
+                    "ncacn_http:{}",
+                    u16::from_be_bytes([rhs[0], rhs[1]])
+                ));
+            }
+            _ => {}
+        }
+    }
+    let (uuid, version) = interface?;
+    Some((uuid, version, binding))
+}
+
+/// Parses an `ept_lookup` answer, returns the entry handle for the next call and
+/// the entries
+fn lookup_response(stub: &[u8]) -> Option<(Vec<u8>, Vec<Entry>)> {
+    let mut ndr = Ndr {
+        data: stub,
+        index: 0,
+    };
+    let entry_handle = ndr.take(20)?.to_vec();
+    let count = ndr.u32()? as usize;
+    // conformant and varying array: maximum count, offset, actual count
+    ndr.u32()?;
+    ndr.u32()?;
+    let actual = ndr.u32()? as usize;
+    if actual != count {
+        return None;
+    }
+
+    let mut entries = Vec::new();
+    let mut has_tower = Vec::new();
+    for _ in 0..count {
+        ndr.align(4);
+        ndr.take(16)?;
+        has_tower.push(ndr.u32()? != 0);
+        // annotation, a varying string
+        ndr.u32()?;
+        let len = ndr.u32()? as usize;
+        let annotation = ndr.take(len)?;
+        let annotation = String::from_utf8_lossy(annotation)
+            .trim_end_matches('\0')
+            .to_owned();
+        entries.push(annotation);
+    }
+
+    // Towers follow the array, as deferred pointers
+    let mut result = Vec::new();
+    for (annotation, has_tower) in entries.into_iter().zip(has_tower) {
+        if !has_tower {
+            continue;
+        }
+        // conformant array size, then tower length
+        ndr.u32()?;
+        let len = ndr.u32()? as usize;
+        let tower = ndr.take(len)?;
+        if let Some((uuid, version, binding)) = tower_binding(tower) {
+            result.push(Entry {
+                uuid,
+                version,
+                annotation,
+                binding,
+            });
+        }
+    }
+    Some((entry_handle, result))
+}
+
+impl EpmProbe {
+    /// Reads a whole answer, gathering the stubs of its fragments
+    async fn read_response(stream: &mut TcpStream, ptype: u8) -> io::Result<Option<Vec<u8>>> {
+        let mut stub = Vec::new();
+        loop {
+            let mut header = [0u8; 16];
+            stream.read_exact(&mut header[..]).await?;
+            let length = u16::from_le_bytes([header[8], header[9]]) as usize;
+            if header[0] != 5 || header[2] != ptype || length < 16 {
+                return Ok(None);
+            }
+            let mut body = vec![0u8; length - 16];
+            stream.read_exact(&mut body[..]).await?;
+            if ptype != PTYPE_RESPONSE {
+                return Ok(Some(body));
+            }
+            // allocation hint, presentation context, cancel count and reserved
+            stub.extend_from_slice(body.get(8..).unwrap_or_default());
+            if header[3] & PFC_LAST_FRAG != 0 {
+                return Ok(Some(stub));
+            }
+        }
+    }
+}
+
+impl Probe for EpmProbe {
+    fn name(&self) -> &'static str {
+        "epm"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 135)
+    }
+
+    fn rarity(&self) -> u8 {
+        8
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            stream.write_all(&bind_request()[..]).await?;
+            if Self::read_response(&mut stream, PTYPE_BIND_ACK)
+                .await?
+                .is_none()
+            {
+                return Ok(ProbeStatus::Unknown);
+            }
+
+            let mut entries = Vec::new();
+            let mut entry_handle = vec![0u8; 20];
+            for call_id in 2..MAX_LOOKUPS as u32 + 2 {
+                stream
+                    .write_all(&lookup_request(call_id, &entry_handle[..])[..])
+                    .await?;
+                let stub = match Self::read_response(&mut stream, PTYPE_RESPONSE).await? {
+                    Some(stub) => stub,
+                    None => break,
+                };
+                match lookup_response(&stub[..]) {
+                    Some((handle, found)) if !found.is_empty() => {
+                        entries.extend(found);
+                        entry_handle = handle;
+                    }
+                    _ => break,
+                }
+                // A null handle means there is nothing left
+                if entry_handle.iter().all(|b| *b == 0) {
+                    break;
+                }
+            }
+
+            let mut findings = vec![Finding::new("entries", entries.len().to_string())];
+            let mut listed: Vec<(&str, (u16, u16))> = Vec::new();
+            for entry in entries.iter() {
+                if listed.contains(&(entry.uuid.as_str(), entry.version)) {
+                    continue;
+                }
+                listed.push((entry.uuid.as_str(), entry.version));
+                let name = INTERFACES
+                    .iter()
+                    .find(|(uuid, _)| *uuid == entry.uuid)
+                    .map(|(_, name)| *name)
+                    .unwrap_or(entry.annotation.as_str());
+                let bindings: Vec<&str> = entries
+                    .iter()
+                    .filter(|e| e.uuid == entry.uuid && e.version == entry.version)
+                    .filter_map(|e| e.binding.as_deref())
+                    .collect();
+                findings.push(Finding::new(
+                    "interface",
+                    format!(
+                        "{} v{}.{} {:?} ({})",
+                        entry.uuid,
+                        entry.version.0,
+                        entry.version.1,
+                        name,
+                        bindings.join(", ")
+                    ),
+                ));
+            }
+
+            Ok(ProbeStatus::Found(Service::new(
+                "MS-RPC endpoint mapper",
+                findings,
+            )))
+        })
+    }
+}
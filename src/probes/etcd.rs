@@ -0,0 +1,110 @@
+//! etcd tells its version on `/version`, and serves its keys, Kubernetes' secrets among
+//! them, to anyone when authentication is not enabled.
+
+use std::net::SocketAddr;
+
+use super::{
+    http::{self, HttpProbe, Scheme},
+    Finding, Probe, ProbeCheckFuture, ProbeStatus, Service, Severity,
+};
+use crate::defaults::SERVER_NAMES;
+
+const VERSION_FIELDS: [(&str, &str); 2] = [
+    ("/etcdserver", "version"),
+    ("/etcdcluster", "cluster version"),
+];
+
+/// Counts every key, from the smallest one ("\0", base64 encoded) with no end
+const COUNT_KEYS_REQUEST: &str = r#"{"key":"AA==","range_end":"AA==","count_only":true}"#;
+
+pub struct EtcdProbe;
+
+/// Outcome of reading the keys without credentials
+enum Access {
+    /// Granted, with the count of keys
+    Granted(String),
+    Refused,
+}
+
+impl EtcdProbe {
+    /// Counts the keys through the JSON gateway of the v3 API, or the v2 API of old servers
+    async fn count_keys(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+    ) -> Option<Access> {
+        let range = HttpProbe::request(
+            peer_addr,
+            scheme,
+            server_name,
+            "POST",
+            "/v3/kv/range",
+            Some(COUNT_KEYS_REQUEST),
+        )
+        .await
+        .ok()??;
+        if range.status != 404 {
+            let json = range.json()?;
+            if range.status != 200 {
+                return Some(Access::Refused);
+            }
+            // A count of zero is omitted
+            let count = json.get("count").and_then(|count| count.as_str());
+            return Some(Access::Granted(count.unwrap_or("0").to_owned()));
+        }
+
+        let keys = HttpProbe::request(peer_addr, scheme, server_name, "GET", "/v2/keys", None)
+            .await
+            .ok()??;
+        let json = keys.json()?;
+        if keys.status != 200 {
+            return Some(Access::Refused);
+        }
+        let count = match json.pointer("/node/nodes") {
+            Some(serde_json::Value::Array(nodes)) => nodes.len(),
+            _ => 0,
+        };
+        Some(Access::Granted(format!("{} (v2)", count)))
+    }
+}
+
+impl Probe for EtcdProbe {
+    fn name(&self) -> &'static str {
+        "etcd"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        // client and peer ports
+        matches!(port, 2379 | 2380)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let server_name = unsafe { SERVER_NAMES }.first().map(|name| name.as_str());
+            let (scheme, response) =
+                match HttpProbe::get_any_scheme(peer_addr, server_name, "/version").await? {
+                    Some(answer) => answer,
+                    None => return Ok(ProbeStatus::Unknown),
+                };
+            let version = match response.json() {
+                Some(json) if json.get("etcdserver").is_some() => json,
+                _ => return Ok(ProbeStatus::Unknown),
+            };
+
+            let mut findings = vec![Finding::new("scheme", scheme.name())];
+            findings.extend(http::json_findings(&version, &VERSION_FIELDS));
+            match Self::count_keys(peer_addr, scheme, server_name).await {
+                Some(Access::Granted(count)) => {
+                    findings.push(Finding::new("keys", count));
+                    findings.push(
+                        Finding::new("auth", "not required").with_severity(Severity::Critical),
+                    );
+                }
+                Some(Access::Refused) => findings.push(Finding::new("auth", "required")),
+                None => {}
+            }
+
+            Ok(ProbeStatus::Found(Service::new("etcd", findings)))
+        })
+    }
+}
@@ -0,0 +1,113 @@
+//! Fingerprints of the services no probe recognized, recorded to write new rules from real
+//! data: the banner (null probe) and the answers to a few generic requests, one line each:
+//!
+//! `<port>/tcp v=<version> null="..." generic-lines="..." get-request="..." tls-hello="..." timed-out=<probes>`
+//!
+//! Answers are escaped (`\r`, `\n`, `\t`, `\\`, `\"`, other bytes as `\xNN`) and empty when
+//! the service said nothing; a request is left out when the connection failed. The probes
+//! which timed out, comma separated, tell a slow service from an unknown one; the field is
+//! left out when none did.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::tls;
+use crate::defaults::{CONNECT_TIMEOUT, FINGERPRINT_FILE, READ_TIMEOUT};
+use crate::utils::run_with_timeout;
+
+/// Upper bound on each recorded answer, enough for rules to match
+const MAX_ANSWER_SIZE: usize = 1024;
+
+/// The generic requests, as (name, request)
+fn requests() -> [(&'static str, Vec<u8>); 3] {
+    [
+        ("generic-lines", b"\r\n\r\n".to_vec()),
+        ("get-request", b"GET / HTTP/1.0\r\n\r\n".to_vec()),
+        ("tls-hello", tls::client_hello_record()),
+    ]
+}
+
+fn escape(data: &[u8]) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for b in data {
+        match b {
+            b'\r' => escaped.push_str("\\r"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\t' => escaped.push_str("\\t"),
+            b'\\' => escaped.push_str("\\\\"),
+            b'"' => escaped.push_str("\\\""),
+            0x20..=0x7e => escaped.push(*b as char),
+            _ => {
+                let _ = write!(escaped, "\\x{:02x}", b);
+            }
+        }
+    }
+    escaped
+}
+
+/// Sends a request on a new connection, returns what came back before the read timeout
+async fn answer(peer_addr: SocketAddr, request: &[u8]) -> Option<Vec<u8>> {
+    let mut stream = run_with_timeout(unsafe { CONNECT_TIMEOUT }, TcpStream::connect(peer_addr))
+        .await?
+        .ok()?;
+    stream.write_all(request).await.ok()?;
+    let mut answer = Vec::new();
+    let _ = run_with_timeout(unsafe { READ_TIMEOUT }, async {
+        let mut chunk = [0u8; 512];
+        while answer.len() < MAX_ANSWER_SIZE {
+            match stream.read(&mut chunk[..]).await {
+                Ok(0) | Err(_) => break,
+                Ok(size) => answer.extend_from_slice(&chunk[..size]),
+            }
+        }
+    })
+    .await;
+    answer.truncate(MAX_ANSWER_SIZE);
+    Some(answer)
+}
+
+/// Records the fingerprint of an unrecognized service, along with the probes which timed
+/// out on it, when a fingerprint file is set
+pub async fn record(peer_addr: &SocketAddr, banner: Option<&[u8]>, timed_out: &[&str]) {
+    let file = match FINGERPRINT_FILE.get() {
+        Some(file) => file,
+        None => return,
+    };
+
+    let requests = requests();
+    let answers = futures::future::join_all(
+        requests
+            .iter()
+            .map(|(_, request)| answer(*peer_addr, &request[..])),
+    )
+    .await;
+
+    let mut line = format!(
+        "{}/tcp v={} null=\"{}\"",
+        peer_addr.port(),
+        env!("CARGO_PKG_VERSION"),
+        escape(banner.unwrap_or_default())
+    );
+    for ((name, _), answer) in requests.iter().zip(answers) {
+        if let Some(answer) = answer {
+            let _ = write!(line, " {}=\"{}\"", name, escape(&answer[..]));
+        }
+    }
+    if !timed_out.is_empty() {
+        let _ = write!(line, " timed-out={}", timed_out.join(","));
+    }
+    line.push('\n');
+
+    // Lines are written whole, one port at a time, without blocking the runtime
+    let mut file = file.lock().await;
+    let written = match file.write_all(line.as_bytes()).await {
+        Ok(()) => file.flush().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        outputln!("      - fingerprint not recorded: {}", e);
+    }
+}
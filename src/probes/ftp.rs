@@ -0,0 +1,154 @@
+//! FTP servers greet clients with a 220 reply, anonymous login is tried with the
+//! conventional `anonymous` user before asking for the system type and the features.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const ANONYMOUS_USER: &str = "anonymous";
+const ANONYMOUS_PASSWORD: &str = "anonymous@example.com";
+
+/// Upper bound on the lines of a reply, the features are one per line
+const MAX_REPLY_LINES: usize = 128;
+
+pub struct FtpProbe;
+
+/// A reply, as its code and its lines of text
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    fn text(&self) -> &str {
+        self.lines.first().map(String::as_str).unwrap_or_default()
+    }
+}
+
+impl FtpProbe {
+    /// Reads a reply, spanning several lines from `123-text` to `123 text`
+    async fn read_reply(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Reply>> {
+        let mut reply: Option<Reply> = None;
+        for _ in 0..MAX_REPLY_LINES {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim_end();
+            let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+            let separator = line.as_bytes().get(3).copied();
+            match (&mut reply, code, separator) {
+                (None, Some(code), Some(b'-')) => {
+                    reply = Some(Reply {
+                        code,
+                        lines: vec![line[4..].to_owned()],
+                    })
+                }
+                (None, Some(code), Some(b' ') | None) => {
+                    return Ok(Some(Reply {
+                        code,
+                        lines: vec![line.get(4..).unwrap_or_default().to_owned()],
+                    }))
+                }
+                (None, _, _) => return Ok(None),
+                (Some(reply), Some(code), Some(b' ') | None) if code == reply.code => {
+                    reply
+                        .lines
+                        .push(line.get(4..).unwrap_or_default().to_owned());
+                    break;
+                }
+                (Some(reply), _, _) => reply.lines.push(line.to_owned()),
+            }
+        }
+        Ok(reply)
+    }
+
+    async fn command(
+        reader: &mut BufReader<TcpStream>,
+        command: &str,
+    ) -> io::Result<Option<Reply>> {
+        reader
+            .get_mut()
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+        Self::read_reply(reader).await
+    }
+}
+
+impl Probe for FtpProbe {
+    fn name(&self) -> &'static str {
+        "ftp"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 21 | 2121)
+    }
+
+    fn accepts_banner(&self, banner: &[u8]) -> bool {
+        banner.starts_with(b"220 ") || banner.starts_with(b"220-")
+    }
+
+    fn expects_banner(&self) -> bool {
+        true
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut reader = BufReader::new(TcpStream::connect(&peer_addr).await?);
+            let greeting = match Self::read_reply(&mut reader).await? {
+                Some(reply) if reply.code == 220 => reply,
+                _ => return Ok(ProbeStatus::Unknown),
+            };
+            let greeting: Vec<&str> = greeting.lines.iter().map(|line| line.trim()).collect();
+            let mut findings = vec![Finding::new("greeting", greeting.join(" "))];
+
+            let mut login = Self::command(&mut reader, &format!("USER {}", ANONYMOUS_USER)).await?;
+            if matches!(&login, Some(reply) if reply.code == 331) {
+                login = Self::command(&mut reader, &format!("PASS {}", ANONYMOUS_PASSWORD)).await?;
+            }
+            findings.push(Finding::new(
+                "anonymous login",
+                match login {
+                    Some(reply) if reply.code == 230 => "accepted".to_owned(),
+                    Some(reply) => format!("refused ({} {})", reply.code, reply.text()),
+                    None => "refused".to_owned(),
+                },
+            ));
+
+            // Servers may hang up on us after a refused login
+            if let Ok(Some(reply)) = Self::command(&mut reader, "SYST").await {
+                if reply.code == 215 {
+                    findings.push(Finding::new("system", reply.text()));
+                }
+            }
+            if let Ok(Some(reply)) = Self::command(&mut reader, "FEAT").await {
+                if reply.code == 211 {
+                    // Features are indented, between the first and the last lines
+                    let features: Vec<&str> = reply
+                        .lines
+                        .iter()
+                        .filter(|line| line.starts_with(' '))
+                        .map(|line| line.trim())
+                        .collect();
+                    // Like `AUTH TLS` or `AUTH TLS;TLS-C;SSL;TLS-P`
+                    let tls = features.iter().any(|feature| {
+                        let feature = feature.to_ascii_uppercase();
+                        feature.starts_with("AUTH ") && feature.contains("TLS")
+                    });
+                    findings.push(Finding::new("features", features.join(", ")));
+                    findings.push(Finding::new(
+                        "tls",
+                        if tls { "supported" } else { "not supported" },
+                    ));
+                }
+            }
+            let _ = reader.get_mut().write_all(b"QUIT\r\n").await;
+
+            Ok(ProbeStatus::Found(Service::new("FTP", findings)))
+        })
+    }
+}
@@ -0,0 +1,307 @@
+use std::io;
+use std::net::SocketAddr;
+
+use super::{
+    http::{self, h2, HttpProbe, Scheme},
+    Finding, Probe, ProbeCheckFuture, ProbeStatus, Service,
+};
+
+/// Reflection services, newest first
+const REFLECTION_SERVICES: [&str; 2] = [
+    "grpc.reflection.v1.ServerReflection",
+    "grpc.reflection.v1alpha.ServerReflection",
+];
+
+/// Status returned for a method the server does not know
+const STATUS_UNIMPLEMENTED: &str = "12";
+
+/// Upper bound on the services we describe, each one costs a request
+const MAX_DESCRIBED_SERVICES: usize = 32;
+
+pub struct GrpcProbe;
+
+/// Reads the length-delimited fields of a protobuf message as (field number, value)
+fn protobuf_fields(mut data: &[u8]) -> Vec<(u64, &[u8])> {
+    fn varint(data: &mut &[u8]) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = data.split_first()?;
+            *data = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    let mut fields = Vec::new();
+    while let Some(key) = varint(&mut data) {
+        let skip = match key & 0x7 {
+            0 => match varint(&mut data) {
+                Some(_) => 0,
+                None => break,
+            },
+            1 => 8,
+            2 => {
+                let len = match varint(&mut data) {
+                    Some(len) if len as usize <= data.len() => len as usize,
+                    _ => break,
+                };
+                let (value, rest) = data.split_at(len);
+                fields.push((key >> 3, value));
+                data = rest;
+                0
+            }
+            5 => 4,
+            _ => break,
+        };
+        if data.len() < skip {
+            break;
+        }
+        data = &data[skip..];
+    }
+    fields
+}
+
+/// Length-delimited fields numbered `number`
+fn protobuf_bytes(data: &[u8], number: u64) -> Vec<&[u8]> {
+    protobuf_fields(data)
+        .into_iter()
+        .filter(|(n, _)| *n == number)
+        .map(|(_, value)| value)
+        .collect()
+}
+
+fn protobuf_string(data: &[u8], number: u64) -> Option<String> {
+    protobuf_bytes(data, number)
+        .first()
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Frames a protobuf message as a gRPC message (not compressed)
+fn grpc_message(message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(message.len() + 5);
+    data.push(0);
+    data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    data.extend_from_slice(message);
+    data
+}
+
+/// Splits a gRPC body in its messages, skipping compressed ones
+fn grpc_messages(mut data: &[u8]) -> Vec<&[u8]> {
+    let mut messages = Vec::new();
+    while data.len() >= 5 {
+        let len = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+        if data.len() < len + 5 {
+            break;
+        }
+        if data[0] == 0 {
+            messages.push(&data[5..len + 5]);
+        }
+        data = &data[len + 5..];
+    }
+    messages
+}
+
+/// A ServerReflectionRequest with a single string field set
+fn reflection_request(number: u8, value: &str) -> Vec<u8> {
+    let mut message = vec![(number << 3) | 2];
+    let mut len = value.len();
+    while len >= 0x80 {
+        message.push((len as u8) | 0x80);
+        len >>= 7;
+    }
+    message.push(len as u8);
+    message.extend_from_slice(value.as_bytes());
+    message
+}
+
+struct Call<'a> {
+    scheme: Scheme,
+    authority: &'a str,
+}
+
+impl GrpcProbe {
+    async fn call<S>(
+        client: &mut h2::Client<S>,
+        call: &Call<'_>,
+        path: &str,
+        message: &[u8],
+    ) -> io::Result<h2::Response>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let user_agent = unsafe { crate::defaults::USER_AGENT };
+        let headers = [
+            (":method", "POST"),
+            (":scheme", call.scheme.name()),
+            (":path", path),
+            (":authority", call.authority),
+            ("content-type", "application/grpc"),
+            ("te", "trailers"),
+            ("user-agent", user_agent),
+        ];
+        client
+            .request(&headers[..], &grpc_message(message)[..])
+            .await
+    }
+
+    fn is_grpc(response: &h2::Response) -> bool {
+        response
+            .header("content-type")
+            .map(|t| t.starts_with("application/grpc"))
+            .unwrap_or(false)
+            || response.header("grpc-status").is_some()
+    }
+
+    /// Lists methods as "name(input) returns (output)", from a FileDescriptorResponse
+    fn describe_service(response: &[u8], service: &str) -> Vec<String> {
+        let mut methods = Vec::new();
+        // FileDescriptorResponse.file_descriptor_proto
+        for file in protobuf_bytes(response, 1) {
+            let package = protobuf_string(file, 2).unwrap_or_default();
+            // FileDescriptorProto.service
+            for descriptor in protobuf_bytes(file, 6) {
+                let name = protobuf_string(descriptor, 1).unwrap_or_default();
+                let full_name = if package.is_empty() {
+                    name
+                } else {
+                    format!("{}.{}", package, name)
+                };
+                if full_name != service {
+                    continue;
+                }
+                // ServiceDescriptorProto.method
+                for method in protobuf_bytes(descriptor, 2) {
+                    methods.push(format!(
+                        "{}({}) returns ({})",
+                        protobuf_string(method, 1).unwrap_or_default(),
+                        protobuf_string(method, 2)
+                            .unwrap_or_default()
+                            .trim_start_matches('.'),
+                        protobuf_string(method, 3)
+                            .unwrap_or_default()
+                            .trim_start_matches('.'),
+                    ));
+                }
+            }
+        }
+        methods
+    }
+
+    /// Speaks gRPC over an HTTP/2 connection, in clear text or inside TLS, and lists
+    /// services through the reflection service when it is enabled. Returns None when the
+    /// peer does not speak gRPC.
+    pub(super) async fn check_scheme(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+    ) -> io::Result<Option<Service>> {
+        let stream = HttpProbe::connect(peer_addr, scheme, server_name, &["h2"]).await?;
+        let mut client = match h2::Client::connect(stream).await? {
+            Some((client, _)) => client,
+            None => {
+                return Ok(None);
+            }
+        };
+        let authority = HttpProbe::authority(peer_addr, scheme, server_name);
+        let call = Call {
+            scheme,
+            authority: &authority,
+        };
+
+        let mut recognized = false;
+        let mut findings = Vec::new();
+        let mut services = None;
+        for reflection in REFLECTION_SERVICES.iter() {
+            let path = format!("/{}/ServerReflectionInfo", reflection);
+            // ServerReflectionRequest.list_services
+            let response =
+                Self::call(&mut client, &call, &path, &reflection_request(7, "*")).await?;
+            if !Self::is_grpc(&response) {
+                break;
+            }
+            recognized = true;
+            if response.header("grpc-status") == Some(STATUS_UNIMPLEMENTED) {
+                continue;
+            }
+
+            // ServerReflectionResponse.list_services_response.service.name
+            let names: Vec<String> = grpc_messages(&response.body[..])
+                .into_iter()
+                .flat_map(|message| protobuf_bytes(message, 6))
+                .flat_map(|list| protobuf_bytes(list, 1))
+                .filter_map(|service| protobuf_string(service, 1))
+                .collect();
+            findings.push(Finding::new(
+                "reflection",
+                format!("enabled ({})", reflection),
+            ));
+            services = Some((path, names));
+            break;
+        }
+
+        if !recognized {
+            return Ok(None);
+        }
+        let (path, names) = match services {
+            Some(services) => services,
+            None => {
+                findings.push(Finding::new("reflection", "disabled"));
+                return Ok(Some(Service::new("gRPC", findings)));
+            }
+        };
+
+        for (index, name) in names.iter().enumerate() {
+            findings.push(Finding::new("service", name.as_str()));
+            if index >= MAX_DESCRIBED_SERVICES || name.starts_with("grpc.reflection.") {
+                continue;
+            }
+            // ServerReflectionRequest.file_containing_symbol
+            let response =
+                match Self::call(&mut client, &call, &path, &reflection_request(4, name)).await {
+                    Ok(response) => response,
+                    Err(_) => break,
+                };
+            // ServerReflectionResponse.file_descriptor_response
+            for message in grpc_messages(&response.body[..]) {
+                for descriptors in protobuf_bytes(message, 4) {
+                    for method in Self::describe_service(descriptors, name) {
+                        findings.push(Finding::new("method", format!("{}/{}", name, method)));
+                    }
+                }
+            }
+        }
+
+        Ok(Some(Service::new("gRPC", findings)))
+    }
+}
+
+impl Probe for GrpcProbe {
+    fn name(&self) -> &'static str {
+        "grpc"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 6565 | 8980 | 9090 | 50051 | 50052)
+    }
+
+    fn accepts_banner(&self, banner: &[u8]) -> bool {
+        http::is_h2_banner(banner)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let server_name = unsafe { crate::defaults::SERVER_NAMES }
+                .first()
+                .map(|name| name.as_str());
+            Ok(
+                match Self::check_scheme(peer_addr, Scheme::Http, server_name).await? {
+                    Some(service) => ProbeStatus::Found(service),
+                    None => ProbeStatus::Unknown,
+                },
+            )
+        })
+    }
+}
@@ -1,94 +1,524 @@
+use std::io::{self, Cursor};
 use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
 
+use regex::Regex;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
 };
 
-use super::{Probe, ProbeCheckFuture, ProbeStatus};
+use super::{cpe, tls::TlsProbe, Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+use crate::defaults::{HTTP_MAX_REDIRECTS, HTTP_TECHNOLOGIES, READ_TIMEOUT, SERVER_NAMES};
+use crate::utils::run_with_timeout;
 
-pub struct HttpProbe;
+pub(super) mod h2;
+mod response;
+pub(super) mod tech;
 
-impl HttpProbe {
-    fn search_interesting_headers(headers: &str) {
-        const INTERESTING_HEADERS: [&'static str; 2] = ["Server: ", "X-Powered-By: "];
-        for interesting_header in INTERESTING_HEADERS.iter() {
-            if let Some(start_index) = headers.find(interesting_header) {
-                let start = start_index + interesting_header.len();
-                let end_index = start
-                    + headers[start..]
-                        .find("\r\n")
-                        .expect("\\r\\n  should be present");
-                println!("      - {}", &headers[start_index..end_index]);
-            }
+pub(super) use response::Response;
+
+/// Headers hardening a web application, reported when missing
+const SECURITY_HEADERS: [&str; 6] = [
+    "Strict-Transport-Security",
+    "Content-Security-Policy",
+    "X-Frame-Options",
+    "X-Content-Type-Options",
+    "Referrer-Policy",
+    "Permissions-Policy",
+];
+
+/// Answer of Go's HTTP servers to plain HTTP sent to their TLS port, like most cloud
+/// native APIs
+const PLAIN_HTTP_TO_TLS_ANSWER: &str = "Client sent an HTTP request to an HTTPS server";
+
+pub(super) trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Scheme {
+    Http,
+    Https,
+}
+
+impl Scheme {
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        }
+    }
+
+    fn default_port(self) -> u16 {
+        match self {
+            Scheme::Http => 80,
+            Scheme::Https => 443,
         }
     }
 }
 
-impl Probe for HttpProbe {
-    fn name(&self) -> &'static str {
-        "http"
+/// Whether a banner starts with the SETTINGS frame an HTTP/2 server sends first
+pub(super) fn is_h2_banner(banner: &[u8]) -> bool {
+    banner.len() >= 9
+        && banner[3] == h2::FRAME_SETTINGS
+        && banner[4] & h2::FLAG_ACK == 0
+        && banner[5..9] == [0, 0, 0, 0]
+}
+
+/// Findings from the fields of a JSON document, as (JSON pointer, finding's key), for the
+/// probes of HTTP APIs
+pub(super) fn json_findings(json: &serde_json::Value, fields: &[(&str, &str)]) -> Vec<Finding> {
+    fields
+        .iter()
+        .filter_map(|(pointer, key)| match json.pointer(pointer)? {
+            serde_json::Value::String(value) if value.is_empty() => None,
+            serde_json::Value::String(value) => Some(Finding::new(*key, value.clone())),
+            serde_json::Value::Null => None,
+            value => Some(Finding::new(*key, value.to_string())),
+        })
+        .collect()
+}
+
+/// `<link>` tags, their `rel` attribute naming an icon and their `href` attribute
+fn link_regexes() -> &'static (Regex, Regex, Regex) {
+    static REGEXES: OnceLock<(Regex, Regex, Regex)> = OnceLock::new();
+    REGEXES.get_or_init(|| {
+        (
+            Regex::new(r"(?is)<link[^>]*>").expect("valid regex"),
+            Regex::new(r#"(?i)\brel\s*=\s*["']?[^"'>]*\bicon\b"#).expect("valid regex"),
+            Regex::new(r#"(?i)\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#)
+                .expect("valid regex"),
+        )
+    })
+}
+
+pub struct HttpProbe;
+
+impl HttpProbe {
+    fn search_title(body: &[u8]) -> Option<String> {
+        let body = String::from_utf8_lossy(body);
+        let lower = body.to_ascii_lowercase();
+        let start = lower.find("<title")?;
+        let start = start + lower[start..].find('>')? + 1;
+        let end = start + lower[start..].find("</title")?;
+        let title = body[start..end]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        Some(title)
     }
 
-    fn is_prefered_port(&self, port: u16) -> bool {
-        match port {
-            80 | 81 | 3128 | 8000 | 8080 => true,
-            _ => false,
+    /// Value of the `Host` header, also used to tell whether a redirect leaves the server
+    pub(super) fn authority(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+    ) -> String {
+        let host = match (server_name, peer_addr) {
+            (Some(name), _) => name.to_owned(),
+            (None, SocketAddr::V4(addr)) => addr.ip().to_string(),
+            (None, SocketAddr::V6(addr)) => format!("[{}]", addr.ip()),
+        };
+        if peer_addr.port() == scheme.default_port() {
+            host
+        } else {
+            format!("{}:{}", host, peer_addr.port())
         }
     }
 
-    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
-        Box::pin(async move {
-            const HTTP_PREFIX: &'static [u8] = b"HTTP/1.1 ";
+    /// Resolves a `Location` header against the current path, returns `None` when it leads
+    /// to another server.
+    fn resolve_location(
+        location: &str,
+        scheme: Scheme,
+        authority: &str,
+        path: &str,
+    ) -> Option<String> {
+        let location = location.split('#').next().unwrap_or_default();
+        let absolute = if let Some(rest) = location.strip_prefix("//") {
+            Some((scheme.name(), rest))
+        } else {
+            location.split_once("://").filter(|(s, _)| {
+                s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+            })
+        };
+
+        match absolute {
+            Some((s, rest)) => {
+                let (target, target_path) = match rest.find(['/', '?']) {
+                    Some(index) => (&rest[..index], &rest[index..]),
+                    None => (rest, "/"),
+                };
+                let default = format!(":{}", scheme.default_port());
+                let target = target.strip_suffix(default.as_str()).unwrap_or(target);
+                if !s.eq_ignore_ascii_case(scheme.name()) || !target.eq_ignore_ascii_case(authority)
+                {
+                    return None;
+                }
+                if target_path.starts_with('?') {
+                    Some(format!("/{}", target_path))
+                } else {
+                    Some(target_path.to_owned())
+                }
+            }
+            None if location.starts_with('/') => Some(location.to_owned()),
+            None => {
+                let base = path.split('?').next().unwrap_or_default();
+                let dir = &base[..base.rfind('/').map(|i| i + 1).unwrap_or(0)];
+                Some(format!(
+                    "{}{}",
+                    if dir.is_empty() { "/" } else { dir },
+                    location
+                ))
+            }
+        }
+    }
+
+    pub(super) async fn connect(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+        alpn: &[&str],
+    ) -> io::Result<Box<dyn Stream>> {
+        Ok(match scheme {
+            Scheme::Http => Box::new(TcpStream::connect(&peer_addr).await?),
+            Scheme::Https => Box::new(
+                TlsProbe::connect(peer_addr, server_name, alpn)
+                    .await
+                    .ok_or_else(|| io::Error::other("TLS handshake failed"))?,
+            ),
+        })
+    }
 
-            let mut stream = TcpStream::connect(&peer_addr).await?;
-            let request = format!(
-                "GET / HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: {}\r\nConnection: Close\r\n\r\n",
-                peer_addr.ip(),
-                peer_addr.port(),
-                unsafe { crate::defaults::USER_AGENT }
-            );
+    /// Sends a GET request over a new connection, returns `None` if the peer does not
+    /// speak HTTP
+    async fn get(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+        path: &str,
+    ) -> io::Result<Option<Response>> {
+        Self::request(peer_addr, scheme, server_name, "GET", path, None).await
+    }
+
+    /// Sends a request, with an optional JSON body, over a new connection within the read
+    /// timeout, returns `None` if the peer does not speak HTTP
+    pub(super) async fn request(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+        method: &str,
+        path: &str,
+        json: Option<&str>,
+    ) -> io::Result<Option<Response>> {
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: */*\r\nConnection: Close\r\n",
+            method,
+            path,
+            Self::authority(peer_addr, scheme, server_name),
+            unsafe { crate::defaults::USER_AGENT }
+        );
+        if let Some(json) = json {
+            request.push_str(&format!(
+                "Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                json.len(),
+                json
+            ));
+        } else {
+            request.push_str("\r\n");
+        }
 
+        let exchange = async {
+            let mut stream = Self::connect(peer_addr, scheme, server_name, &[]).await?;
             stream.write_all(request.as_bytes()).await?;
-            let mut buffer = [0u8; 8192];
+            Response::read(&mut stream).await
+        };
+        run_with_timeout(unsafe { READ_TIMEOUT }, exchange)
+            .await
+            .unwrap_or_else(|| Err(io::ErrorKind::TimedOut.into()))
+    }
 
-            let size = stream.read(&mut buffer[..]).await?;
-            let data = &buffer[..size];
+    /// Time `check_scheme` may take: each of its requests, for the page, the redirects
+    /// followed and the favicon, is bounded by the read timeout
+    pub(super) fn scheme_timeout() -> Duration {
+        let (read_timeout, max_redirects) = unsafe { (READ_TIMEOUT, HTTP_MAX_REDIRECTS) };
+        read_timeout * (max_redirects as u32 + 2)
+    }
 
-            if data.len() <= HTTP_PREFIX.len() + 10 {
-                return Ok(ProbeStatus::Unknown);
+    /// Sends a GET request in plain HTTP, then inside TLS if the server does not speak plain
+    /// HTTP, returns the scheme which worked along with the response
+    pub(super) async fn get_any_scheme(
+        peer_addr: SocketAddr,
+        server_name: Option<&str>,
+        path: &str,
+    ) -> io::Result<Option<(Scheme, Response)>> {
+        match Self::get(peer_addr, Scheme::Http, server_name, path).await {
+            Ok(Some(response))
+                if !String::from_utf8_lossy(&response.body[..])
+                    .contains(PLAIN_HTTP_TO_TLS_ANSWER) =>
+            {
+                return Ok(Some((Scheme::Http, response)));
             }
-            if !data.starts_with(HTTP_PREFIX) {
-                return Ok(ProbeStatus::Unknown);
+            _ => {}
+        }
+        Ok(Self::get(peer_addr, Scheme::Https, server_name, path)
+            .await?
+            .map(|response| (Scheme::Https, response)))
+    }
+
+    /// Findings of the interesting headers, the product of the service being the first
+    /// known one they name
+    fn header_findings(response: &Response) -> Vec<Finding> {
+        const INTERESTING_HEADERS: [&str; 2] = ["Server", "X-Powered-By"];
+        let mut findings = Vec::new();
+        let mut product = None;
+        for name in INTERESTING_HEADERS.iter() {
+            for value in response.headers_named(name) {
+                findings.push(Finding::new(*name, value));
+                if product.is_none() {
+                    product = cpe::header_findings(value);
+                }
             }
-            if !&data[HTTP_PREFIX.len()..][..3]
-                .iter()
-                .all(|c| c.is_ascii_digit())
-            {
-                return Ok(ProbeStatus::Unknown);
+        }
+        findings.extend(product.unwrap_or_default());
+
+        let cookies: Vec<&str> = response
+            .headers_named("Set-Cookie")
+            .filter_map(|cookie| cookie.split_once('='))
+            .map(|(name, _)| name.trim())
+            .collect();
+        if !cookies.is_empty() {
+            findings.push(Finding::new("cookies", cookies.join(", ")));
+        }
+
+        let (present, missing): (Vec<&str>, Vec<&str>) = SECURITY_HEADERS
+            .iter()
+            .partition(|name| response.header(name).is_some());
+        if !present.is_empty() {
+            findings.push(Finding::new("security headers present", present.join(", ")));
+        }
+        if !missing.is_empty() {
+            findings.push(Finding::new("security headers missing", missing.join(", ")));
+        }
+        findings
+    }
+
+    /// Path of the favicon, from a `<link rel="icon">` tag when it is served by the same host
+    fn favicon_path(
+        response: &Response,
+        scheme: Scheme,
+        authority: &str,
+        path: &str,
+    ) -> Option<String> {
+        let body = String::from_utf8_lossy(&response.body[..]);
+        let (link_re, rel_re, href_re) = link_regexes();
+
+        let href = link_re
+            .find_iter(&body)
+            .map(|m| m.as_str())
+            .filter(|tag| rel_re.is_match(tag))
+            .find_map(|tag| href_re.captures(tag))
+            .and_then(|c| c.get(1).or_else(|| c.get(2)).or_else(|| c.get(3)))
+            .map(|m| m.as_str().to_owned());
+
+        match href {
+            Some(href) if !href.starts_with("data:") => {
+                Self::resolve_location(&href, scheme, authority, path)
             }
-            let needle = &b"\r\n\r\n"[..];
-            let index = match data
-                .windows(needle.len())
-                .position(|window| window == needle)
-            {
-                Some(index) => index,
+            _ => Some("/favicon.ico".to_owned()),
+        }
+    }
+
+    /// Shodan-style favicon hash: MurmurHash3 of the base64 encoding, with a line feed
+    /// every 76 characters
+    fn favicon_hash(data: &[u8]) -> i32 {
+        let encoded = base64::encode(data);
+        let mut lines = String::with_capacity(encoded.len() + encoded.len() / 76 + 1);
+        for chunk in encoded.as_bytes().chunks(76) {
+            lines.push_str(std::str::from_utf8(chunk).expect("base64 is ASCII"));
+            lines.push('\n');
+        }
+        murmur3::murmur3_32(&mut Cursor::new(lines.as_bytes()), 0).expect("reading from memory")
+            as i32
+    }
+
+    /// Requests `/` over plain HTTP or inside a TLS session, follows redirects staying on
+    /// this server and returns the service found, named after the scheme (`HTTPS` inside
+    /// TLS), None when the peer does not speak HTTP. A redirect or the favicon timing out
+    /// leaves the findings gathered so far.
+    pub(super) async fn check_scheme(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+    ) -> io::Result<Option<Service>> {
+        let mut path = "/".to_owned();
+        let mut response = match Self::get(peer_addr, scheme, server_name, &path).await? {
+            Some(response) => response,
+            None => {
+                return Ok(None);
+            }
+        };
+
+        let mut findings = vec![Finding::new("status", response.status_line.clone())];
+
+        let authority = Self::authority(peer_addr, scheme, server_name);
+        let max_redirects = unsafe { HTTP_MAX_REDIRECTS };
+        let mut redirects = 0;
+        while matches!(response.status, 301 | 302 | 303 | 307 | 308) {
+            let location = match response.header("Location") {
+                Some(location) => location.to_owned(),
+                None => break,
+            };
+            let next = match Self::resolve_location(&location, scheme, &authority, &path) {
+                Some(next) if redirects < max_redirects => next,
+                Some(_) => {
+                    findings.push(Finding::new(
+                        "redirect",
+                        format!("{} (too many redirects)", location),
+                    ));
+                    break;
+                }
                 None => {
-                    return Ok(ProbeStatus::Unknown);
+                    findings.push(Finding::new(
+                        "redirect",
+                        format!("{} (other server)", location),
+                    ));
+                    break;
                 }
             };
-            let headers = match std::str::from_utf8(&data[..(index + needle.len())]) {
-                Ok(headers) => headers,
-                Err(_) => {
-                    return Ok(ProbeStatus::Unknown);
+            redirects += 1;
+            match Self::get(peer_addr, scheme, server_name, &next).await {
+                Ok(Some(next_response)) => {
+                    findings.push(Finding::new(
+                        "redirect",
+                        format!("{} -> {}", next, next_response.status_line),
+                    ));
+                    response = next_response;
+                    path = next;
                 }
-            };
+                _ => {
+                    findings.push(Finding::new("redirect", format!("{} (no answer)", next)));
+                    break;
+                }
+            }
+        }
 
-            println!("      Found protocol HTTP");
-            println!("      Interesting headers:");
-            Self::search_interesting_headers(headers);
+        if let Some(title) = Self::search_title(&response.body[..]) {
+            findings.push(Finding::new("title", title));
+        }
+        findings.extend(Self::header_findings(&response));
+
+        let technologies = tech::detect(unsafe { HTTP_TECHNOLOGIES }, &response);
+        if !technologies.is_empty() {
+            findings.push(Finding::new("technologies", technologies.join(", ")));
+        }
+
+        if let Some(favicon) = Self::favicon_path(&response, scheme, &authority, &path) {
+            if let Ok(Some(icon)) = Self::get(peer_addr, scheme, server_name, &favicon).await {
+                if icon.status == 200 && !icon.body.is_empty() {
+                    findings.push(Finding::new(
+                        "favicon",
+                        format!("{} (mmh3: {})", favicon, Self::favicon_hash(&icon.body[..])),
+                    ));
+                }
+            }
+        }
 
-            Ok(ProbeStatus::Recognized)
+        Ok(Some(Service::new(
+            scheme.name().to_ascii_uppercase(),
+            findings,
+        )))
+    }
+
+    /// Opens an HTTP/2 connection, with prior knowledge when in clear text (h2c) or
+    /// through ALPN inside TLS (h2), and returns the server's settings.
+    pub(super) async fn check_h2(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+    ) -> io::Result<Option<Service>> {
+        let handshake = async {
+            let mut stream = Self::connect(peer_addr, scheme, server_name, &["h2"]).await?;
+            h2::handshake(&mut stream).await
+        };
+        let settings = match run_with_timeout(unsafe { READ_TIMEOUT }, handshake)
+            .await
+            .unwrap_or_else(|| Err(io::ErrorKind::TimedOut.into()))?
+        {
+            Some(settings) => settings,
+            None => {
+                return Ok(None);
+            }
+        };
+
+        let findings = settings
+            .into_iter()
+            .map(|(id, value)| {
+                Finding::new(
+                    format!("SETTINGS_{}", h2::setting_name(id)),
+                    value.to_string(),
+                )
+            })
+            .collect();
+        let protocol = match scheme {
+            Scheme::Http => "h2c",
+            Scheme::Https => "h2",
+        };
+        Ok(Some(Service::new(protocol, findings)))
+    }
+
+    /// The service found over HTTP/1, telling whether HTTP/2 is also spoken, or the HTTP/2
+    /// one alone
+    pub(super) fn merge_h2(http1: Option<Service>, h2: Option<Service>) -> Option<Service> {
+        match (http1, h2) {
+            (Some(mut http1), Some(h2)) => {
+                http1.findings.push(Finding::new("http/2", h2.protocol));
+                http1.findings.extend(h2.findings);
+                Some(http1)
+            }
+            (http1, h2) => http1.or(h2),
+        }
+    }
+}
+
+impl Probe for HttpProbe {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 80 | 81 | 3128 | 8000 | 8080)
+    }
+
+    fn rarity(&self) -> u8 {
+        1
+    }
+
+    fn accepts_banner(&self, banner: &[u8]) -> bool {
+        is_h2_banner(banner)
+    }
+
+    fn timeout(&self) -> Duration {
+        let read_timeout = unsafe { READ_TIMEOUT };
+        Self::scheme_timeout() + read_timeout
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let server_name = unsafe { SERVER_NAMES }.first().map(|name| name.as_str());
+            let http1 = Self::check_scheme(peer_addr, Scheme::Http, server_name)
+                .await
+                .unwrap_or(None);
+            let h2c = Self::check_h2(peer_addr, Scheme::Http, server_name)
+                .await
+                .unwrap_or(None);
+            Ok(match Self::merge_h2(http1, h2c) {
+                Some(service) => ProbeStatus::Found(service),
+                None => ProbeStatus::Unknown,
+            })
         })
     }
 }
@@ -0,0 +1,321 @@
+//! Just enough HTTP/2 (RFC 9113) to open a connection, read the server's
+//! SETTINGS and make a few sequential requests.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+mod hpack;
+
+/// Connection preface sent by clients, before their first SETTINGS frame
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_DATA: u8 = 0x0;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_RST_STREAM: u8 = 0x3;
+pub const FRAME_SETTINGS: u8 = 0x4;
+const FRAME_PING: u8 = 0x6;
+const FRAME_GOAWAY: u8 = 0x7;
+const FRAME_WINDOW_UPDATE: u8 = 0x8;
+const FRAME_CONTINUATION: u8 = 0x9;
+
+pub const FLAG_ACK: u8 = 0x1;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+/// Upper bound on the body of a response we are willing to read
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Frames larger than the default SETTINGS_MAX_FRAME_SIZE are not expected before we
+/// raise it, which we never do
+const MAX_FRAME_SIZE: usize = 16384;
+
+#[derive(Debug)]
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) -> Self {
+        Self {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.payload.len() + 9);
+        data.extend_from_slice(&(self.payload.len() as u32).to_be_bytes()[1..]);
+        data.push(self.kind);
+        data.push(self.flags);
+        data.extend_from_slice(&(self.stream_id & 0x7fff_ffff).to_be_bytes());
+        data.extend_from_slice(&self.payload[..]);
+        data
+    }
+
+    pub async fn read<S>(stream: &mut S) -> io::Result<Self>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut header = [0u8; 9];
+        stream.read_exact(&mut header[..]).await?;
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        if length > MAX_FRAME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP/2 frame too large",
+            ));
+        }
+        let stream_id =
+            u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload[..]).await?;
+        Ok(Self::new(header[3], header[4], stream_id, payload))
+    }
+
+    /// Parameters of a SETTINGS frame, as (identifier, value)
+    pub fn settings(&self) -> Vec<(u16, u32)> {
+        self.payload
+            .chunks_exact(6)
+            .map(|c| {
+                (
+                    u16::from_be_bytes([c[0], c[1]]),
+                    u32::from_be_bytes([c[2], c[3], c[4], c[5]]),
+                )
+            })
+            .collect()
+    }
+}
+
+pub fn setting_name(id: u16) -> String {
+    match id {
+        0x1 => "HEADER_TABLE_SIZE".to_owned(),
+        0x2 => "ENABLE_PUSH".to_owned(),
+        0x3 => "MAX_CONCURRENT_STREAMS".to_owned(),
+        0x4 => "INITIAL_WINDOW_SIZE".to_owned(),
+        0x5 => "MAX_FRAME_SIZE".to_owned(),
+        0x6 => "MAX_HEADER_LIST_SIZE".to_owned(),
+        0x8 => "ENABLE_CONNECT_PROTOCOL".to_owned(),
+        0x9 => "NO_RFC7540_PRIORITIES".to_owned(),
+        _ => format!("0x{:04x}", id),
+    }
+}
+
+/// Sends the connection preface with an empty SETTINGS frame, returns the server's
+/// SETTINGS or `None` if it does not speak HTTP/2.
+///
+/// The server's SETTINGS are acknowledged, so the connection can be used afterwards.
+pub async fn handshake<S>(stream: &mut S) -> io::Result<Option<Vec<(u16, u32)>>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut data = PREFACE.to_vec();
+    data.extend_from_slice(&Frame::new(FRAME_SETTINGS, 0, 0, Vec::new()).to_bytes()[..]);
+    stream.write_all(&data[..]).await?;
+
+    // An HTTP/1 server answers with a status line, which never parses as a SETTINGS frame
+    let frame = match Frame::read(stream).await {
+        Ok(frame) => frame,
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if frame.kind != FRAME_SETTINGS
+        || frame.flags & FLAG_ACK != 0
+        || frame.stream_id != 0
+        || frame.payload.len() % 6 != 0
+    {
+        return Ok(None);
+    }
+
+    stream
+        .write_all(&Frame::new(FRAME_SETTINGS, FLAG_ACK, 0, Vec::new()).to_bytes()[..])
+        .await?;
+    Ok(Some(frame.settings()))
+}
+
+/// Answer to a request, trailers are merged into the headers
+#[derive(Debug, Default)]
+pub struct Response {
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// the stream was reset or the connection closed before the end of the response
+    pub reset: bool,
+}
+
+impl Response {
+    /// First header named `name` (always lower case in HTTP/2)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Client side of an HTTP/2 connection, making one request at a time
+pub struct Client<S> {
+    stream: S,
+    decoder: hpack::Decoder,
+    next_stream_id: u32,
+    /// the server sent GOAWAY
+    closed: bool,
+}
+
+/// Removes the padding and priority fields in front of a HEADERS or DATA payload
+fn frame_data(frame: &Frame) -> &[u8] {
+    let mut data = &frame.payload[..];
+    let mut padding = 0;
+    if frame.flags & FLAG_PADDED != 0 && !data.is_empty() {
+        padding = data[0] as usize;
+        data = &data[1..];
+    }
+    if frame.kind == FRAME_HEADERS && frame.flags & FLAG_PRIORITY != 0 {
+        data = data.get(5..).unwrap_or_default();
+    }
+    &data[..data.len().saturating_sub(padding)]
+}
+
+impl<S> Client<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Opens the connection, returns `None` if the server does not speak HTTP/2
+    pub async fn connect(mut stream: S) -> io::Result<Option<(Self, Vec<(u16, u32)>)>> {
+        let settings = match handshake(&mut stream).await? {
+            Some(settings) => settings,
+            None => return Ok(None),
+        };
+        let client = Self {
+            stream,
+            decoder: hpack::Decoder::new(),
+            next_stream_id: 1,
+            closed: false,
+        };
+        Ok(Some((client, settings)))
+    }
+
+    async fn write_frame(&mut self, frame: Frame) -> io::Result<()> {
+        self.stream.write_all(&frame.to_bytes()[..]).await
+    }
+
+    /// Sends a request on a new stream and waits for the whole response.
+    ///
+    /// `headers` must start with the pseudo-headers.
+    pub async fn request(&mut self, headers: &[(&str, &str)], body: &[u8]) -> io::Result<Response> {
+        if self.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "HTTP/2 connection closed by the server",
+            ));
+        }
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 2;
+
+        let block = hpack::encode(headers);
+        let flags = if body.is_empty() {
+            FLAG_END_HEADERS | FLAG_END_STREAM
+        } else {
+            FLAG_END_HEADERS
+        };
+        self.write_frame(Frame::new(FRAME_HEADERS, flags, stream_id, block))
+            .await?;
+        let mut chunks = body.chunks(MAX_FRAME_SIZE).peekable();
+        while let Some(chunk) = chunks.next() {
+            let flags = if chunks.peek().is_none() {
+                FLAG_END_STREAM
+            } else {
+                0
+            };
+            self.write_frame(Frame::new(FRAME_DATA, flags, stream_id, chunk.to_vec()))
+                .await?;
+        }
+
+        let mut response = Response::default();
+        let mut block = Vec::new();
+        let mut end_stream = false;
+        loop {
+            let frame = match Frame::read(&mut self.stream).await {
+                Ok(frame) => frame,
+                Err(_) if !response.headers.is_empty() => {
+                    response.reset = true;
+                    return Ok(response);
+                }
+                Err(e) => return Err(e),
+            };
+            match frame.kind {
+                FRAME_SETTINGS if frame.flags & FLAG_ACK == 0 => {
+                    self.write_frame(Frame::new(FRAME_SETTINGS, FLAG_ACK, 0, Vec::new()))
+                        .await?;
+                }
+                FRAME_PING if frame.flags & FLAG_ACK == 0 => {
+                    self.write_frame(Frame::new(FRAME_PING, FLAG_ACK, 0, frame.payload))
+                        .await?;
+                }
+                FRAME_GOAWAY => {
+                    self.closed = true;
+                    let last_stream_id = frame
+                        .payload
+                        .get(..4)
+                        .map(|id| u32::from_be_bytes([id[0], id[1], id[2], id[3]]) & 0x7fff_ffff)
+                        .unwrap_or_default();
+                    if last_stream_id < stream_id {
+                        response.reset = true;
+                        return Ok(response);
+                    }
+                }
+                _ if frame.stream_id != stream_id => {}
+                FRAME_HEADERS | FRAME_CONTINUATION => {
+                    // END_STREAM is carried by HEADERS, even when followed by CONTINUATION
+                    if frame.kind == FRAME_HEADERS {
+                        end_stream = frame.flags & FLAG_END_STREAM != 0;
+                    }
+                    block.extend_from_slice(frame_data(&frame));
+                    if frame.flags & FLAG_END_HEADERS != 0 {
+                        let headers = self.decoder.decode(&block[..])?;
+                        block.clear();
+                        response.headers.extend(headers.into_iter().map(|(n, v)| {
+                            (
+                                String::from_utf8_lossy(&n[..]).into_owned(),
+                                String::from_utf8_lossy(&v[..]).into_owned(),
+                            )
+                        }));
+                    }
+                    if end_stream && frame.flags & FLAG_END_HEADERS != 0 {
+                        return Ok(response);
+                    }
+                }
+                FRAME_DATA => {
+                    let length = frame.payload.len() as u32;
+                    response.body.extend_from_slice(frame_data(&frame));
+                    if frame.flags & FLAG_END_STREAM != 0 {
+                        return Ok(response);
+                    }
+                    if response.body.len() > MAX_BODY_SIZE {
+                        response.reset = true;
+                        return Ok(response);
+                    }
+                    // Give back the flow control window we used
+                    if length > 0 {
+                        let increment = length.to_be_bytes().to_vec();
+                        self.write_frame(Frame::new(FRAME_WINDOW_UPDATE, 0, 0, increment.clone()))
+                            .await?;
+                        self.write_frame(Frame::new(FRAME_WINDOW_UPDATE, 0, stream_id, increment))
+                            .await?;
+                    }
+                }
+                FRAME_RST_STREAM => {
+                    response.reset = true;
+                    return Ok(response);
+                }
+                _ => {}
+            }
+        }
+    }
+}
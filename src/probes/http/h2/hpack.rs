@@ -0,0 +1,639 @@
+//! Just enough HPACK (RFC 7541), the header compression of HTTP/2: a decoder checking every
+//! integer, index and length the server sends, and an encoder sending plain literals.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::OnceLock;
+
+/// A header field, as (name, value)
+pub type Header = (Vec<u8>, Vec<u8>);
+
+/// Size of the dynamic table, the default SETTINGS_HEADER_TABLE_SIZE we never change
+const MAX_TABLE_SIZE: usize = 4096;
+
+/// Upper bound on the decoded headers of a block, as counted by SETTINGS_MAX_HEADER_LIST_SIZE:
+/// a small block can reference the same large entry of the dynamic table many times
+const MAX_HEADER_LIST_SIZE: usize = 256 * 1024;
+
+/// Overhead counted for each entry of the dynamic table and header list
+const ENTRY_OVERHEAD: usize = 32;
+
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Huffman code of each symbol, as (code, length in bits), the last one being EOS
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+const EOS: u16 = 256;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn truncated() -> io::Error {
+    invalid("Truncated HPACK block")
+}
+
+/// Canonical Huffman decoding tables: the symbols ordered by code, and for each length the
+/// first code of this length, the number of them and the position of the first one
+struct Huffman {
+    symbols: Vec<u16>,
+    first_code: [u32; 31],
+    count: [u32; 31],
+    offset: [usize; 31],
+}
+
+fn huffman() -> &'static Huffman {
+    static HUFFMAN: OnceLock<Huffman> = OnceLock::new();
+    HUFFMAN.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..HUFFMAN_CODES.len() as u16).collect();
+        symbols.sort_by_key(|&s| {
+            let (code, length) = HUFFMAN_CODES[s as usize];
+            (length, code)
+        });
+        let mut huffman = Huffman {
+            symbols,
+            first_code: [0; 31],
+            count: [0; 31],
+            offset: [0; 31],
+        };
+        for (position, &symbol) in huffman.symbols.iter().enumerate() {
+            let (code, length) = HUFFMAN_CODES[symbol as usize];
+            let length = length as usize;
+            if huffman.count[length] == 0 {
+                huffman.first_code[length] = code;
+                huffman.offset[length] = position;
+            }
+            huffman.count[length] += 1;
+        }
+        huffman
+    })
+}
+
+fn huffman_decode(data: &[u8]) -> io::Result<Vec<u8>> {
+    let huffman = huffman();
+    let mut decoded = Vec::with_capacity(data.len() * 8 / 5);
+    let (mut code, mut length) = (0u32, 0usize);
+    for byte in data {
+        for shift in (0..8).rev() {
+            code = (code << 1) | u32::from((byte >> shift) & 1);
+            length += 1;
+            if length >= huffman.count.len() {
+                return Err(invalid("Invalid Huffman code"));
+            }
+            let index = code.wrapping_sub(huffman.first_code[length]);
+            if huffman.count[length] > 0 && index < huffman.count[length] {
+                match huffman.symbols[huffman.offset[length] + index as usize] {
+                    EOS => return Err(invalid("Huffman EOS in a string")),
+                    symbol => decoded.push(symbol as u8),
+                }
+                code = 0;
+                length = 0;
+            }
+        }
+    }
+    // padding is the start of EOS, all ones and shorter than a byte
+    if length >= 8 || code != (1 << length) - 1 {
+        return Err(invalid("Invalid Huffman padding"));
+    }
+    Ok(decoded)
+}
+
+/// Reads an integer with a prefix of `prefix` bits, rejecting those beyond 2^28
+fn decode_integer(data: &[u8], position: &mut usize, prefix: u8) -> io::Result<usize> {
+    let mask = (1usize << prefix) - 1;
+    let first = *data.get(*position).ok_or_else(truncated)?;
+    *position += 1;
+    let mut value = first as usize & mask;
+    if value < mask {
+        return Ok(value);
+    }
+    for shift in (0..=21).step_by(7) {
+        let byte = *data.get(*position).ok_or_else(truncated)?;
+        *position += 1;
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("HPACK integer too large"))
+}
+
+fn decode_string(data: &[u8], position: &mut usize) -> io::Result<Vec<u8>> {
+    let huffman = data.get(*position).ok_or_else(truncated)? & 0x80 != 0;
+    let length = decode_integer(data, position, 7)?;
+    let end = position.checked_add(length).ok_or_else(truncated)?;
+    let string = data.get(*position..end).ok_or_else(truncated)?;
+    *position = end;
+    if huffman {
+        huffman_decode(string)
+    } else {
+        Ok(string.to_vec())
+    }
+}
+
+/// Decoder of the header blocks of a connection, keeping its dynamic table
+pub struct Decoder {
+    /// Most recent entries first
+    table: VecDeque<Header>,
+    table_size: usize,
+    max_table_size: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            table: VecDeque::new(),
+            table_size: 0,
+            max_table_size: MAX_TABLE_SIZE,
+        }
+    }
+
+    fn entry(&self, index: usize) -> io::Result<Header> {
+        match index {
+            0 => Err(invalid("HPACK index 0")),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            }
+            _ => self
+                .table
+                .get(index - 62)
+                .cloned()
+                .ok_or_else(|| invalid("HPACK index out of the table")),
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.table_size > self.max_table_size {
+            match self.table.pop_back() {
+                Some((name, value)) => {
+                    self.table_size -= name.len() + value.len() + ENTRY_OVERHEAD;
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn insert(&mut self, header: Header) {
+        self.table_size += header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+        self.table.push_front(header);
+        // an entry larger than the table empties it, itself included
+        self.evict();
+    }
+
+    /// Decodes a header block, the dynamic table updated along
+    pub fn decode(&mut self, block: &[u8]) -> io::Result<Vec<Header>> {
+        let mut headers = Vec::new();
+        let mut list_size = 0usize;
+        let mut position = 0;
+        while let Some(&first) = block.get(position) {
+            let header = if first & 0x80 != 0 {
+                // Indexed header field
+                let index = decode_integer(block, &mut position, 7)?;
+                self.entry(index)?
+            } else if first & 0xe0 == 0x20 {
+                // Dynamic table size update
+                let size = decode_integer(block, &mut position, 5)?;
+                if size > MAX_TABLE_SIZE {
+                    return Err(invalid("HPACK table size above our setting"));
+                }
+                self.max_table_size = size;
+                self.evict();
+                continue;
+            } else {
+                // Literal header field, with incremental indexing (6 bits prefix) or not (4)
+                let indexing = first & 0x40 != 0;
+                let index = decode_integer(block, &mut position, if indexing { 6 } else { 4 })?;
+                let name = match index {
+                    0 => decode_string(block, &mut position)?,
+                    index => self.entry(index)?.0,
+                };
+                let value = decode_string(block, &mut position)?;
+                if indexing {
+                    self.insert((name.clone(), value.clone()));
+                }
+                (name, value)
+            };
+            list_size += header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+            if list_size > MAX_HEADER_LIST_SIZE {
+                return Err(invalid("HPACK header list too large"));
+            }
+            headers.push(header);
+        }
+        Ok(headers)
+    }
+}
+
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix: u8, value: usize) {
+    let mask = (1usize << prefix) - 1;
+    if value < mask {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | mask as u8);
+    let mut value = value - mask;
+    while value >= 0x80 {
+        block.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+/// Encodes headers as literals without indexing, neither compressed nor left in the
+/// server's dynamic table
+pub fn encode(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        block.push(0x00);
+        for string in [name, value] {
+            encode_integer(&mut block, 0x00, 7, string.len());
+            block.extend_from_slice(string.as_bytes());
+        }
+    }
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> Vec<Header> {
+        pairs
+            .iter()
+            .map(|(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_huffman_requests_with_the_dynamic_table() {
+        // RFC 7541, C.4.1 and C.4.2
+        let mut decoder = Decoder::new();
+        let first = b"\x82\x86\x84\x41\x8c\xf1\xe3\xc2\xe5\xf2\x3a\x6b\xa0\xab\x90\xf4\xff";
+        assert_eq!(
+            decoder.decode(first).unwrap(),
+            headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+        let second = b"\x82\x86\x84\xbe\x58\x86\xa8\xeb\x10\x64\x9c\xbf";
+        assert_eq!(
+            decoder.decode(second).unwrap(),
+            headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        for block in [
+            &b"\x3f"[..],                     // truncated table size update
+            &b"\x3f\xe2\x1f"[..],             // table size above our setting
+            &b"\x80"[..],                     // index 0
+            &b"\xbe"[..],                     // index beyond the empty dynamic table
+            &b"\xff\xff\xff\xff\xff\x0f"[..], // integer overflow
+            &b"\x40\x85abc"[..],              // string longer than the block
+            &b"\x40\x81\xfe\x00"[..],         // invalid Huffman padding
+        ] {
+            assert!(Decoder::new().decode(block).is_err(), "{:02x?}", block);
+        }
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let long = "x".repeat(200);
+        let pairs = [(":path", "/"), ("user-agent", long.as_str())];
+        let block = encode(&pairs[..]);
+        assert_eq!(
+            Decoder::new().decode(&block[..]).unwrap(),
+            headers(&pairs[..])
+        );
+    }
+}
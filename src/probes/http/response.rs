@@ -0,0 +1,251 @@
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::utils::run_with_timeout;
+
+/// Upper bound on the size of a response we are willing to read
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// Upper bound on the size of the status line and headers
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Servers ignoring `Connection: close` would otherwise stall us until the probe times out
+const IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub struct Response {
+    pub status_line: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// Body, with the transfer encoding removed
+    pub body: Vec<u8>,
+}
+
+enum BodyLength {
+    Chunked,
+    Fixed(usize),
+    UntilClose,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Removes the chunked transfer encoding, keeping what could be decoded of a truncated body
+fn decode_chunked(mut data: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(data.len());
+    while let Some(line_end) = find(data, b"\r\n") {
+        let size = std::str::from_utf8(&data[..line_end])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok());
+        let size = match size {
+            Some(0) | None => break,
+            Some(size) => size,
+        };
+        data = &data[line_end + 2..];
+        if data.len() < size {
+            body.extend_from_slice(data);
+            break;
+        }
+        body.extend_from_slice(&data[..size]);
+        data = data.get(size + 2..).unwrap_or_default();
+    }
+    body
+}
+
+fn is_chunk_complete(data: &[u8]) -> bool {
+    data.ends_with(b"\r\n0\r\n\r\n") || data == b"0\r\n\r\n"
+}
+
+impl Response {
+    /// First header named `name`, case-insensitively
+    pub fn header<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        self.headers_named(name).next()
+    }
+
+    /// Every header named `name`, case-insensitively
+    pub fn headers_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Body parsed as JSON, whatever the announced content type
+    pub fn json(&self) -> Option<serde_json::Value> {
+        serde_json::from_slice(&self.body[..]).ok()
+    }
+
+    fn parse_head(head: &str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let status_line = lines.next()?;
+        let mut parts = status_line.splitn(3, ' ');
+        if !parts.next()?.starts_with("HTTP/1.") {
+            return None;
+        }
+        let status = parts.next()?;
+        if status.len() != 3 {
+            return None;
+        }
+        let status = status.parse().ok()?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_owned(), value.trim().to_owned()))
+            .collect();
+
+        Some(Self {
+            status_line: status_line.to_owned(),
+            status,
+            headers,
+            body: Vec::new(),
+        })
+    }
+
+    fn body_length(&self) -> BodyLength {
+        if self.status / 100 == 1 || self.status == 204 || self.status == 304 {
+            return BodyLength::Fixed(0);
+        }
+        if let Some(encoding) = self.header("Transfer-Encoding") {
+            if encoding.to_ascii_lowercase().contains("chunked") {
+                return BodyLength::Chunked;
+            }
+        }
+        match self.header("Content-Length").and_then(|l| l.parse().ok()) {
+            Some(length) => BodyLength::Fixed(length),
+            None => BodyLength::UntilClose,
+        }
+    }
+
+    /// Reads a whole response from `stream`, returns `None` if the peer does not speak HTTP
+    pub async fn read<S>(stream: &mut S) -> io::Result<Option<Self>>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut data = Vec::with_capacity(8192);
+
+        // Status line and headers
+        let head_end = loop {
+            if let Some(index) = find(&data[..], b"\r\n\r\n") {
+                break index;
+            }
+            if data.len() > MAX_HEAD_SIZE {
+                return Ok(None);
+            }
+            if data.len() >= 7 && !data.starts_with(b"HTTP/1.") {
+                return Ok(None);
+            }
+            if stream.read_buf(&mut data).await? == 0 {
+                return Ok(None);
+            }
+        };
+        let mut response = match std::str::from_utf8(&data[..head_end])
+            .ok()
+            .and_then(Self::parse_head)
+        {
+            Some(response) => response,
+            None => {
+                return Ok(None);
+            }
+        };
+        data.drain(..head_end + 4);
+
+        // Body, stopping at the first error as we already know it is HTTP
+        let length = response.body_length();
+        loop {
+            let complete = match length {
+                BodyLength::Chunked => is_chunk_complete(&data[..]),
+                BodyLength::Fixed(length) => data.len() >= length,
+                BodyLength::UntilClose => false,
+            };
+            if complete || data.len() >= MAX_RESPONSE_SIZE {
+                break;
+            }
+            match run_with_timeout(IDLE_TIMEOUT, stream.read_buf(&mut data)).await {
+                Some(Ok(n)) if n > 0 => {}
+                _ => break,
+            }
+        }
+        data.truncate(MAX_RESPONSE_SIZE);
+
+        response.body = match length {
+            BodyLength::Chunked => decode_chunked(&data[..]),
+            BodyLength::Fixed(length) => {
+                data.truncate(length);
+                data
+            }
+            BodyLength::UntilClose => data,
+        };
+
+        Ok(Some(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(data: &[u8]) -> Option<Response> {
+        let mut stream = data;
+        Response::read(&mut stream).await.unwrap()
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        assert_eq!(
+            decode_chunked(b"5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n"),
+            b"hello, world"
+        );
+    }
+
+    #[test]
+    fn keeps_what_precedes_broken_chunks() {
+        // Truncated in the middle of a chunk, of its size line, or right after it
+        assert_eq!(decode_chunked(b"5\r\nhello\r\n7\r\n, wo"), b"hello, wo");
+        assert_eq!(decode_chunked(b"5\r\nhello\r\n7"), b"hello");
+        assert_eq!(decode_chunked(b"5\r\nhello"), b"hello");
+        // Sizes too large for memory or for a number
+        assert_eq!(
+            decode_chunked(b"5\r\nhello\r\nffffffffffff\r\nab"),
+            b"helloab"
+        );
+        assert_eq!(
+            decode_chunked(b"5\r\nhello\r\nffffffffffffffffffffffff\r\nab"),
+            b"hello"
+        );
+        assert_eq!(decode_chunked(b"zz\r\nhello\r\n"), b"");
+    }
+
+    #[tokio::test]
+    async fn reads_truncated_chunked_responses() {
+        let response =
+            read(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n3\r\nw")
+                .await
+                .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"hellow");
+    }
+
+    #[tokio::test]
+    async fn bounds_oversized_responses() {
+        let mut head = b"HTTP/1.1 200 OK\r\nX-Padding: ".to_vec();
+        head.resize(MAX_HEAD_SIZE + 1024, b'a');
+        assert!(read(&head[..]).await.is_none());
+
+        let mut data = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        let chunk = vec![b'a'; 0x10000];
+        while data.len() <= MAX_RESPONSE_SIZE + chunk.len() {
+            data.extend_from_slice(b"10000\r\n");
+            data.extend_from_slice(&chunk[..]);
+            data.extend_from_slice(b"\r\n");
+        }
+        data.extend_from_slice(b"0\r\n\r\n");
+        let response = read(&data[..]).await.unwrap();
+        assert!(response.body.len() <= MAX_RESPONSE_SIZE);
+        assert!(response.body.len() >= MAX_RESPONSE_SIZE - chunk.len());
+    }
+}
@@ -0,0 +1,260 @@
+//! Technology detection driven by Wappalyzer-style rules.
+//!
+//! Only the `headers`, `cookies`, `html`, `scriptSrc` (or `scripts`), `meta`
+//! and `implies` fields are used. Patterns the `regex` crate cannot compile
+//! (look-arounds, back-references) are skipped.
+
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde_json::Value;
+
+use super::response::Response;
+
+struct Pattern {
+    regex: Regex,
+    /// `\1`-style template for the version
+    version: Option<String>,
+}
+
+/// A technology and the patterns identifying it
+pub struct Technology {
+    name: String,
+    headers: Vec<(String, Pattern)>,
+    cookies: Vec<(String, Pattern)>,
+    html: Vec<Pattern>,
+    scripts: Vec<Pattern>,
+    meta: Vec<(String, Pattern)>,
+    implies: Vec<String>,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        let mut parts = pattern.split("\\;");
+        let regex = Regex::new(&format!("(?i){}", parts.next().unwrap_or_default())).ok()?;
+        let version = parts
+            .filter_map(|tag| tag.strip_prefix("version:"))
+            .next()
+            .map(|v| v.to_owned());
+        Some(Self { regex, version })
+    }
+
+    /// Returns the version found (possibly empty) when the pattern matches
+    fn matches(&self, text: &str) -> Option<String> {
+        let captures = self.regex.captures(text)?;
+        let mut version = self.version.clone().unwrap_or_default();
+        for i in (1..captures.len()).rev() {
+            let group = captures.get(i).map(|m| m.as_str()).unwrap_or_default();
+            version = version.replace(&format!("\\{}", i), group);
+        }
+        Some(version)
+    }
+}
+
+fn patterns(value: Option<&Value>) -> Vec<Pattern> {
+    match value {
+        Some(Value::String(s)) => Pattern::parse(s).into_iter().collect(),
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(|v| v.as_str())
+            .filter_map(Pattern::parse)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn named_patterns(value: Option<&Value>) -> Vec<(String, Pattern)> {
+    match value {
+        Some(Value::Object(map)) => map
+            .iter()
+            .flat_map(|(name, value)| {
+                patterns(Some(value))
+                    .into_iter()
+                    .map(move |p| (name.to_ascii_lowercase(), p))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::String(s)) => vec![s.split("\\;").next().unwrap_or_default().to_owned()],
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|s| s.split("\\;").next().unwrap_or_default().to_owned())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Loads rules from a JSON file, either a Wappalyzer `technologies.json`
+/// (`{"technologies": {...}}`, or `{"apps": {...}}` for older ones) or directly a map of
+/// technologies.
+pub fn load(path: &Path) -> io::Result<Vec<Technology>> {
+    let content = std::fs::read_to_string(path)?;
+    let root: Value = serde_json::from_str(&content)?;
+    let technologies = root
+        .get("technologies")
+        .or_else(|| root.get("apps"))
+        .unwrap_or(&root)
+        .as_object()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Expected a JSON object"))?;
+
+    Ok(technologies
+        .iter()
+        .map(|(name, rules)| Technology {
+            name: name.clone(),
+            headers: named_patterns(rules.get("headers")),
+            cookies: named_patterns(rules.get("cookies")),
+            html: patterns(rules.get("html")),
+            scripts: patterns(rules.get("scriptSrc").or_else(|| rules.get("scripts"))),
+            meta: named_patterns(rules.get("meta")),
+            implies: strings(rules.get("implies")),
+        })
+        .collect())
+}
+
+/// `<script>` tags and their `src` attribute
+fn script_regexes() -> &'static (Regex, Regex) {
+    static REGEXES: OnceLock<(Regex, Regex)> = OnceLock::new();
+    REGEXES.get_or_init(|| {
+        (
+            Regex::new(r"(?is)<script[^>]*>").expect("valid regex"),
+            Regex::new(r#"(?i)\bsrc\s*=\s*(["'])?([^"'\s>]+)"#).expect("valid regex"),
+        )
+    })
+}
+
+/// `<meta>` tags, their `name` (or `property`) attribute and their `content` attribute
+fn meta_regexes() -> &'static (Regex, Regex, Regex) {
+    static REGEXES: OnceLock<(Regex, Regex, Regex)> = OnceLock::new();
+    REGEXES.get_or_init(|| {
+        (
+            Regex::new(r"(?is)<meta[^>]*>").expect("valid regex"),
+            Regex::new(r#"(?i)\b(?:name|property)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+                .expect("valid regex"),
+            Regex::new(r#"(?i)\bcontent\s*=\s*(?:"([^"]*)"|'([^']*)')"#).expect("valid regex"),
+        )
+    })
+}
+
+/// What the rules are matched against
+struct Page {
+    headers: Vec<(String, String)>,
+    cookies: Vec<(String, String)>,
+    html: String,
+    scripts: Vec<String>,
+    meta: Vec<(String, String)>,
+}
+
+impl Page {
+    fn new(response: &Response) -> Self {
+        let html = String::from_utf8_lossy(&response.body[..]).into_owned();
+        let cookies = response
+            .headers_named("Set-Cookie")
+            .filter_map(|c| c.split(';').next())
+            .filter_map(|c| c.split_once('='))
+            .map(|(n, v)| (n.trim().to_ascii_lowercase(), v.trim().to_owned()))
+            .collect();
+
+        let (script_re, src_re) = script_regexes();
+        let scripts = script_re
+            .find_iter(&html)
+            .filter_map(|m| src_re.captures(m.as_str()))
+            .filter_map(|c| c.get(2).map(|m| m.as_str().to_owned()))
+            .collect();
+
+        let (meta_re, name_re, content_re) = meta_regexes();
+        let meta = meta_re
+            .find_iter(&html)
+            .filter_map(|m| {
+                let tag = m.as_str();
+                let name = name_re
+                    .captures(tag)
+                    .and_then(|c| c.get(1).or_else(|| c.get(2)))?;
+                let content = content_re
+                    .captures(tag)
+                    .and_then(|c| c.get(1).or_else(|| c.get(2)))?;
+                Some((
+                    name.as_str().to_ascii_lowercase(),
+                    content.as_str().to_owned(),
+                ))
+            })
+            .collect();
+
+        Self {
+            headers: response
+                .headers
+                .iter()
+                .map(|(n, v)| (n.to_ascii_lowercase(), v.clone()))
+                .collect(),
+            cookies,
+            html,
+            scripts,
+            meta,
+        }
+    }
+}
+
+fn match_named(rules: &[(String, Pattern)], values: &[(String, String)]) -> Option<String> {
+    rules.iter().find_map(|(name, pattern)| {
+        values
+            .iter()
+            .filter(|(n, _)| n == name)
+            .find_map(|(_, v)| pattern.matches(v))
+    })
+}
+
+impl Technology {
+    fn detect(&self, page: &Page) -> Option<String> {
+        match_named(&self.headers[..], &page.headers[..])
+            .or_else(|| match_named(&self.cookies[..], &page.cookies[..]))
+            .or_else(|| match_named(&self.meta[..], &page.meta[..]))
+            .or_else(|| self.html.iter().find_map(|p| p.matches(&page.html)))
+            .or_else(|| {
+                self.scripts
+                    .iter()
+                    .find_map(|p| page.scripts.iter().find_map(|s| p.matches(s)))
+            })
+    }
+}
+
+/// Returns the technologies found, as "name" or "name version"
+pub fn detect(technologies: &[Technology], response: &Response) -> Vec<String> {
+    let page = Page::new(response);
+    let mut found: Vec<(String, String)> = Vec::new();
+    for technology in technologies {
+        if let Some(version) = technology.detect(&page) {
+            found.push((technology.name.clone(), version));
+        }
+    }
+
+    // Add implied technologies, which may imply others
+    let mut i = 0;
+    while i < found.len() {
+        let name = found[i].0.clone();
+        if let Some(technology) = technologies.iter().find(|t| t.name == name) {
+            for implied in &technology.implies {
+                if !found.iter().any(|(n, _)| n == implied) {
+                    found.push((implied.clone(), String::new()));
+                }
+            }
+        }
+        i += 1;
+    }
+
+    found
+        .into_iter()
+        .map(|(name, version)| {
+            if version.is_empty() {
+                name
+            } else {
+                format!("{} {}", name, version)
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,143 @@
+//! Kafka brokers answer an ApiVersions request (version 0, understood by every broker
+//! since 0.10.0) with the APIs they implement, which dates their release.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const API_PRODUCE: i16 = 0;
+const API_FETCH: i16 = 1;
+const API_VERSIONS: i16 = 18;
+
+const CLIENT_ID: &str = "port-scanner";
+
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// An API introduced by each release, a broker implementing it is at least that version
+const RELEASE_APIS: [(i16, &str); 12] = [
+    (18, "0.10.0"),
+    (20, "0.10.1"),
+    (33, "0.11.0"),
+    (37, "1.0"),
+    (42, "1.1"),
+    (43, "2.2"),
+    (44, "2.3"),
+    (47, "2.4"),
+    (49, "2.6"),
+    (57, "2.7"),
+    (61, "2.8"),
+    (67, "3.0"),
+];
+
+pub struct KafkaProbe;
+
+/// Versions of an API implemented by the broker
+struct ApiVersion {
+    key: i16,
+    min: i16,
+    max: i16,
+}
+
+fn api_versions_request(correlation_id: i32) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&API_VERSIONS.to_be_bytes());
+    body.extend_from_slice(&0i16.to_be_bytes());
+    body.extend_from_slice(&correlation_id.to_be_bytes());
+    body.extend_from_slice(&(CLIENT_ID.len() as i16).to_be_bytes());
+    body.extend_from_slice(CLIENT_ID.as_bytes());
+
+    let mut request = (body.len() as i32).to_be_bytes().to_vec();
+    request.extend_from_slice(&body[..]);
+    request
+}
+
+/// Reads an ApiVersions response (version 0) as its error code and the APIs it lists
+fn api_versions(data: &[u8]) -> Option<(i16, Vec<ApiVersion>)> {
+    let i16_at = |index: usize| {
+        data.get(index..index + 2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]))
+    };
+    let error_code = i16_at(0)?;
+    let count = data.get(2..6)?;
+    let count = u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize;
+    let mut apis = Vec::new();
+    for index in 0..count {
+        let offset = 6 + index * 6;
+        apis.push(ApiVersion {
+            key: i16_at(offset)?,
+            min: i16_at(offset + 2)?,
+            max: i16_at(offset + 4)?,
+        });
+    }
+    Some((error_code, apis))
+}
+
+/// Release range of a broker, from the APIs it implements
+fn release_range(apis: &[ApiVersion]) -> Option<String> {
+    let has_api = |key: i16| apis.iter().any(|api| api.key == key);
+    let newest = RELEASE_APIS.iter().rposition(|(key, _)| has_api(*key))?;
+    Some(match RELEASE_APIS.get(newest + 1) {
+        Some((_, next)) => format!(">= {}, < {}", RELEASE_APIS[newest].1, next),
+        None => format!(">= {}", RELEASE_APIS[newest].1),
+    })
+}
+
+impl Probe for KafkaProbe {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 9092 | 9093)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            let correlation_id = rand::random::<i32>() & 0x7fff_ffff;
+            stream
+                .write_all(&api_versions_request(correlation_id)[..])
+                .await?;
+
+            let mut header = [0u8; 8];
+            stream.read_exact(&mut header[..]).await?;
+            let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            let response_id = i32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+            if response_id != correlation_id || !(4..=MAX_RESPONSE_SIZE).contains(&size) {
+                return Ok(ProbeStatus::Unknown);
+            }
+            let mut body = vec![0u8; size - 4];
+            stream.read_exact(&mut body[..]).await?;
+            let (error_code, apis) = match api_versions(&body[..]) {
+                Some(response) => response,
+                None => return Ok(ProbeStatus::Unknown),
+            };
+
+            let mut findings = Vec::new();
+            if error_code != 0 {
+                findings.push(Finding::new("error code", error_code.to_string()));
+            }
+            if let Some(range) = release_range(&apis) {
+                findings.push(Finding::new("version", range));
+            }
+            findings.push(Finding::new("apis", apis.len().to_string()));
+            for (key, name) in [
+                (API_PRODUCE, "Produce"),
+                (API_FETCH, "Fetch"),
+                (API_VERSIONS, "ApiVersions"),
+            ] {
+                if let Some(api) = apis.iter().find(|api| api.key == key) {
+                    findings.push(Finding::new(
+                        format!("{} versions", name),
+                        format!("{} to {}", api.min, api.max),
+                    ));
+                }
+            }
+
+            Ok(ProbeStatus::Found(Service::new("Kafka", findings)))
+        })
+    }
+}
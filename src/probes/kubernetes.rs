@@ -0,0 +1,158 @@
+//! Kubernetes API servers tell their version to anonymous clients, kubelets only answer
+//! about their pods, both grant anonymous requests the rights given to
+//! `system:anonymous` when anonymous authentication is enabled.
+
+use std::net::SocketAddr;
+
+use super::{
+    http::{self, HttpProbe, Response, Scheme},
+    Finding, Probe, ProbeCheckFuture, ProbeStatus, Service, Severity,
+};
+use crate::defaults::SERVER_NAMES;
+
+/// Fields of the API server's `/version` worth reporting
+const VERSION_FIELDS: [(&str, &str); 4] = [
+    ("/gitVersion", "version"),
+    ("/platform", "platform"),
+    ("/goVersion", "go version"),
+    ("/buildDate", "build date"),
+];
+
+const KUBELET_PORT: u16 = 10250;
+const KUBELET_READ_ONLY_PORT: u16 = 10255;
+
+pub struct KubernetesProbe;
+
+/// Whether a response is a `Status` object, the errors of the API server
+fn is_status(response: &Response) -> bool {
+    response
+        .json()
+        .is_some_and(|json| json.get("kind").and_then(|kind| kind.as_str()) == Some("Status"))
+}
+
+/// Describes how an anonymous request was handled, enabled anonymous authentication being
+/// a default of API servers but a misconfiguration of kubelets
+fn anonymous_findings(response: &Response, resource: &str, enabled: Severity) -> Vec<Finding> {
+    match response.status {
+        200 => vec![
+            Finding::new("anonymous auth", "enabled"),
+            Finding::new("anonymous access", format!("{} readable", resource))
+                .with_severity(Severity::Critical),
+        ],
+        403 => vec![
+            Finding::new("anonymous auth", "enabled").with_severity(enabled),
+            Finding::new("anonymous access", format!("{} forbidden", resource)),
+        ],
+        401 => vec![Finding::new("anonymous auth", "disabled")],
+        _ => vec![Finding::new(
+            format!("{} request", resource),
+            response.status_line.clone(),
+        )],
+    }
+}
+
+impl KubernetesProbe {
+    async fn check_api_server(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+        version: &Response,
+    ) -> Vec<Finding> {
+        let mut findings = vec![Finding::new("scheme", scheme.name())];
+        match version.json() {
+            Some(json) if version.status == 200 => {
+                findings.extend(http::json_findings(&json, &VERSION_FIELDS));
+                if let Ok(Some(pods)) = HttpProbe::request(
+                    peer_addr,
+                    scheme,
+                    server_name,
+                    "GET",
+                    "/api/v1/pods?limit=1",
+                    None,
+                )
+                .await
+                {
+                    findings.extend(anonymous_findings(&pods, "pods", Severity::Low));
+                }
+            }
+            _ => findings.extend(anonymous_findings(version, "version", Severity::Low)),
+        }
+        findings
+    }
+
+    /// Kubelets answer `/pods` with a `PodList`, or refuse it telling the user they saw
+    async fn check_kubelet(
+        peer_addr: SocketAddr,
+        scheme: Scheme,
+        server_name: Option<&str>,
+    ) -> Option<Vec<Finding>> {
+        let pods = HttpProbe::request(peer_addr, scheme, server_name, "GET", "/pods", None)
+            .await
+            .ok()??;
+        let body = String::from_utf8_lossy(&pods.body[..]);
+        let is_kubelet = match pods.status {
+            200 => pods.json().is_some_and(|json| {
+                json.get("kind").and_then(|kind| kind.as_str()) == Some("PodList")
+            }),
+            403 => body.contains("user=system:anonymous"),
+            401 => {
+                body.trim() == "Unauthorized"
+                    && matches!(peer_addr.port(), KUBELET_PORT | KUBELET_READ_ONLY_PORT)
+            }
+            _ => false,
+        };
+        if !is_kubelet {
+            return None;
+        }
+
+        let mut findings = vec![Finding::new("scheme", scheme.name())];
+        if let Some(serde_json::Value::Array(items)) =
+            pods.json().and_then(|json| json.get("items").cloned())
+        {
+            findings.push(Finding::new("pods", items.len().to_string()));
+        }
+        findings.extend(anonymous_findings(&pods, "pods", Severity::Medium));
+        Some(findings)
+    }
+}
+
+impl Probe for KubernetesProbe {
+    fn name(&self) -> &'static str {
+        "kubernetes"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 6443 | KUBELET_PORT | KUBELET_READ_ONLY_PORT)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let server_name = unsafe { SERVER_NAMES }.first().map(|name| name.as_str());
+            let (scheme, version) =
+                match HttpProbe::get_any_scheme(peer_addr, server_name, "/version").await? {
+                    Some(answer) => answer,
+                    None => return Ok(ProbeStatus::Unknown),
+                };
+
+            let is_api_server = is_status(&version)
+                || version
+                    .json()
+                    .is_some_and(|json| json.get("gitVersion").is_some());
+            if is_api_server {
+                let findings =
+                    Self::check_api_server(peer_addr, scheme, server_name, &version).await;
+                return Ok(ProbeStatus::Found(Service::new(
+                    "Kubernetes API server",
+                    findings,
+                )));
+            }
+            match Self::check_kubelet(peer_addr, scheme, server_name).await {
+                Some(findings) => Ok(ProbeStatus::Found(Service::new(
+                    "Kubernetes kubelet",
+                    findings,
+                ))),
+                None => Ok(ProbeStatus::Unknown),
+            }
+        })
+    }
+}
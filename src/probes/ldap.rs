@@ -0,0 +1,259 @@
+//! LDAP servers describe themselves in their root DSE, the entry with an empty name,
+//! which anonymous clients are allowed to read without binding.
+
+use std::net::SocketAddr;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use super::{ber, Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const SEARCH_REQUEST: u8 = 0x63;
+const SEARCH_RESULT_ENTRY: u8 = 0x64;
+const SEARCH_RESULT_DONE: u8 = 0x65;
+const SEARCH_RESULT_REFERENCE: u8 = 0x73;
+/// Context specific primitive [7], the `present` filter
+const FILTER_PRESENT: u8 = 0x87;
+
+const SCOPE_BASE_OBJECT: u8 = 0;
+const NEVER_DEREF_ALIASES: u8 = 0;
+
+const MESSAGE_ID: i64 = 1;
+
+const MAX_MESSAGE_SIZE: usize = 256 * 1024;
+const MAX_MESSAGES: usize = 8;
+
+/// Attributes of the root DSE worth reporting, as (attribute, finding's key)
+const ATTRIBUTES: [(&str, &str); 13] = [
+    ("vendorName", "vendor"),
+    ("vendorVersion", "vendor version"),
+    ("supportedLDAPVersion", "ldap versions"),
+    ("namingContexts", "naming contexts"),
+    ("defaultNamingContext", "default naming context"),
+    ("rootDomainNamingContext", "root domain naming context"),
+    ("supportedSASLMechanisms", "sasl mechanisms"),
+    ("dnsHostName", "dns host name"),
+    ("ldapServiceName", "service name"),
+    ("serverName", "server name"),
+    ("domainFunctionality", "domain functional level"),
+    ("forestFunctionality", "forest functional level"),
+    (
+        "domainControllerFunctionality",
+        "domain controller functional level",
+    ),
+];
+
+/// Attributes only read to deduce findings
+const OTHER_ATTRIBUTES: [&str; 3] = ["objectClass", "supportedCapabilities", "supportedExtension"];
+
+const LDAP_CAP_ACTIVE_DIRECTORY_OID: &str = "1.2.840.113556.1.4.800";
+const LDAP_CAP_ACTIVE_DIRECTORY_ADAM_OID: &str = "1.2.840.113556.1.4.1851";
+const START_TLS_OID: &str = "1.3.6.1.4.1.1466.20037";
+
+/// Windows releases introducing the functional levels
+const FUNCTIONAL_LEVELS: [&str; 11] = [
+    "2000",
+    "2003 interim",
+    "2003",
+    "2008",
+    "2008 R2",
+    "2012",
+    "2012 R2",
+    "2016",
+    "",
+    "",
+    "2025",
+];
+
+pub struct LdapProbe;
+
+fn search_request() -> Vec<u8> {
+    let attributes: Vec<u8> = ATTRIBUTES
+        .iter()
+        .map(|(attribute, _)| *attribute)
+        .chain(OTHER_ATTRIBUTES.iter().copied())
+        .flat_map(|attribute| ber::encode(ber::OCTET_STRING, attribute.as_bytes()))
+        .collect();
+    let search = [
+        ber::encode(ber::OCTET_STRING, b""),
+        ber::encode(ber::ENUMERATED, &[SCOPE_BASE_OBJECT]),
+        ber::encode(ber::ENUMERATED, &[NEVER_DEREF_ALIASES]),
+        // no size nor time limit, with the values
+        ber::encode(ber::INTEGER, &[0]),
+        ber::encode(ber::INTEGER, &[0]),
+        ber::encode(ber::BOOLEAN, &[0]),
+        ber::encode(FILTER_PRESENT, b"objectClass"),
+        ber::encode(ber::SEQUENCE, &attributes),
+    ]
+    .concat();
+    let message = [
+        ber::encode(ber::INTEGER, &[MESSAGE_ID as u8]),
+        ber::encode(SEARCH_REQUEST, &search),
+    ]
+    .concat();
+    ber::encode(ber::SEQUENCE, &message)
+}
+
+/// Reads an LDAP message as its id, the tag of its operation and the operation
+fn ldap_message(data: &[u8]) -> Option<(i64, u8, &[u8])> {
+    let (tag, message, _) = ber::parse(data)?;
+    if tag != ber::SEQUENCE {
+        return None;
+    }
+    let (tag, id, message) = ber::parse(message)?;
+    if tag != ber::INTEGER {
+        return None;
+    }
+    let (tag, operation, _) = ber::parse(message)?;
+    Some((ber::integer(id)?, tag, operation))
+}
+
+/// Reads the attributes of a SearchResultEntry with their values
+fn entry_attributes(entry: &[u8]) -> Option<Vec<(String, Vec<String>)>> {
+    let (_, _, entry) = ber::parse(entry)?;
+    let (tag, mut list, _) = ber::parse(entry)?;
+    if tag != ber::SEQUENCE {
+        return None;
+    }
+    let mut attributes = Vec::new();
+    while let Some((_, attribute, next)) = ber::parse(list) {
+        let (_, name, attribute) = ber::parse(attribute)?;
+        let (tag, mut set, _) = ber::parse(attribute)?;
+        if tag != ber::SET {
+            return None;
+        }
+        let mut values = Vec::new();
+        while let Some((_, value, next)) = ber::parse(set) {
+            values.push(String::from_utf8_lossy(value).into_owned());
+            set = next;
+        }
+        attributes.push((String::from_utf8_lossy(name).into_owned(), values));
+        list = next;
+    }
+    Some(attributes)
+}
+
+/// Reads a SearchResultDone's result code and diagnostic message
+fn result(done: &[u8]) -> Option<(i64, String)> {
+    let (_, code, done) = ber::parse(done)?;
+    let (_, _, done) = ber::parse(done)?;
+    let message = ber::parse(done)
+        .map(|(_, message, _)| String::from_utf8_lossy(message).into_owned())
+        .unwrap_or_default();
+    Some((ber::integer(code)?, message))
+}
+
+fn functional_level(level: &str) -> String {
+    match level
+        .parse::<usize>()
+        .ok()
+        .and_then(|index| FUNCTIONAL_LEVELS.get(index))
+    {
+        Some(name) if !name.is_empty() => format!("{} (Windows Server {})", level, name),
+        _ => level.to_owned(),
+    }
+}
+
+/// Describes the root DSE
+fn root_dse_findings(attributes: &[(String, Vec<String>)]) -> Vec<Finding> {
+    let values = |name: &str| {
+        attributes
+            .iter()
+            .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, values)| &values[..])
+            .unwrap_or_default()
+    };
+    let has_value = |name: &str, value: &str| values(name).iter().any(|v| v == value);
+
+    let mut findings = Vec::new();
+    for (attribute, key) in ATTRIBUTES.iter() {
+        let values = values(attribute);
+        if values.is_empty() {
+            continue;
+        }
+        let value = if attribute.ends_with("Functionality") {
+            functional_level(&values[0])
+        } else {
+            values.join(", ")
+        };
+        findings.push(Finding::new(*key, value));
+    }
+    if values("vendorName").is_empty() && has_value("objectClass", "OpenLDAProotDSE") {
+        findings.insert(0, Finding::new("vendor", "OpenLDAP"));
+    }
+
+    let active_directory = if has_value("supportedCapabilities", LDAP_CAP_ACTIVE_DIRECTORY_OID) {
+        "yes"
+    } else if has_value("supportedCapabilities", LDAP_CAP_ACTIVE_DIRECTORY_ADAM_OID) {
+        "yes (lightweight directory services)"
+    } else {
+        "no"
+    };
+    findings.push(Finding::new("active directory", active_directory));
+    if !values("supportedExtension").is_empty() {
+        findings.push(Finding::new(
+            "starttls",
+            if has_value("supportedExtension", START_TLS_OID) {
+                "supported"
+            } else {
+                "not supported"
+            },
+        ));
+    }
+    findings
+}
+
+impl Probe for LdapProbe {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        // 3268 is Active Directory's global catalog
+        matches!(port, 389 | 3268)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            stream.write_all(&search_request()[..]).await?;
+
+            let mut findings = Vec::new();
+            for _ in 0..MAX_MESSAGES {
+                let message = ber::read(&mut stream, MAX_MESSAGE_SIZE).await?;
+                let (id, tag, operation) = match ldap_message(&message[..]) {
+                    Some(message) => message,
+                    None => return Ok(ProbeStatus::Unknown),
+                };
+                // Unsolicited notifications, like a notice of disconnection, have id 0
+                if id != MESSAGE_ID && id != 0 {
+                    return Ok(ProbeStatus::Unknown);
+                }
+                match tag {
+                    SEARCH_RESULT_ENTRY => {
+                        if let Some(attributes) = entry_attributes(operation) {
+                            findings.extend(root_dse_findings(&attributes));
+                        }
+                    }
+                    SEARCH_RESULT_REFERENCE => {}
+                    SEARCH_RESULT_DONE => {
+                        match result(operation) {
+                            Some((0, _)) => {}
+                            Some((code, message)) if message.is_empty() => {
+                                findings.push(Finding::new("error", code.to_string()))
+                            }
+                            Some((code, message)) => findings
+                                .push(Finding::new("error", format!("{} ({})", message, code))),
+                            None => return Ok(ProbeStatus::Unknown),
+                        }
+                        break;
+                    }
+                    // Extended response to notify a disconnection
+                    _ => break,
+                }
+            }
+
+            Ok(ProbeStatus::Found(Service::new("LDAP", findings)))
+        })
+    }
+}
@@ -0,0 +1,75 @@
+//! memcached speaks a text protocol where `version` and `stats` need no authentication
+//! (SASL is only available with the binary protocol).
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+/// Statistics worth reporting
+const STATS: [&str; 6] = [
+    "uptime",
+    "curr_connections",
+    "curr_items",
+    "total_items",
+    "limit_maxbytes",
+    "threads",
+];
+
+/// Upper bound on the lines of `stats`, which has about a hundred
+const MAX_STATS_LINES: usize = 512;
+
+pub struct MemcachedProbe;
+
+impl MemcachedProbe {
+    async fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        Ok(line.trim_end().to_owned())
+    }
+}
+
+impl Probe for MemcachedProbe {
+    fn name(&self) -> &'static str {
+        "memcached"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 11211)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut reader = BufReader::new(TcpStream::connect(&peer_addr).await?);
+            reader.get_mut().write_all(b"version\r\n").await?;
+            let line = Self::read_line(&mut reader).await?;
+            let version = match line.strip_prefix("VERSION ") {
+                Some(version) => version.to_owned(),
+                None => return Ok(ProbeStatus::Unknown),
+            };
+            let mut findings = vec![Finding::new("version", version)];
+
+            reader.get_mut().write_all(b"stats\r\n").await?;
+            for _ in 0..MAX_STATS_LINES {
+                let line = Self::read_line(&mut reader).await?;
+                let mut fields = line.splitn(3, ' ');
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some("STAT"), Some(name), Some(value)) => {
+                        if STATS.contains(&name) {
+                            findings.push(Finding::new(name, value));
+                        }
+                    }
+                    _ => break,
+                }
+            }
+            if findings.len() > 1 {
+                findings.push(Finding::new("auth", "not required"));
+            }
+
+            Ok(ProbeStatus::Found(Service::new("memcached", findings)))
+        })
+    }
+}
@@ -0,0 +1,152 @@
+//! Modbus/TCP devices describe themselves with the Read Device Identification function
+//! (43/14), a read-only request which leaves registers and coils alone.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const PROTOCOL_ID: u16 = 0;
+/// Unit identifier meaning "not significant" on TCP, gateways do not forward it
+const UNIT_ID: u8 = 0xff;
+
+const FUNCTION_ENCAPSULATED_INTERFACE: u8 = 0x2b;
+const MEI_READ_DEVICE_ID: u8 = 0x0e;
+const EXCEPTION_FLAG: u8 = 0x80;
+
+/// Regular identification, the basic objects followed by the optional ones
+const READ_DEVICE_ID_REGULAR: u8 = 0x02;
+
+/// Upper bound on the requests, when objects do not fit in one response
+const MAX_REQUESTS: usize = 4;
+
+/// Standard objects, as (id, finding's key)
+const OBJECTS: [(u8, &str); 7] = [
+    (0x00, "vendor"),
+    (0x01, "product code"),
+    (0x02, "revision"),
+    (0x03, "vendor url"),
+    (0x04, "product name"),
+    (0x05, "model name"),
+    (0x06, "user application name"),
+];
+
+pub struct ModbusProbe;
+
+/// Device identification objects, as (id, value)
+type Objects = Vec<(u8, String)>;
+
+fn read_device_id_request(transaction_id: u16, object_id: u8) -> Vec<u8> {
+    let pdu = [
+        FUNCTION_ENCAPSULATED_INTERFACE,
+        MEI_READ_DEVICE_ID,
+        READ_DEVICE_ID_REGULAR,
+        object_id,
+    ];
+    let mut request = Vec::new();
+    request.extend_from_slice(&transaction_id.to_be_bytes());
+    request.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+    // the unit identifier is counted in the length
+    request.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+    request.push(UNIT_ID);
+    request.extend_from_slice(&pdu);
+    request
+}
+
+fn exception_name(code: u8) -> String {
+    match code {
+        1 => "illegal function".to_owned(),
+        2 => "illegal data address".to_owned(),
+        3 => "illegal data value".to_owned(),
+        4 => "server device failure".to_owned(),
+        6 => "server device busy".to_owned(),
+        10 => "gateway path unavailable".to_owned(),
+        11 => "gateway target device failed to respond".to_owned(),
+        _ => format!("exception {}", code),
+    }
+}
+
+/// Device identification objects of a response, and the next object id when more follow
+fn device_objects(pdu: &[u8]) -> Option<(Objects, Option<u8>)> {
+    if pdu.len() < 7 || pdu[1] != MEI_READ_DEVICE_ID {
+        return None;
+    }
+    let more_follows = pdu[4] == 0xff;
+    let next_object_id = pdu[5];
+    let count = pdu[6] as usize;
+    let mut objects = Vec::new();
+    let mut data = &pdu[7..];
+    for _ in 0..count {
+        let (id, len) = (*data.first()?, *data.get(1)? as usize);
+        let value = data.get(2..2 + len)?;
+        objects.push((id, String::from_utf8_lossy(value).trim().to_owned()));
+        data = &data[2 + len..];
+    }
+    Some((objects, more_follows.then_some(next_object_id)))
+}
+
+impl Probe for ModbusProbe {
+    fn name(&self) -> &'static str {
+        "modbus"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 502)
+    }
+
+    fn is_industrial(&self) -> bool {
+        true
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            let mut findings = Vec::new();
+            let mut object_id = 0;
+            for transaction_id in 1..=MAX_REQUESTS as u16 {
+                stream
+                    .write_all(&read_device_id_request(transaction_id, object_id)[..])
+                    .await?;
+
+                let mut header = [0u8; 7];
+                stream.read_exact(&mut header[..]).await?;
+                let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+                if u16::from_be_bytes([header[0], header[1]]) != transaction_id
+                    || u16::from_be_bytes([header[2], header[3]]) != PROTOCOL_ID
+                    || !(3..=254).contains(&length)
+                {
+                    return Ok(ProbeStatus::Unknown);
+                }
+                let mut pdu = vec![0u8; length - 1];
+                stream.read_exact(&mut pdu[..]).await?;
+
+                if pdu[0] == FUNCTION_ENCAPSULATED_INTERFACE | EXCEPTION_FLAG {
+                    findings.push(Finding::new(
+                        "device identification",
+                        format!("not supported ({})", exception_name(pdu[1])),
+                    ));
+                    break;
+                }
+                let (objects, next) = match device_objects(&pdu[..]) {
+                    Some(objects) if pdu[0] == FUNCTION_ENCAPSULATED_INTERFACE => objects,
+                    _ => return Ok(ProbeStatus::Unknown),
+                };
+                for (id, value) in objects {
+                    let key = match OBJECTS.iter().find(|(i, _)| *i == id) {
+                        Some((_, key)) => key.to_string(),
+                        None => format!("object {:#04x}", id),
+                    };
+                    findings.push(Finding::new(key, value));
+                }
+                match next {
+                    Some(next) => object_id = next,
+                    None => break,
+                }
+            }
+
+            Ok(ProbeStatus::Found(Service::new("Modbus/TCP", findings)))
+        })
+    }
+}
@@ -0,0 +1,308 @@
+//! MongoDB answers the `isMaster` handshake (`hello` in recent releases, which still
+//! accept the legacy name) and `buildInfo` before authentication.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const OP_REPLY: i32 = 1;
+const OP_QUERY: i32 = 2004;
+const OP_MSG: i32 = 2013;
+
+/// OP_MSG, used for commands other than the handshake, appeared with wire version 6
+const OP_MSG_WIRE_VERSION: i64 = 6;
+
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+pub struct MongodbProbe;
+
+/// The few BSON types we need to read
+#[derive(Debug)]
+enum Bson {
+    Double(f64),
+    String(String),
+    Bool(bool),
+    Int(i64),
+    Other,
+}
+
+/// Encodes a document, integers as int32 and without the fields of other types
+fn bson_document(fields: &[(&str, Bson)]) -> Vec<u8> {
+    let mut data = vec![0u8; 4];
+    for (name, value) in fields.iter() {
+        let (kind, value) = match value {
+            Bson::Double(double) => (0x01, double.to_le_bytes().to_vec()),
+            Bson::String(string) => {
+                let mut value = (string.len() as i32 + 1).to_le_bytes().to_vec();
+                value.extend_from_slice(string.as_bytes());
+                value.push(0);
+                (0x02, value)
+            }
+            Bson::Bool(value) => (0x08, vec![u8::from(*value)]),
+            Bson::Int(int) => (0x10, (*int as i32).to_le_bytes().to_vec()),
+            Bson::Other => continue,
+        };
+        data.push(kind);
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+        data.extend_from_slice(&value[..]);
+    }
+    data.push(0);
+    let len = (data.len() as i32).to_le_bytes();
+    data[..4].copy_from_slice(&len);
+    data
+}
+
+/// Decodes the top level fields of a document, nested ones are skipped
+fn bson_fields(document: &[u8]) -> Option<Vec<(String, Bson)>> {
+    fn int32(data: &[u8], index: usize) -> Option<i32> {
+        let bytes = data.get(index..index + 4)?;
+        Some(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    /// A length, never negative
+    fn length(data: &[u8], index: usize) -> Option<usize> {
+        usize::try_from(int32(data, index)?).ok()
+    }
+    fn int64(data: &[u8], index: usize) -> Option<i64> {
+        let bytes = data.get(index..index + 8)?;
+        let mut array = [0u8; 8];
+        array.copy_from_slice(bytes);
+        Some(i64::from_le_bytes(array))
+    }
+
+    let document = document.get(..length(document, 0)?)?;
+    let mut fields = Vec::new();
+    let mut index = 4;
+    loop {
+        let kind = *document.get(index)?;
+        if kind == 0 {
+            return Some(fields);
+        }
+        let name = document.get(index + 1..)?;
+        let name_len = name.iter().position(|&b| b == 0)?;
+        let name = String::from_utf8_lossy(&name[..name_len]);
+        index += name_len + 2;
+        let (value, size) = match kind {
+            0x01 => (
+                Bson::Double(f64::from_bits(int64(document, index)? as u64)),
+                8,
+            ),
+            0x02 => {
+                // the length counts the final NUL
+                let len = length(document, index)?.checked_sub(1)?;
+                let start = index + 4;
+                let bytes = document.get(start..start.checked_add(len)?)?;
+                let string = String::from_utf8_lossy(bytes).into_owned();
+                (Bson::String(string), len + 5)
+            }
+            // documents, arrays and binary data are prefixed by their length
+            0x03 | 0x04 => (Bson::Other, length(document, index)?),
+            0x05 => (Bson::Other, length(document, index)?.checked_add(5)?),
+            0x07 => (Bson::Other, 12),
+            0x08 => (Bson::Bool(*document.get(index)? != 0), 1),
+            0x09 | 0x11 => (Bson::Other, 8),
+            0x0a => (Bson::Other, 0),
+            0x10 => (Bson::Int(i64::from(int32(document, index)?)), 4),
+            0x12 => (Bson::Int(int64(document, index)?), 8),
+            0x13 => (Bson::Other, 16),
+            _ => return None,
+        };
+        fields.push((name.into_owned(), value));
+        index = index.checked_add(size)?;
+    }
+}
+
+fn field<'a>(fields: &'a [(String, Bson)], name: &str) -> Option<&'a Bson> {
+    fields
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, value)| value)
+}
+
+fn int_field(fields: &[(String, Bson)], name: &str) -> Option<i64> {
+    match field(fields, name)? {
+        Bson::Int(int) => Some(*int),
+        Bson::Double(double) => Some(*double as i64),
+        _ => None,
+    }
+}
+
+fn string_field<'a>(fields: &'a [(String, Bson)], name: &str) -> Option<&'a str> {
+    match field(fields, name)? {
+        Bson::String(string) => Some(string.as_str()),
+        _ => None,
+    }
+}
+
+fn bool_field(fields: &[(String, Bson)], name: &str) -> bool {
+    matches!(field(fields, name), Some(Bson::Bool(true)))
+}
+
+impl MongodbProbe {
+    /// Sends a message and reads the reply, as (operation code, body)
+    async fn exchange(
+        stream: &mut TcpStream,
+        op_code: i32,
+        body: &[u8],
+    ) -> io::Result<Option<(i32, Vec<u8>)>> {
+        let request_id: i32 = rand::random::<i32>() & 0x7fff_ffff;
+        let mut message = Vec::with_capacity(body.len() + 16);
+        message.extend_from_slice(&(body.len() as i32 + 16).to_le_bytes());
+        message.extend_from_slice(&request_id.to_le_bytes());
+        message.extend_from_slice(&0i32.to_le_bytes());
+        message.extend_from_slice(&op_code.to_le_bytes());
+        message.extend_from_slice(body);
+        stream.write_all(&message[..]).await?;
+
+        let mut header = [0u8; 16];
+        stream.read_exact(&mut header[..]).await?;
+        let length = i32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let response_to = i32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        let op_code = i32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+        if response_to != request_id || !(16..=MAX_MESSAGE_SIZE).contains(&length) {
+            return Ok(None);
+        }
+        let mut body = vec![0u8; length - 16];
+        stream.read_exact(&mut body[..]).await?;
+        Ok(Some((op_code, body)))
+    }
+
+    /// Runs a command on the admin database through the legacy OP_QUERY
+    async fn query(
+        stream: &mut TcpStream,
+        command: &str,
+    ) -> io::Result<Option<Vec<(String, Bson)>>> {
+        let mut body = 0i32.to_le_bytes().to_vec();
+        body.extend_from_slice(b"admin.$cmd\0");
+        // documents to skip and to return
+        body.extend_from_slice(&0i32.to_le_bytes());
+        body.extend_from_slice(&(-1i32).to_le_bytes());
+        body.extend_from_slice(&bson_document(&[(command, Bson::Int(1))])[..]);
+
+        Ok(match Self::exchange(stream, OP_QUERY, &body[..]).await? {
+            // flags, cursor id, starting from and number returned precede the document
+            Some((OP_REPLY, reply)) => reply.get(20..).and_then(bson_fields),
+            _ => None,
+        })
+    }
+
+    /// Runs a command on the admin database through OP_MSG
+    async fn command(
+        stream: &mut TcpStream,
+        command: &str,
+    ) -> io::Result<Option<Vec<(String, Bson)>>> {
+        let mut body = 0u32.to_le_bytes().to_vec();
+        // a single body section
+        body.push(0);
+        body.extend_from_slice(
+            &bson_document(&[
+                (command, Bson::Int(1)),
+                ("$db", Bson::String("admin".to_owned())),
+            ])[..],
+        );
+
+        Ok(match Self::exchange(stream, OP_MSG, &body[..]).await? {
+            // flags and section kind precede the document
+            Some((OP_MSG, reply)) => reply.get(5..).and_then(bson_fields),
+            _ => None,
+        })
+    }
+}
+
+impl Probe for MongodbProbe {
+    fn name(&self) -> &'static str {
+        "mongodb"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 27017..=27019)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            let hello = match Self::query(&mut stream, "isMaster").await? {
+                Some(fields) if field(&fields, "maxWireVersion").is_some() => fields,
+                _ => return Ok(ProbeStatus::Unknown),
+            };
+
+            let mut findings = Vec::new();
+            let max_wire_version = int_field(&hello, "maxWireVersion").unwrap_or_default();
+            let build_info = if max_wire_version >= OP_MSG_WIRE_VERSION {
+                Self::command(&mut stream, "buildInfo").await
+            } else {
+                Self::query(&mut stream, "buildInfo").await
+            };
+            if let Ok(Some(build_info)) = build_info {
+                if let Some(version) = string_field(&build_info, "version") {
+                    findings.push(Finding::new("version", version));
+                }
+            }
+            findings.push(Finding::new(
+                "wire versions",
+                format!(
+                    "{} to {}",
+                    int_field(&hello, "minWireVersion").unwrap_or_default(),
+                    max_wire_version
+                ),
+            ));
+
+            let role = if string_field(&hello, "msg") == Some("isdbgrid") {
+                "router (mongos)"
+            } else if bool_field(&hello, "isreplicaset") {
+                "replica set member, not initialized"
+            } else if string_field(&hello, "setName").is_none() {
+                "standalone"
+            } else if bool_field(&hello, "ismaster") {
+                "primary"
+            } else if bool_field(&hello, "secondary") {
+                "secondary"
+            } else if bool_field(&hello, "arbiterOnly") {
+                "arbiter"
+            } else {
+                "replica set member"
+            };
+            findings.push(Finding::new("role", role));
+            if let Some(set_name) = string_field(&hello, "setName") {
+                findings.push(Finding::new("replica set", set_name));
+            }
+
+            Ok(ProbeStatus::Found(Service::new("MongoDB", findings)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let document = bson_document(&[
+            ("ismaster", Bson::Bool(true)),
+            ("maxWireVersion", Bson::Int(17)),
+            ("version", Bson::String("7.0.2".to_owned())),
+        ]);
+        let fields = bson_fields(&document[..]).unwrap();
+        assert!(bool_field(&fields[..], "ismaster"));
+        assert_eq!(int_field(&fields[..], "maxWireVersion"), Some(17));
+        assert_eq!(string_field(&fields[..], "version"), Some("7.0.2"));
+    }
+
+    #[test]
+    fn rejects_hostile_lengths() {
+        for length in [-1i32, 0, i32::MAX] {
+            let mut document = bson_document(&[("version", Bson::String("7.0.2".to_owned()))]);
+            document[13..17].copy_from_slice(&length.to_le_bytes());
+            assert!(bson_fields(&document[..]).is_none(), "{}", length);
+        }
+        let mut document = vec![0x10, 0, 0, 0, 0x03, b'a', 0];
+        document.extend_from_slice(&(-5i32).to_le_bytes());
+        document.extend_from_slice(&[0, 0, 0, 0, 0]);
+        assert!(bson_fields(&document[..]).is_none());
+    }
+}
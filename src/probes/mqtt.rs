@@ -0,0 +1,84 @@
+//! MQTT brokers answer a CONNECT without credentials with a CONNACK telling whether
+//! anonymous clients are accepted.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const PACKET_CONNECT: u8 = 0x10;
+const PACKET_CONNACK: u8 = 0x20;
+const PACKET_DISCONNECT: u8 = 0xe0;
+
+/// Protocol level of MQTT 3.1.1
+const PROTOCOL_LEVEL: u8 = 4;
+
+/// Only asks for a clean session, without will, user name or password
+const FLAG_CLEAN_SESSION: u8 = 0x02;
+
+const KEEP_ALIVE: u16 = 30;
+
+pub struct MqttProbe;
+
+fn connect_packet(client_id: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&4u16.to_be_bytes());
+    body.extend_from_slice(b"MQTT");
+    body.push(PROTOCOL_LEVEL);
+    body.push(FLAG_CLEAN_SESSION);
+    body.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+    body.extend_from_slice(&(client_id.len() as u16).to_be_bytes());
+    body.extend_from_slice(client_id.as_bytes());
+
+    // The remaining length fits in a single byte
+    let mut packet = vec![PACKET_CONNECT, body.len() as u8];
+    packet.extend_from_slice(&body[..]);
+    packet
+}
+
+fn return_code(code: u8) -> &'static str {
+    match code {
+        0 => "accepted",
+        1 => "refused (unacceptable protocol version)",
+        2 => "refused (identifier rejected)",
+        3 => "refused (server unavailable)",
+        4 => "refused (bad user name or password)",
+        5 => "refused (not authorized)",
+        _ => "refused",
+    }
+}
+
+impl Probe for MqttProbe {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 1883)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            let client_id = format!("port-scanner-{:08x}", rand::random::<u32>());
+            stream.write_all(&connect_packet(&client_id)[..]).await?;
+
+            let mut connack = [0u8; 4];
+            stream.read_exact(&mut connack[..]).await?;
+            if connack[0] != PACKET_CONNACK || connack[1] != 2 || connack[2] & 0xfe != 0 {
+                return Ok(ProbeStatus::Unknown);
+            }
+            if connack[3] == 0 {
+                stream.write_all(&[PACKET_DISCONNECT, 0]).await?;
+            }
+
+            let findings = vec![
+                Finding::new("protocol", "3.1.1"),
+                Finding::new("anonymous access", return_code(connack[3])),
+            ];
+            Ok(ProbeStatus::Found(Service::new("MQTT", findings)))
+        })
+    }
+}
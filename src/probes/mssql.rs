@@ -0,0 +1,182 @@
+//! Microsoft SQL Server answers the TDS PRELOGIN message, which negotiates encryption
+//! before the login, with its version.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const PACKET_PRELOGIN: u8 = 0x12;
+const PACKET_RESPONSE: u8 = 0x04;
+const STATUS_EOM: u8 = 0x01;
+
+const OPTION_VERSION: u8 = 0x00;
+const OPTION_ENCRYPTION: u8 = 0x01;
+const OPTION_INSTOPT: u8 = 0x02;
+const OPTION_THREADID: u8 = 0x03;
+const OPTION_MARS: u8 = 0x04;
+const OPTION_TERMINATOR: u8 = 0xff;
+
+/// ENCRYPT_OFF: we could encrypt, but do not require it
+const ENCRYPT_OFF: u8 = 0x00;
+
+const MAX_PACKET_SIZE: usize = 4096;
+
+pub struct MssqlProbe;
+
+/// Builds a PRELOGIN packet from its options
+fn prelogin(options: &[(u8, &[u8])]) -> Vec<u8> {
+    let header_len = 8;
+    let mut offset = options.len() * 5 + 1;
+    let mut payload = Vec::new();
+    for (token, data) in options.iter() {
+        payload.push(*token);
+        payload.extend_from_slice(&(offset as u16).to_be_bytes());
+        payload.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        offset += data.len();
+    }
+    payload.push(OPTION_TERMINATOR);
+    for (_, data) in options.iter() {
+        payload.extend_from_slice(data);
+    }
+
+    let mut packet = vec![PACKET_PRELOGIN, STATUS_EOM];
+    packet.extend_from_slice(&((payload.len() + header_len) as u16).to_be_bytes());
+    // SPID, packet id and window
+    packet.extend_from_slice(&[0, 0, 1, 0]);
+    packet.extend_from_slice(&payload[..]);
+    packet
+}
+
+/// Reads the options of a PRELOGIN payload as (token, data)
+fn prelogin_options(payload: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut options = Vec::new();
+    let mut index = 0;
+    loop {
+        let token = *payload.get(index)?;
+        if token == OPTION_TERMINATOR {
+            return Some(options);
+        }
+        let entry = payload.get(index + 1..index + 5)?;
+        let offset = u16::from_be_bytes([entry[0], entry[1]]) as usize;
+        let length = u16::from_be_bytes([entry[2], entry[3]]) as usize;
+        options.push((token, payload.get(offset..offset + length)?));
+        index += 5;
+    }
+}
+
+/// Marketing name of a release, from its major and minor versions
+fn product_name(major: u8, minor: u8) -> Option<&'static str> {
+    Some(match (major, minor) {
+        (8, _) => "SQL Server 2000",
+        (9, _) => "SQL Server 2005",
+        (10, 50) => "SQL Server 2008 R2",
+        (10, _) => "SQL Server 2008",
+        (11, _) => "SQL Server 2012",
+        (12, _) => "SQL Server 2014",
+        (13, _) => "SQL Server 2016",
+        (14, _) => "SQL Server 2017",
+        (15, _) => "SQL Server 2019",
+        (16, _) => "SQL Server 2022",
+        (17, _) => "SQL Server 2025",
+        _ => return None,
+    })
+}
+
+impl MssqlProbe {
+    async fn read_packet(stream: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header[..]).await?;
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        if header[0] != PACKET_RESPONSE || !(8..=MAX_PACKET_SIZE).contains(&length) {
+            return Ok(None);
+        }
+        let mut payload = vec![0u8; length - 8];
+        stream.read_exact(&mut payload[..]).await?;
+        Ok(Some(payload))
+    }
+
+    fn findings(options: &[(u8, &[u8])]) -> Option<Vec<Finding>> {
+        let mut findings = Vec::new();
+        let version = options
+            .iter()
+            .find(|(token, _)| *token == OPTION_VERSION)
+            .map(|(_, data)| *data)
+            .filter(|data| data.len() >= 6)?;
+        let (major, minor) = (version[0], version[1]);
+        let build = u16::from_be_bytes([version[2], version[3]]);
+        let sub_build = u16::from_be_bytes([version[4], version[5]]);
+        let version = format!("{}.{}.{}.{}", major, minor, build, sub_build);
+        findings.push(Finding::new(
+            "version",
+            match product_name(major, minor) {
+                Some(name) => format!("{} ({})", version, name),
+                None => version,
+            },
+        ));
+
+        for (token, data) in options.iter() {
+            match *token {
+                OPTION_ENCRYPTION => {
+                    let encryption = match data.first() {
+                        Some(0x00) => "off (login packet only)",
+                        Some(0x01) => "on",
+                        Some(0x02) => "not supported",
+                        Some(0x03) => "required",
+                        _ => "unknown",
+                    };
+                    findings.push(Finding::new("encryption", encryption));
+                }
+                OPTION_MARS => {
+                    let mars = if data.first() == Some(&1) {
+                        "supported"
+                    } else {
+                        "not supported"
+                    };
+                    findings.push(Finding::new("mars", mars));
+                }
+                _ => {}
+            }
+        }
+        Some(findings)
+    }
+}
+
+impl Probe for MssqlProbe {
+    fn name(&self) -> &'static str {
+        "mssql"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 1433 | 1434)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            let packet = prelogin(&[
+                (OPTION_VERSION, &[0, 0, 0, 0, 0, 0]),
+                (OPTION_ENCRYPTION, &[ENCRYPT_OFF]),
+                // the default instance, with its name NUL terminated
+                (OPTION_INSTOPT, &[0]),
+                (OPTION_THREADID, &[0, 0, 0, 0]),
+                (OPTION_MARS, &[0]),
+            ]);
+            stream.write_all(&packet[..]).await?;
+
+            let findings = match Self::read_packet(&mut stream).await? {
+                Some(payload) => prelogin_options(&payload[..])
+                    .as_deref()
+                    .and_then(Self::findings),
+                None => None,
+            };
+            match findings {
+                Some(findings) => Ok(ProbeStatus::Found(Service::new("MSSQL", findings))),
+                None => Ok(ProbeStatus::Unknown),
+            }
+        })
+    }
+}
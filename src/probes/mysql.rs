@@ -0,0 +1,225 @@
+//! MySQL and MariaDB servers greet clients with their initial handshake packet
+//! (protocol version 10), which is all we read: no login is attempted.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const PROTOCOL_VERSION: u8 = 10;
+
+/// First byte of an error packet, sent instead of the handshake to refused hosts
+const ERR_PACKET: u8 = 0xff;
+
+/// Handshake packets are small, anything larger is not MySQL
+const MAX_PACKET_SIZE: usize = 1024;
+
+const CLIENT_SSL: u32 = 0x800;
+
+const CAPABILITIES: [(u32, &str); 29] = [
+    (0x1, "LONG_PASSWORD"),
+    (0x2, "FOUND_ROWS"),
+    (0x4, "LONG_FLAG"),
+    (0x8, "CONNECT_WITH_DB"),
+    (0x10, "NO_SCHEMA"),
+    (0x20, "COMPRESS"),
+    (0x40, "ODBC"),
+    (0x80, "LOCAL_FILES"),
+    (0x100, "IGNORE_SPACE"),
+    (0x200, "PROTOCOL_41"),
+    (0x400, "INTERACTIVE"),
+    (CLIENT_SSL, "SSL"),
+    (0x1000, "IGNORE_SIGPIPE"),
+    (0x2000, "TRANSACTIONS"),
+    (0x4000, "RESERVED"),
+    (0x8000, "SECURE_CONNECTION"),
+    (0x1_0000, "MULTI_STATEMENTS"),
+    (0x2_0000, "MULTI_RESULTS"),
+    (0x4_0000, "PS_MULTI_RESULTS"),
+    (0x8_0000, "PLUGIN_AUTH"),
+    (0x10_0000, "CONNECT_ATTRS"),
+    (0x20_0000, "PLUGIN_AUTH_LENENC_CLIENT_DATA"),
+    (0x40_0000, "CAN_HANDLE_EXPIRED_PASSWORDS"),
+    (0x80_0000, "SESSION_TRACK"),
+    (0x100_0000, "DEPRECATE_EOF"),
+    (0x200_0000, "OPTIONAL_RESULTSET_METADATA"),
+    (0x400_0000, "ZSTD_COMPRESSION_ALGORITHM"),
+    (0x800_0000, "QUERY_ATTRIBUTES"),
+    (0x1000_0000, "MULTI_FACTOR_AUTHENTICATION"),
+];
+
+pub struct MysqlProbe;
+
+/// Checks the packet header (3 bytes of length, sequence number 0) and the first
+/// byte of the payload
+fn is_greeting(banner: &[u8]) -> bool {
+    if banner.len() < 6 || banner[3] != 0 {
+        return false;
+    }
+    let length = u32::from_le_bytes([banner[0], banner[1], banner[2], 0]) as usize;
+    length <= MAX_PACKET_SIZE
+        && length + 4 >= banner.len()
+        && match banner[4] {
+            PROTOCOL_VERSION => banner[5..].contains(&0),
+            ERR_PACKET => true,
+            _ => false,
+        }
+}
+
+/// Reads a NUL terminated string
+fn null_terminated(data: &mut &[u8]) -> Option<String> {
+    let end = data.iter().position(|&b| b == 0)?;
+    let string = String::from_utf8_lossy(&data[..end]).into_owned();
+    *data = &data[end + 1..];
+    Some(string)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if data.len() < len {
+        return None;
+    }
+    let (head, rest) = data.split_at(len);
+    *data = rest;
+    Some(head)
+}
+
+impl MysqlProbe {
+    /// Describes an error packet: code, SQL state and message
+    fn error_findings(mut payload: &[u8]) -> Vec<Finding> {
+        let code = match take(&mut payload, 2) {
+            Some(code) => u16::from_le_bytes([code[0], code[1]]),
+            None => return Vec::new(),
+        };
+        // Protocol 4.1 errors have a SQL state, prefixed with '#'
+        if payload.first() == Some(&b'#') && payload.len() >= 6 {
+            payload = &payload[6..];
+        }
+        vec![Finding::new(
+            "error",
+            format!("{} ({})", String::from_utf8_lossy(payload), code),
+        )]
+    }
+
+    /// Parses the handshake, returns the product name and what it tells about the server
+    fn greeting_findings(mut payload: &[u8]) -> Option<(&'static str, Vec<Finding>)> {
+        let mut findings = Vec::new();
+        let version = null_terminated(&mut payload)?;
+        // MariaDB prefixes its version for clients expecting a 5.x server
+        let (product, version) = if version.contains("MariaDB") {
+            let version = version.strip_prefix("5.5.5-").unwrap_or(&version);
+            ("MariaDB", version.to_owned())
+        } else {
+            ("MySQL", version)
+        };
+        findings.push(Finding::new("version", version));
+
+        let connection_id = take(&mut payload, 4)?;
+        findings.push(Finding::new(
+            "connection id",
+            u32::from_le_bytes([
+                connection_id[0],
+                connection_id[1],
+                connection_id[2],
+                connection_id[3],
+            ])
+            .to_string(),
+        ));
+
+        // First part of the scramble and its filler
+        take(&mut payload, 9)?;
+        let lower = take(&mut payload, 2)?;
+        let mut capabilities = u32::from(u16::from_le_bytes([lower[0], lower[1]]));
+        // Servers before 4.1 stop here
+        if let Some(extended) = take(&mut payload, 16) {
+            // character set and status flags come first
+            capabilities |= u32::from(u16::from_le_bytes([extended[3], extended[4]])) << 16;
+            let scramble_len = extended[5] as usize;
+            // Second part of the scramble, then the authentication plugin name
+            let scramble_len = scramble_len.saturating_sub(8).max(13);
+            if take(&mut payload, scramble_len).is_some() {
+                let plugin = null_terminated(&mut payload)
+                    .unwrap_or_else(|| String::from_utf8_lossy(payload).into_owned());
+                if !plugin.is_empty() {
+                    findings.push(Finding::new("auth plugin", plugin));
+                }
+            }
+        }
+
+        let names: Vec<&str> = CAPABILITIES
+            .iter()
+            .filter(|(flag, _)| capabilities & flag != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        findings.push(Finding::new(
+            "capabilities",
+            format!("0x{:08x} ({})", capabilities, names.join(", ")),
+        ));
+        findings.push(Finding::new(
+            "tls",
+            if capabilities & CLIENT_SSL != 0 {
+                "supported"
+            } else {
+                "not supported"
+            },
+        ));
+
+        Some((product, findings))
+    }
+
+    async fn read_packet(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header[..]).await?;
+        let length = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        if length > MAX_PACKET_SIZE || header[3] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a MySQL handshake",
+            ));
+        }
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload[..]).await?;
+        Ok(payload)
+    }
+}
+
+impl Probe for MysqlProbe {
+    fn name(&self) -> &'static str {
+        "mysql"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 3306 | 3307)
+    }
+
+    fn accepts_banner(&self, banner: &[u8]) -> bool {
+        is_greeting(banner)
+    }
+
+    fn expects_banner(&self) -> bool {
+        true
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            let payload = Self::read_packet(&mut stream).await?;
+
+            match payload.first() {
+                Some(&PROTOCOL_VERSION) => match Self::greeting_findings(&payload[1..]) {
+                    Some((product, findings)) => {
+                        Ok(ProbeStatus::Found(Service::new(product, findings)))
+                    }
+                    None => Ok(ProbeStatus::Unknown),
+                },
+                Some(&ERR_PACKET) => Ok(ProbeStatus::Found(Service::new(
+                    "MySQL",
+                    Self::error_findings(&payload[1..]),
+                ))),
+                _ => Ok(ProbeStatus::Unknown),
+            }
+        })
+    }
+}
@@ -0,0 +1,78 @@
+//! NATS servers greet clients with an `INFO` line carrying a JSON object.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const INFO_PREFIX: &[u8] = b"INFO {";
+
+const MAX_INFO_SIZE: u64 = 64 * 1024;
+
+/// Fields of the INFO object worth reporting
+const INFO_FIELDS: [&str; 13] = [
+    "server_name",
+    "server_id",
+    "version",
+    "go",
+    "proto",
+    "git_commit",
+    "cluster",
+    "auth_required",
+    "tls_required",
+    "tls_available",
+    "jetstream",
+    "headers",
+    "max_payload",
+];
+
+pub struct NatsProbe;
+
+impl Probe for NatsProbe {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 4222)
+    }
+
+    fn accepts_banner(&self, banner: &[u8]) -> bool {
+        banner.starts_with(INFO_PREFIX)
+    }
+
+    fn expects_banner(&self) -> bool {
+        true
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let stream = TcpStream::connect(&peer_addr).await?;
+            let mut reader = BufReader::new(stream).take(MAX_INFO_SIZE);
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+
+            let info = match line
+                .strip_prefix("INFO ")
+                .and_then(|json| serde_json::from_str::<serde_json::Value>(json.trim()).ok())
+            {
+                Some(serde_json::Value::Object(info)) => info,
+                _ => return Ok(ProbeStatus::Unknown),
+            };
+
+            let mut findings = Vec::new();
+            for name in INFO_FIELDS.iter() {
+                let value = match info.get(*name) {
+                    Some(serde_json::Value::String(value)) => value.clone(),
+                    Some(serde_json::Value::Null) | None => continue,
+                    Some(value) => value.to_string(),
+                };
+                findings.push(Finding::new(*name, value));
+            }
+
+            Ok(ProbeStatus::Found(Service::new("NATS", findings)))
+        })
+    }
+}
@@ -0,0 +1,99 @@
+//! NTLM authentication leaks the server's names and OS build in its CHALLENGE
+//! message, which is sent in answer to a NEGOTIATE message before any credential.
+
+use super::Finding;
+
+const SIGNATURE: &[u8] = b"NTLMSSP\0";
+
+const MESSAGE_CHALLENGE: u32 = 2;
+
+/// UNICODE, OEM, REQUEST_TARGET, NTLM, ALWAYS_SIGN, EXTENDED_SESSIONSECURITY,
+/// TARGET_INFO, VERSION, 128 and 56
+const NEGOTIATE_FLAGS: u32 = 0xa288_8207;
+
+/// Attributes of the target information, as (id, finding's key)
+const AV_PAIRS: [(u16, &str); 5] = [
+    (1, "netbios computer"),
+    (2, "netbios domain"),
+    (3, "dns computer"),
+    (4, "dns domain"),
+    (5, "dns forest"),
+];
+
+const AV_EOL: u16 = 0;
+
+/// NEGOTIATE message, without domain nor workstation
+pub(super) fn negotiate_message() -> Vec<u8> {
+    let mut message = SIGNATURE.to_vec();
+    message.extend_from_slice(&1u32.to_le_bytes());
+    message.extend_from_slice(&NEGOTIATE_FLAGS.to_le_bytes());
+    // empty domain and workstation fields
+    message.extend_from_slice(&[0u8; 16]);
+    // version 6.1 build 7601, NTLM revision 15
+    message.extend_from_slice(&[6, 1, 0xb1, 0x1d, 0, 0, 0, 15]);
+    message
+}
+
+fn utf16(data: &[u8]) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units[..])
+}
+
+/// Finds a CHALLENGE message in `data`, whatever wraps it (SPNEGO, CredSSP, ...), and
+/// describes the server
+pub(super) fn challenge_findings(data: &[u8]) -> Option<Vec<Finding>> {
+    let start = data
+        .windows(SIGNATURE.len())
+        .position(|window| window == SIGNATURE)?;
+    let message = &data[start..];
+    let u16_at = |index: usize| {
+        message
+            .get(index..index + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_at = |index: usize| {
+        message
+            .get(index..index + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if u32_at(8)? != MESSAGE_CHALLENGE {
+        return None;
+    }
+
+    let mut findings = Vec::new();
+    let info_len = u16_at(40)? as usize;
+    let info_offset = u32_at(44)? as usize;
+    let mut info = message
+        .get(info_offset..info_offset + info_len)
+        .unwrap_or_default();
+    while info.len() >= 4 {
+        let id = u16::from_le_bytes([info[0], info[1]]);
+        let len = u16::from_le_bytes([info[2], info[3]]) as usize;
+        if id == AV_EOL || info.len() < len + 4 {
+            break;
+        }
+        if let Some((_, key)) = AV_PAIRS.iter().find(|(i, _)| *i == id) {
+            findings.push(Finding::new(*key, utf16(&info[4..4 + len])));
+        }
+        info = &info[4 + len..];
+    }
+
+    // The version is only there when negotiated, before the payload
+    if info_offset >= 56 {
+        if let Some(version) = message.get(48..56) {
+            findings.push(Finding::new(
+                "os version",
+                format!(
+                    "{}.{} build {}",
+                    version[0],
+                    version[1],
+                    u16::from_le_bytes([version[2], version[3]])
+                ),
+            ));
+        }
+    }
+    Some(findings)
+}
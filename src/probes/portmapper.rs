@@ -0,0 +1,319 @@
+//! The ONC RPC portmapper (rpcbind) lists the RPC programs registered on a host, among
+//! them the NFS mount daemon, which lists the exported file systems.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const RPC_VERSION: u32 = 2;
+const MSG_CALL: u32 = 0;
+const MSG_REPLY: u32 = 1;
+const MSG_ACCEPTED: u32 = 0;
+const SUCCESS: u32 = 0;
+const AUTH_NULL: u32 = 0;
+
+const PMAP_PROGRAM: u32 = 100_000;
+const PMAP_VERSION: u32 = 2;
+const PMAPPROC_DUMP: u32 = 4;
+
+const MOUNT_PROGRAM: u32 = 100_005;
+const MOUNTPROC_EXPORT: u32 = 5;
+
+const IPPROTO_TCP: u32 = 6;
+const IPPROTO_UDP: u32 = 17;
+
+/// Last fragment flag of the record marking
+const LAST_FRAGMENT: u32 = 0x8000_0000;
+
+const MAX_RECORD_SIZE: usize = 256 * 1024;
+const MAX_FRAGMENTS: usize = 64;
+
+/// Well known RPC programs
+const PROGRAMS: [(u32, &str); 16] = [
+    (100_000, "portmapper"),
+    (100_001, "rstatd"),
+    (100_002, "rusersd"),
+    (100_003, "nfs"),
+    (100_004, "ypserv"),
+    (100_005, "mountd"),
+    (100_007, "ypbind"),
+    (100_008, "walld"),
+    (100_009, "yppasswdd"),
+    (100_011, "rquotad"),
+    (100_021, "nlockmgr"),
+    (100_024, "status"),
+    (100_068, "cmsd"),
+    (100_083, "ttdbserverd"),
+    (100_227, "nfs_acl"),
+    (150_001, "pcnfsd"),
+];
+
+pub struct PortmapperProbe;
+
+/// A program registered to the portmapper
+struct Mapping {
+    program: u32,
+    version: u32,
+    protocol: u32,
+    port: u32,
+}
+
+/// Reader of XDR encoded data
+struct Xdr<'a> {
+    data: &'a [u8],
+}
+
+impl Xdr<'_> {
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.data.get(..4)?;
+        self.data = &self.data[4..];
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Variable length opaque data, padded to 4 bytes
+    fn opaque(&mut self) -> Option<&[u8]> {
+        let len = self.u32()? as usize;
+        let padded = len.checked_add(3)? & !3;
+        let data = self.data;
+        self.data = data.get(padded..)?;
+        Some(&data[..len])
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.opaque()
+            .map(|data| String::from_utf8_lossy(data).into_owned())
+    }
+
+    /// Optional data announcing the items of a list
+    fn follows(&mut self) -> Option<bool> {
+        Some(self.u32()? != 0)
+    }
+}
+
+fn call(xid: u32, program: u32, version: u32, procedure: u32) -> Vec<u8> {
+    let mut message = Vec::new();
+    for field in [
+        xid,
+        MSG_CALL,
+        RPC_VERSION,
+        program,
+        version,
+        procedure,
+        // credentials and verifier
+        AUTH_NULL,
+        0,
+        AUTH_NULL,
+        0,
+    ] {
+        message.extend_from_slice(&field.to_be_bytes());
+    }
+    let mut record = (LAST_FRAGMENT | message.len() as u32)
+        .to_be_bytes()
+        .to_vec();
+    record.extend_from_slice(&message[..]);
+    record
+}
+
+/// Reads a record, made of fragments
+async fn read_record(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut record = Vec::new();
+    for _ in 0..MAX_FRAGMENTS {
+        let header = stream.read_u32().await?;
+        let size = (header & !LAST_FRAGMENT) as usize;
+        if record.len() + size > MAX_RECORD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "RPC record too large",
+            ));
+        }
+        let start = record.len();
+        record.resize(start + size, 0);
+        stream.read_exact(&mut record[start..]).await?;
+        if header & LAST_FRAGMENT != 0 {
+            return Ok(record);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "RPC record too fragmented",
+    ))
+}
+
+/// Calls a procedure without arguments, returns the results of a successful reply
+async fn rpc(
+    stream: &mut TcpStream,
+    program: u32,
+    version: u32,
+    procedure: u32,
+) -> io::Result<Option<Vec<u8>>> {
+    let xid = rand::random::<u32>();
+    stream
+        .write_all(&call(xid, program, version, procedure)[..])
+        .await?;
+    let record = read_record(stream).await?;
+
+    let mut reply = Xdr { data: &record[..] };
+    let accepted = (|| {
+        if reply.u32()? != xid || reply.u32()? != MSG_REPLY || reply.u32()? != MSG_ACCEPTED {
+            return None;
+        }
+        // verifier
+        reply.u32()?;
+        reply.opaque()?;
+        Some(reply.u32()? == SUCCESS)
+    })();
+    Ok(match accepted {
+        Some(true) => Some(reply.data.to_vec()),
+        _ => None,
+    })
+}
+
+fn dump_mappings(results: &[u8]) -> Option<Vec<Mapping>> {
+    let mut xdr = Xdr { data: results };
+    let mut mappings = Vec::new();
+    while xdr.follows()? {
+        mappings.push(Mapping {
+            program: xdr.u32()?,
+            version: xdr.u32()?,
+            protocol: xdr.u32()?,
+            port: xdr.u32()?,
+        });
+    }
+    Some(mappings)
+}
+
+/// Reads the exported directories, with the groups allowed to mount them
+fn exports(results: &[u8]) -> Option<Vec<(String, Vec<String>)>> {
+    let mut xdr = Xdr { data: results };
+    let mut exports = Vec::new();
+    while xdr.follows()? {
+        let directory = xdr.string()?;
+        let mut groups = Vec::new();
+        while xdr.follows()? {
+            groups.push(xdr.string()?);
+        }
+        exports.push((directory, groups));
+    }
+    Some(exports)
+}
+
+fn program_name(program: u32) -> String {
+    match PROGRAMS.iter().find(|(p, _)| *p == program) {
+        Some((_, name)) => format!("{} {}", program, name),
+        None => program.to_string(),
+    }
+}
+
+fn protocol_name(protocol: u32) -> String {
+    match protocol {
+        IPPROTO_TCP => "tcp".to_owned(),
+        IPPROTO_UDP => "udp".to_owned(),
+        _ => format!("protocol {}", protocol),
+    }
+}
+
+/// Describes the registered programs, one finding per program
+fn program_findings(mappings: &[Mapping]) -> Vec<Finding> {
+    let mut programs: BTreeMap<u32, (Vec<u32>, Vec<String>)> = BTreeMap::new();
+    for mapping in mappings.iter() {
+        let (versions, endpoints) = programs.entry(mapping.program).or_default();
+        if !versions.contains(&mapping.version) {
+            versions.push(mapping.version);
+        }
+        let endpoint = format!("{}/{}", protocol_name(mapping.protocol), mapping.port);
+        if !endpoints.contains(&endpoint) {
+            endpoints.push(endpoint);
+        }
+    }
+
+    let mut findings = vec![Finding::new("programs", programs.len().to_string())];
+    for (program, (mut versions, endpoints)) in programs {
+        versions.sort_unstable();
+        let versions: Vec<String> = versions.iter().map(u32::to_string).collect();
+        findings.push(Finding::new(
+            "program",
+            format!(
+                "{} v{} ({})",
+                program_name(program),
+                versions.join(","),
+                endpoints.join(", ")
+            ),
+        ));
+    }
+    findings
+}
+
+impl PortmapperProbe {
+    /// Asks the mount daemon for its exports, over TCP
+    async fn export_findings(peer_addr: SocketAddr, mappings: &[Mapping]) -> Option<Vec<Finding>> {
+        // Every version of the MOUNT protocol has the same EXPORT procedure
+        let mountd = mappings
+            .iter()
+            .filter(|m| m.program == MOUNT_PROGRAM && m.protocol == IPPROTO_TCP)
+            .filter(|m| (1..=3).contains(&m.version))
+            .max_by_key(|m| m.version)?;
+        let port = u16::try_from(mountd.port).ok()?;
+        let mut stream = TcpStream::connect(SocketAddr::new(peer_addr.ip(), port))
+            .await
+            .ok()?;
+        let results = rpc(&mut stream, MOUNT_PROGRAM, mountd.version, MOUNTPROC_EXPORT)
+            .await
+            .ok()??;
+
+        let exports = exports(&results[..])?;
+        let mut findings = vec![Finding::new("exports", exports.len().to_string())];
+        for (directory, groups) in exports {
+            findings.push(Finding::new(
+                "export",
+                if groups.is_empty() {
+                    format!("{} (everyone)", directory)
+                } else {
+                    format!("{} ({})", directory, groups.join(", "))
+                },
+            ));
+        }
+        Some(findings)
+    }
+}
+
+impl Probe for PortmapperProbe {
+    fn name(&self) -> &'static str {
+        "portmapper"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 111)
+    }
+
+    fn rarity(&self) -> u8 {
+        8
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            let mappings = match rpc(&mut stream, PMAP_PROGRAM, PMAP_VERSION, PMAPPROC_DUMP)
+                .await?
+                .and_then(|results| dump_mappings(&results[..]))
+            {
+                Some(mappings) => mappings,
+                None => return Ok(ProbeStatus::Unknown),
+            };
+
+            let mut findings = program_findings(&mappings);
+            if let Some(export_findings) = Self::export_findings(peer_addr, &mappings).await {
+                findings.extend(export_findings);
+            }
+
+            Ok(ProbeStatus::Found(Service::new(
+                "ONC RPC portmapper",
+                findings,
+            )))
+        })
+    }
+}
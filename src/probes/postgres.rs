@@ -0,0 +1,122 @@
+//! PostgreSQL rejects a startup message asking for a protocol it does not know with
+//! an error telling the versions it supports, before any authentication.
+
+use std::io;
+use std::net::SocketAddr;
+
+use regex::Regex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+/// Startup message for protocol 4.0, which no server implements
+const UNSUPPORTED_STARTUP: [u8; 8] = [0, 0, 0, 8, 0, 4, 0, 0];
+
+/// Asks the server whether it accepts TLS, before any startup message
+const SSL_REQUEST: [u8; 8] = [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f];
+
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+pub struct PostgresProbe;
+
+impl PostgresProbe {
+    /// Reads an ErrorResponse as (field type, value)
+    async fn read_error(stream: &mut TcpStream) -> io::Result<Option<Vec<(u8, String)>>> {
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header[..]).await?;
+        let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if header[0] != b'E' || !(5..=MAX_MESSAGE_SIZE).contains(&length) {
+            return Ok(None);
+        }
+        let mut body = vec![0u8; length - 4];
+        stream.read_exact(&mut body[..]).await?;
+
+        let mut fields = Vec::new();
+        let mut data = &body[..];
+        while let Some((&kind, rest)) = data.split_first() {
+            if kind == 0 {
+                break;
+            }
+            let end = match rest.iter().position(|&b| b == 0) {
+                Some(end) => end,
+                None => return Ok(None),
+            };
+            fields.push((kind, String::from_utf8_lossy(&rest[..end]).into_owned()));
+            data = &rest[end + 1..];
+        }
+        Ok(Some(fields))
+    }
+
+    fn error_findings(fields: &[(u8, String)]) -> Vec<Finding> {
+        let field = |kind: u8| {
+            fields
+                .iter()
+                .find(|(k, _)| *k == kind)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let mut findings = Vec::new();
+        let message = field(b'M').unwrap_or_default();
+        let versions = Regex::new(r"(\d+\.\d+) to (\d+\.\d+)").expect("valid regex");
+        if let Some(captures) = versions.captures(message) {
+            findings.push(Finding::new(
+                "protocol",
+                format!("{} to {}", &captures[1], &captures[2]),
+            ));
+            // The old protocol 2.0 was removed in PostgreSQL 14
+            findings.push(Finding::new(
+                "version",
+                if &captures[1] == "3.0" {
+                    ">= 14"
+                } else {
+                    "< 14"
+                },
+            ));
+        }
+        findings.push(Finding::new("error", message));
+        // Source location of the error, which changes with releases
+        if let (Some(file), Some(line)) = (field(b'F'), field(b'L')) {
+            findings.push(Finding::new(
+                "error source",
+                format!("{}:{} ({})", file, line, field(b'R').unwrap_or_default()),
+            ));
+        }
+        findings
+    }
+}
+
+impl Probe for PostgresProbe {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 5432 | 5433)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            stream.write_all(&UNSUPPORTED_STARTUP[..]).await?;
+            let fields = match Self::read_error(&mut stream).await? {
+                // SQLSTATE feature_not_supported
+                Some(fields) if fields.iter().any(|f| *f == (b'C', "0A000".to_owned())) => fields,
+                _ => return Ok(ProbeStatus::Unknown),
+            };
+            let mut findings = Self::error_findings(&fields);
+
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            stream.write_all(&SSL_REQUEST[..]).await?;
+            let mut answer = [0u8; 1];
+            let tls = match stream.read_exact(&mut answer[..]).await {
+                Ok(_) if answer[0] == b'S' => "supported",
+                Ok(_) if answer[0] == b'N' => "not supported",
+                _ => "unknown",
+            };
+            findings.push(Finding::new("tls", tls));
+
+            Ok(ProbeStatus::Found(Service::new("PostgreSQL", findings)))
+        })
+    }
+}
@@ -0,0 +1,214 @@
+//! RDP servers negotiate their security protocol in the X.224 connection request,
+//! and leak their names in the NTLM challenge of CredSSP.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{ber, ntlm, tls::TlsProbe, Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const TPKT_VERSION: u8 = 3;
+const X224_CONNECTION_REQUEST: u8 = 0xe0;
+const X224_CONNECTION_CONFIRM: u8 = 0xd0;
+
+const NEG_REQ: u8 = 0x01;
+const NEG_RSP: u8 = 0x02;
+const NEG_FAILURE: u8 = 0x03;
+
+const PROTOCOL_RDP: u32 = 0x0;
+const PROTOCOL_SSL: u32 = 0x1;
+const PROTOCOL_HYBRID: u32 = 0x2;
+const PROTOCOL_RDSTLS: u32 = 0x4;
+const PROTOCOL_HYBRID_EX: u32 = 0x8;
+
+/// Security protocols, tried one at a time as (requested, expected selection), the
+/// extended CredSSP is only requested along with the protocols it builds upon
+const PROTOCOLS: [(u32, u32, &str); 5] = [
+    (PROTOCOL_RDP, PROTOCOL_RDP, "RDP"),
+    (PROTOCOL_SSL, PROTOCOL_SSL, "TLS"),
+    (PROTOCOL_HYBRID, PROTOCOL_HYBRID, "CredSSP (NLA)"),
+    (
+        PROTOCOL_HYBRID_EX | PROTOCOL_HYBRID | PROTOCOL_SSL,
+        PROTOCOL_HYBRID_EX,
+        "CredSSP with early user authorization",
+    ),
+    (PROTOCOL_RDSTLS, PROTOCOL_RDSTLS, "RDSTLS"),
+];
+
+const NEG_RSP_FLAGS: [(u8, &str); 4] = [
+    (0x01, "EXTENDED_CLIENT_DATA"),
+    (0x02, "DYNVC_GFX"),
+    (0x08, "RESTRICTED_ADMIN"),
+    (0x10, "REDIRECTED_AUTHENTICATION"),
+];
+
+/// Version of the CredSSP TSRequest we send
+const CREDSSP_VERSION: u8 = 6;
+
+const MAX_TSREQUEST_SIZE: usize = 16 * 1024;
+
+pub struct RdpProbe;
+
+/// Answer to an X.224 connection request
+enum Negotiation {
+    /// Protocol selected by the server, with its flags
+    Selected(u32, u8),
+    Failure(u32),
+    /// Servers before RDP 5.2 do not negotiate, they only know standard RDP security
+    None,
+}
+
+fn connection_request(protocols: u32) -> Vec<u8> {
+    let mut x224 = vec![0, X224_CONNECTION_REQUEST, 0, 0, 0, 0, 0];
+    x224.extend_from_slice(&[NEG_REQ, 0, 8, 0]);
+    x224.extend_from_slice(&protocols.to_le_bytes());
+    // length indicator, which does not count itself
+    x224[0] = (x224.len() - 1) as u8;
+
+    let mut packet = vec![TPKT_VERSION, 0];
+    packet.extend_from_slice(&((x224.len() + 4) as u16).to_be_bytes());
+    packet.extend_from_slice(&x224[..]);
+    packet
+}
+
+fn failure_reason(code: u32) -> String {
+    match code {
+        1 => "TLS required".to_owned(),
+        2 => "TLS not allowed".to_owned(),
+        3 => "no certificate".to_owned(),
+        4 => "inconsistent flags".to_owned(),
+        5 => "CredSSP required".to_owned(),
+        6 => "TLS with user authentication required".to_owned(),
+        _ => format!("failure {}", code),
+    }
+}
+
+impl RdpProbe {
+    async fn negotiate(stream: &mut TcpStream, protocols: u32) -> io::Result<Option<Negotiation>> {
+        stream.write_all(&connection_request(protocols)[..]).await?;
+
+        let mut tpkt = [0u8; 4];
+        stream.read_exact(&mut tpkt[..]).await?;
+        let length = u16::from_be_bytes([tpkt[2], tpkt[3]]) as usize;
+        if tpkt[0] != TPKT_VERSION || !(11..=512).contains(&length) {
+            return Ok(None);
+        }
+        let mut x224 = vec![0u8; length - 4];
+        stream.read_exact(&mut x224[..]).await?;
+        if x224[1] & 0xf0 != X224_CONNECTION_CONFIRM {
+            return Ok(None);
+        }
+
+        Ok(Some(match x224.get(7..15) {
+            Some(neg) if neg[0] == NEG_RSP => {
+                Negotiation::Selected(u32::from_le_bytes([neg[4], neg[5], neg[6], neg[7]]), neg[1])
+            }
+            Some(neg) if neg[0] == NEG_FAILURE => {
+                Negotiation::Failure(u32::from_le_bytes([neg[4], neg[5], neg[6], neg[7]]))
+            }
+            _ => Negotiation::None,
+        }))
+    }
+
+    /// Starts CredSSP with an NTLM NEGOTIATE message, returns the server's challenge
+    async fn ntlm_findings(peer_addr: SocketAddr) -> Option<Vec<Finding>> {
+        let mut stream = TcpStream::connect(&peer_addr).await.ok()?;
+        match Self::negotiate(&mut stream, PROTOCOL_SSL | PROTOCOL_HYBRID)
+            .await
+            .ok()??
+        {
+            Negotiation::Selected(PROTOCOL_HYBRID, _) => {}
+            _ => return None,
+        }
+        let mut stream = TlsProbe::connect_over(stream, None, &[]).await?;
+
+        let token = ber::encode(
+            0xa0,
+            &ber::encode(ber::OCTET_STRING, &ntlm::negotiate_message()),
+        );
+        let nego_data = ber::encode(ber::SEQUENCE, &ber::encode(ber::SEQUENCE, &token));
+        let version = ber::encode(0xa0, &ber::encode(ber::INTEGER, &[CREDSSP_VERSION]));
+        let request = ber::encode(
+            ber::SEQUENCE,
+            &[version, ber::encode(0xa1, &nego_data)].concat(),
+        );
+        stream.write_all(&request[..]).await.ok()?;
+
+        let response = ber::read(&mut stream, MAX_TSREQUEST_SIZE).await.ok()?;
+        ntlm::challenge_findings(&response[..])
+    }
+}
+
+impl Probe for RdpProbe {
+    fn name(&self) -> &'static str {
+        "rdp"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 3389)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        let prefered_port = self.is_prefered_port(peer_addr.port());
+        Box::pin(async move {
+            let mut supported = Vec::new();
+            let mut refused = Vec::new();
+            let mut flags = 0;
+            let mut recognized = false;
+            for (requested, expected, name) in PROTOCOLS.iter() {
+                let mut stream = TcpStream::connect(&peer_addr).await?;
+                match Self::negotiate(&mut stream, *requested).await {
+                    Ok(Some(Negotiation::Selected(selected, selected_flags))) => {
+                        recognized = true;
+                        if selected == *expected {
+                            supported.push(*name);
+                            flags |= selected_flags;
+                        }
+                    }
+                    Ok(Some(Negotiation::Failure(code))) => {
+                        recognized = true;
+                        refused.push(format!("{} ({})", name, failure_reason(code)));
+                    }
+                    // Any X.224 service confirms a connection, only RDP negotiates
+                    Ok(Some(Negotiation::None)) if prefered_port => {
+                        supported.push(*name);
+                        break;
+                    }
+                    _ if !recognized => return Ok(ProbeStatus::Unknown),
+                    _ => {}
+                }
+            }
+
+            let mut findings = vec![Finding::new("security", supported.join(", "))];
+            if !refused.is_empty() {
+                findings.push(Finding::new("refused", refused.join(", ")));
+            }
+            let nla_required = supported.iter().all(|name| name.starts_with("CredSSP"));
+            if !supported.is_empty() {
+                findings.push(Finding::new(
+                    "nla",
+                    if nla_required {
+                        "required"
+                    } else {
+                        "not required"
+                    },
+                ));
+            }
+            let names: Vec<&str> = NEG_RSP_FLAGS
+                .iter()
+                .filter(|(flag, _)| flags & flag != 0)
+                .map(|(_, name)| *name)
+                .collect();
+            if !names.is_empty() {
+                findings.push(Finding::new("flags", names.join(", ")));
+            }
+            if let Some(ntlm_findings) = Self::ntlm_findings(peer_addr).await {
+                findings.extend(ntlm_findings);
+            }
+
+            Ok(ProbeStatus::Found(Service::new("RDP", findings)))
+        })
+    }
+}
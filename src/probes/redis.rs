@@ -0,0 +1,100 @@
+//! Redis (and its forks) answers `INFO server` without authentication unless a
+//! password is set, in which case the error tells us so.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+/// `INFO server`, as a RESP array of bulk strings
+const INFO_SERVER: &[u8] = b"*2\r\n$4\r\nINFO\r\n$6\r\nserver\r\n";
+
+const MAX_REPLY_SIZE: usize = 64 * 1024;
+
+/// Fields of the server section worth reporting, with their finding's key
+const INFO_FIELDS: [(&str, &str); 5] = [
+    ("redis_version", "version"),
+    ("valkey_version", "valkey version"),
+    ("redis_mode", "mode"),
+    ("os", "os"),
+    ("executable", "executable"),
+];
+
+pub struct RedisProbe;
+
+impl RedisProbe {
+    /// Reads a reply, either an error or a bulk string
+    async fn read_reply(stream: TcpStream) -> io::Result<Result<String, String>> {
+        let mut reader = BufReader::new(stream).take(MAX_REPLY_SIZE as u64);
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Not a RESP reply");
+
+        if let Some(error) = line.strip_prefix('-') {
+            return Ok(Err(error.trim_end().to_owned()));
+        }
+        let length = line
+            .strip_prefix('$')
+            .and_then(|length| length.trim_end().parse::<usize>().ok())
+            .filter(|length| *length <= MAX_REPLY_SIZE)
+            .ok_or_else(invalid)?;
+        let mut bulk = vec![0u8; length];
+        reader.read_exact(&mut bulk[..]).await?;
+        Ok(Ok(String::from_utf8_lossy(&bulk[..]).into_owned()))
+    }
+
+    fn info_findings(info: &str) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for (field, key) in INFO_FIELDS.iter() {
+            let value = info
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name == field)
+                .map(|(_, value)| value.trim());
+            if let Some(value) = value {
+                findings.push(Finding::new(*key, value));
+            }
+        }
+        findings.push(Finding::new("auth", "not required"));
+        findings
+    }
+}
+
+impl Probe for RedisProbe {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 6379 | 6380)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            stream.write_all(INFO_SERVER).await?;
+
+            let findings = match Self::read_reply(stream).await? {
+                Ok(info) if info.contains("redis_version:") => Self::info_findings(&info),
+                Ok(_) => return Ok(ProbeStatus::Unknown),
+                Err(error) if error.starts_with("NOAUTH") => {
+                    vec![Finding::new("auth", "required")]
+                }
+                // Without a password, remote clients are refused in protected mode
+                Err(error) if error.starts_with("DENIED") => {
+                    vec![Finding::new("auth", "protected mode")]
+                }
+                // Servers before 2.8 had no NOAUTH error
+                Err(error) if error == "ERR operation not permitted" => {
+                    vec![Finding::new("auth", "required")]
+                }
+                Err(_) => return Ok(ProbeStatus::Unknown),
+            };
+
+            Ok(ProbeStatus::Found(Service::new("Redis", findings)))
+        })
+    }
+}
@@ -0,0 +1,164 @@
+//! The probes checking services, in the order they run. The built-in probes are registered
+//! by default; others can be added, with a priority placing them ahead or behind.
+
+use super::{
+    amqp, bacnet, consul, dnp3, dns, docker, epm, etcd, ftp, grpc, http, kafka, kubernetes, ldap,
+    memcached, modbus, mongodb, mqtt, mssql, mysql, nats, portmapper, postgres, rdp, redis, rsync,
+    s7, smb, telnet, tls, vnc, x11, BoxedProbe,
+};
+
+/// Priority of the built-in probes
+pub const DEFAULT_PRIORITY: i32 = 0;
+
+struct Entry {
+    probe: BoxedProbe,
+    priority: i32,
+    enabled: bool,
+}
+
+pub struct ProbeRegistry {
+    /// By decreasing priority, then in the order of registration
+    entries: Vec<Entry>,
+}
+
+impl ProbeRegistry {
+    /// A registry without any probe
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds a probe, after the ones of the same priority
+    pub fn register(&mut self, probe: BoxedProbe) -> &mut Self {
+        self.register_with_priority(probe, DEFAULT_PRIORITY)
+    }
+
+    /// Adds a probe, after the ones of higher or the same priority and before the others
+    pub fn register_with_priority(&mut self, probe: BoxedProbe, priority: i32) -> &mut Self {
+        self.insert(Entry {
+            probe,
+            priority,
+            enabled: true,
+        });
+        self
+    }
+
+    /// Moves the probes of this name after the ones of higher or the same priority and
+    /// before the others, returns whether there was one
+    pub fn set_priority(&mut self, name: &str, priority: i32) -> bool {
+        let (moved, kept) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|entry| entry.probe.name() == name);
+        self.entries = kept;
+        let moved: Vec<Entry> = moved;
+        let found = !moved.is_empty();
+        for entry in moved {
+            self.insert(Entry { priority, ..entry });
+        }
+        found
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        let index = self
+            .entries
+            .iter()
+            .position(|other| other.priority < entry.priority)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(index, entry);
+    }
+
+    /// Disables the probes of this name, returns whether there was one
+    pub fn disable(&mut self, name: &str) -> bool {
+        let mut found = false;
+        for entry in self.entries.iter_mut() {
+            if entry.probe.name() == name {
+                entry.enabled = false;
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Names of the registered probes, enabled or not
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        for entry in self.entries.iter() {
+            if !names.contains(&entry.probe.name()) {
+                names.push(entry.probe.name());
+            }
+        }
+        names
+    }
+
+    /// The enabled probes, in the order they run
+    pub fn probes(&self) -> impl Iterator<Item = &BoxedProbe> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.enabled)
+            .map(|entry| &entry.probe)
+    }
+}
+
+impl Default for ProbeRegistry {
+    /// The built-in probes
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry
+            // Before HTTP, which would recognize its HTTP/2 transport
+            .register(Box::new(grpc::GrpcProbe))
+            // Before HTTP too, which would recognize their HTTP APIs
+            .register(Box::new(docker::DockerProbe))
+            .register(Box::new(kubernetes::KubernetesProbe))
+            .register(Box::new(etcd::EtcdProbe))
+            .register(Box::new(consul::ConsulProbe))
+            .register(Box::new(http::HttpProbe))
+            .register(Box::new(dns::DnsProbe))
+            .register(Box::new(dns::DnsUdpProbe))
+            .register(Box::new(mysql::MysqlProbe))
+            .register(Box::new(postgres::PostgresProbe))
+            .register(Box::new(mssql::MssqlProbe))
+            .register(Box::new(redis::RedisProbe))
+            .register(Box::new(mongodb::MongodbProbe))
+            .register(Box::new(amqp::AmqpProbe))
+            .register(Box::new(mqtt::MqttProbe))
+            .register(Box::new(kafka::KafkaProbe))
+            .register(Box::new(memcached::MemcachedProbe))
+            .register(Box::new(nats::NatsProbe))
+            .register(Box::new(smb::SmbProbe))
+            .register(Box::new(rdp::RdpProbe))
+            .register(Box::new(epm::EpmProbe))
+            .register(Box::new(ldap::LdapProbe))
+            .register(Box::new(ftp::FtpProbe))
+            .register(Box::new(rsync::RsyncProbe))
+            .register(Box::new(portmapper::PortmapperProbe))
+            .register(Box::new(vnc::VncProbe))
+            .register(Box::new(telnet::TelnetProbe))
+            .register(Box::new(x11::X11Probe))
+            .register(Box::new(modbus::ModbusProbe))
+            .register(Box::new(s7::S7Probe))
+            .register(Box::new(bacnet::BacnetProbe))
+            .register(Box::new(dnp3::Dnp3Probe))
+            .register(Box::new(tls::TlsProbe));
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reorders_probes_by_priority() {
+        let mut registry = ProbeRegistry::default();
+        assert_eq!(registry.names()[..2], ["grpc", "docker"]);
+
+        assert!(registry.set_priority("tls", 10));
+        assert!(registry.set_priority("grpc", -1));
+        let names = registry.names();
+        assert_eq!(names[..2], ["tls", "docker"]);
+        assert_eq!(names.last(), Some(&"grpc"));
+
+        assert!(!registry.set_priority("unknown", 10));
+    }
+}
@@ -0,0 +1,109 @@
+//! rsync daemons greet clients with `@RSYNCD: <version>`, and list their modules to
+//! clients asking for `#list` instead of a module.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const GREETING_PREFIX: &str = "@RSYNCD: ";
+
+/// Version we claim, before 31 which requires to negotiate the checksums
+const CLIENT_VERSION: &str = "30.0";
+
+/// Upper bound on the lines of the listing, the message of the day included
+const MAX_LIST_LINES: usize = 1024;
+
+pub struct RsyncProbe;
+
+impl RsyncProbe {
+    async fn read_line(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+    }
+}
+
+impl Probe for RsyncProbe {
+    fn name(&self) -> &'static str {
+        "rsync"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 873)
+    }
+
+    fn accepts_banner(&self, banner: &[u8]) -> bool {
+        banner.starts_with(GREETING_PREFIX.as_bytes())
+    }
+
+    fn expects_banner(&self) -> bool {
+        true
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut reader = BufReader::new(TcpStream::connect(&peer_addr).await?);
+            let greeting = Self::read_line(&mut reader).await?;
+            // Since protocol 31, the version is followed by the supported checksums
+            let mut greeting = match greeting.strip_prefix(GREETING_PREFIX) {
+                Some(greeting) => greeting.split_whitespace(),
+                None => return Ok(ProbeStatus::Unknown),
+            };
+            let mut findings = Vec::new();
+            if let Some(version) = greeting.next() {
+                findings.push(Finding::new("protocol", version));
+            }
+            let digests: Vec<&str> = greeting.collect();
+            if !digests.is_empty() {
+                findings.push(Finding::new("checksums", digests.join(", ")));
+            }
+
+            reader
+                .get_mut()
+                .write_all(format!("{}{}\n#list\n", GREETING_PREFIX, CLIENT_VERSION).as_bytes())
+                .await?;
+            let mut modules = Vec::new();
+            let mut motd = Vec::new();
+            for _ in 0..MAX_LIST_LINES {
+                let line = match Self::read_line(&mut reader).await {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if line == "@RSYNCD: EXIT" {
+                    break;
+                } else if let Some(error) = line.strip_prefix("@ERROR") {
+                    findings.push(Finding::new("error", error.trim_start_matches(':').trim()));
+                    break;
+                } else if let Some((name, comment)) = line.split_once('\t') {
+                    // Names are padded to 15 characters
+                    modules.push((name.trim_end().to_owned(), comment.trim().to_owned()));
+                } else if !line.trim().is_empty() {
+                    motd.push(line);
+                }
+            }
+
+            if !motd.is_empty() {
+                findings.push(Finding::new("motd", motd.join(" ")));
+            }
+            findings.push(Finding::new("modules", modules.len().to_string()));
+            for (name, comment) in modules {
+                findings.push(Finding::new(
+                    "module",
+                    if comment.is_empty() {
+                        name
+                    } else {
+                        format!("{} ({})", name, comment)
+                    },
+                ));
+            }
+
+            Ok(ProbeStatus::Found(Service::new("rsync", findings)))
+        })
+    }
+}
@@ -0,0 +1,216 @@
+//! Siemens S7 PLCs speak S7comm over ISO-on-TCP (TPKT and COTP). Once a connection is set
+//! up, the System Status Lists (SZL) describing the module are readable without password.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const TPKT_VERSION: u8 = 3;
+const COTP_CONNECTION_CONFIRM: u8 = 0xd0;
+const S7_PROTOCOL_ID: u8 = 0x32;
+const S7_ACK_DATA: u8 = 0x03;
+const S7_USER_DATA: u8 = 0x07;
+
+/// Upper bound on TPKT packets, S7 PDUs are negotiated to 240 bytes or a few multiples
+const MAX_TPKT_SIZE: usize = 4096;
+
+/// COTP connection request to rack 0, slot 2 (S7-300/400)
+const CONNECTION_REQUEST: [u8; 22] = [
+    0x03, 0x00, 0x00, 0x16, 0x11, 0xe0, 0x00, 0x00, 0x00, 0x14, 0x00, 0xc1, 0x02, 0x01, 0x00, 0xc2,
+    0x02, 0x01, 0x02, 0xc0, 0x01, 0x0a,
+];
+
+/// Destination TSAP of rack 0, slot 0 (S7-1200/1500), tried after the first one is refused
+const FALLBACK_DST_TSAP: [u8; 2] = [0x02, 0x00];
+const DST_TSAP_OFFSET: usize = 17;
+
+/// Job negotiating the PDU size and the parallel jobs
+const SETUP_COMMUNICATION: [u8; 25] = [
+    0x03, 0x00, 0x00, 0x19, 0x02, 0xf0, 0x80, 0x32, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00,
+    0x00, 0xf0, 0x00, 0x00, 0x01, 0x00, 0x01, 0x01, 0xe0,
+];
+
+/// User data request reading a SZL, whose id is at `SZL_ID_OFFSET`
+const READ_SZL: [u8; 33] = [
+    0x03, 0x00, 0x00, 0x21, 0x02, 0xf0, 0x80, 0x32, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00,
+    0x08, 0x00, 0x01, 0x12, 0x04, 0x11, 0x44, 0x01, 0x00, 0xff, 0x09, 0x00, 0x04, 0x00, 0x11, 0x00,
+    0x01,
+];
+const SZL_ID_OFFSET: usize = 29;
+
+/// Module identification: order number and firmware version
+const SZL_MODULE_ID: u16 = 0x0011;
+/// Component identification: names, serial number, module type
+const SZL_COMPONENT_ID: u16 = 0x001c;
+
+/// Component identification entries worth reporting, as (index, finding's key)
+const COMPONENTS: [(u16, &str); 6] = [
+    (0x0001, "system name"),
+    (0x0002, "module name"),
+    (0x0003, "plant"),
+    (0x0004, "copyright"),
+    (0x0005, "serial number"),
+    (0x0007, "module type"),
+];
+
+pub struct S7Probe;
+
+async fn read_tpkt(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header[..]).await.ok()?;
+    let size = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if header[0] != TPKT_VERSION || !(5..=MAX_TPKT_SIZE).contains(&size) {
+        return None;
+    }
+    let mut payload = vec![0u8; size - 4];
+    stream.read_exact(&mut payload[..]).await.ok()?;
+    Some(payload)
+}
+
+/// S7 PDU of a COTP data packet
+fn s7_pdu(payload: &[u8]) -> Option<&[u8]> {
+    // COTP header: length, DT code, TPDU number
+    let cotp_size = *payload.first()? as usize + 1;
+    let pdu = payload.get(cotp_size..)?;
+    (pdu.first() == Some(&S7_PROTOCOL_ID)).then_some(pdu)
+}
+
+/// Entries of a SZL read response, the parameter and data sizes following the header
+fn szl_entries(pdu: &[u8]) -> Option<Vec<&[u8]>> {
+    if pdu.get(1) != Some(&S7_USER_DATA) || pdu.len() < 10 {
+        return None;
+    }
+    let parameter_size = u16::from_be_bytes([pdu[6], pdu[7]]) as usize;
+    let data = pdu.get(10 + parameter_size..)?;
+    // return code, transport size, length, SZL id, index, entry size, entry count
+    if data.len() < 12 || data[0] != 0xff {
+        return None;
+    }
+    let entry_size = u16::from_be_bytes([data[8], data[9]]) as usize;
+    let entry_count = u16::from_be_bytes([data[10], data[11]]) as usize;
+    if entry_size < 2 {
+        return None;
+    }
+    Some(
+        data[12..]
+            .chunks_exact(entry_size)
+            .take(entry_count)
+            .collect(),
+    )
+}
+
+fn text(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_owned()
+}
+
+fn module_findings(entries: &[&[u8]]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for entry in entries.iter().filter(|entry| entry.len() >= 28) {
+        match u16::from_be_bytes([entry[0], entry[1]]) {
+            0x0001 => findings.push(Finding::new("order number", text(&entry[2..22]))),
+            // firmware, as a 'V' and its three numbers
+            0x0007 if entry[24] == b'V' => findings.push(Finding::new(
+                "firmware",
+                format!("V{}.{}.{}", entry[25], entry[26], entry[27]),
+            )),
+            _ => {}
+        }
+    }
+    findings
+}
+
+fn component_findings(entries: &[&[u8]]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for entry in entries.iter().filter(|entry| entry.len() >= 34) {
+        let index = u16::from_be_bytes([entry[0], entry[1]]);
+        if let Some((_, key)) = COMPONENTS.iter().find(|(i, _)| *i == index) {
+            let value = text(&entry[2..34]);
+            if !value.is_empty() {
+                findings.push(Finding::new(*key, value));
+            }
+        }
+    }
+    findings
+}
+
+impl S7Probe {
+    /// Connects to the CPU, with the second TSAP when the first one is refused
+    async fn connect(peer_addr: SocketAddr) -> std::io::Result<Option<TcpStream>> {
+        let mut request = CONNECTION_REQUEST;
+        for dst_tsap in [None, Some(FALLBACK_DST_TSAP)] {
+            if let Some(dst_tsap) = dst_tsap {
+                request[DST_TSAP_OFFSET..DST_TSAP_OFFSET + 2].copy_from_slice(&dst_tsap);
+            }
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            stream.write_all(&request[..]).await?;
+            match read_tpkt(&mut stream).await {
+                Some(payload) if payload.get(1) == Some(&COTP_CONNECTION_CONFIRM) => {
+                    return Ok(Some(stream));
+                }
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn read_szl(stream: &mut TcpStream, szl_id: u16) -> std::io::Result<Option<Vec<u8>>> {
+        let mut request = READ_SZL;
+        request[SZL_ID_OFFSET..SZL_ID_OFFSET + 2].copy_from_slice(&szl_id.to_be_bytes());
+        stream.write_all(&request[..]).await?;
+        Ok(read_tpkt(stream)
+            .await
+            .and_then(|payload| s7_pdu(&payload[..]).map(|pdu| pdu.to_vec())))
+    }
+}
+
+impl Probe for S7Probe {
+    fn name(&self) -> &'static str {
+        "s7"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 102)
+    }
+
+    fn is_industrial(&self) -> bool {
+        true
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = match Self::connect(peer_addr).await? {
+                Some(stream) => stream,
+                None => return Ok(ProbeStatus::Unknown),
+            };
+
+            stream.write_all(&SETUP_COMMUNICATION[..]).await?;
+            let setup = read_tpkt(&mut stream).await;
+            let pdu_size = match setup.as_deref().and_then(s7_pdu) {
+                Some(pdu) if pdu.get(1) == Some(&S7_ACK_DATA) && pdu.len() >= 20 => {
+                    u16::from_be_bytes([pdu[18], pdu[19]])
+                }
+                _ => return Ok(ProbeStatus::Unknown),
+            };
+
+            let mut findings = vec![Finding::new("pdu size", pdu_size.to_string())];
+            if let Some(pdu) = Self::read_szl(&mut stream, SZL_MODULE_ID).await? {
+                if let Some(entries) = szl_entries(&pdu[..]) {
+                    findings.extend(module_findings(&entries[..]));
+                }
+            }
+            if let Some(pdu) = Self::read_szl(&mut stream, SZL_COMPONENT_ID).await? {
+                if let Some(entries) = szl_entries(&pdu[..]) {
+                    findings.extend(component_findings(&entries[..]));
+                }
+            }
+
+            Ok(ProbeStatus::Found(Service::new("S7comm", findings)))
+        })
+    }
+}
@@ -0,0 +1,516 @@
+//! Probes written as Rhai scripts, loaded from a directory at startup. A script defines
+//! `name()`, `ports()` and `check(target)`, optionally `rarity()` and
+//! `accepts_banner(banner)`. `check` connects through `target`, limited to the probed host,
+//! and returns `()` when it does not recognize the service, otherwise
+//! `#{ protocol: "...", findings: [#{ key: "...", value: "...", severity: "high" }] }`.
+//!
+//! Scripts are sandboxed: no modules, no `eval`, bounded work and data, no other host.
+
+use std::{
+    io,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use rhai::{
+    module_resolvers::DummyModuleResolver, Array, Blob, Dynamic, Engine, EvalAltResult, Map, Scope,
+    AST,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Handle;
+use tokio::time::Instant;
+use tokio_native_tls::TlsStream;
+
+use super::{
+    tls::TlsProbe, BoxedProbe, Finding, Probe, ProbeCheckFuture, ProbeStatus, Service, Severity,
+};
+use crate::defaults::{CONNECT_TIMEOUT, READ_TIMEOUT, SERVER_NAMES};
+use crate::utils::run_with_timeout;
+
+const SCRIPT_EXTENSION: &str = "rhai";
+
+/// Priority of the scripts in the registry, ahead of the built-in probes: they target
+/// services these would miss
+pub const SCRIPT_PRIORITY: i32 = 10;
+
+/// Bounds on the work of a script
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// `accepts_banner` runs on the scan's workers, it may only do little
+const MAX_BANNER_OPERATIONS: u64 = 10_000;
+const MAX_CALL_LEVELS: usize = 32;
+/// Nesting of expressions, at the top level and in functions: set as Rhai's defaults
+/// depend on the build profile
+const MAX_EXPR_DEPTHS: (usize, usize) = (64, 32);
+const MAX_DATA_SIZE: usize = 1 << 20;
+const MAX_ITEMS: usize = 10_000;
+
+/// Upper bound on the data returned by a single `recv`
+const MAX_RECV_SIZE: usize = 64 * 1024;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// A loaded script, with what it declared about itself
+struct Script {
+    name: &'static str,
+    ports: Vec<u16>,
+    rarity: u8,
+    accepts_banner: bool,
+    ast: AST,
+}
+
+pub struct ScriptProbe(Arc<Script>);
+
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| new_engine(MAX_OPERATIONS))
+}
+
+/// The engine running `accepts_banner`, with a much lower bound on the operations
+fn banner_engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| new_engine(MAX_BANNER_OPERATIONS))
+}
+
+fn new_engine(max_operations: u64) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .disable_symbol("eval")
+        .set_max_operations(max_operations)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_expr_depths(MAX_EXPR_DEPTHS.0, MAX_EXPR_DEPTHS.1)
+        .set_max_string_size(MAX_DATA_SIZE)
+        .set_max_array_size(MAX_ITEMS)
+        .set_max_map_size(MAX_ITEMS)
+        .on_print(|_| {})
+        .on_debug(|_, _, _| {});
+
+    engine
+        .register_type_with_name::<Target>("Target")
+        .register_get("host", |target: &mut Target| target.addr.ip().to_string())
+        .register_get("port", |target: &mut Target| target.addr.port() as i64)
+        .register_fn("connect", |target: &mut Target| target.connect(None))
+        .register_fn("connect", |target: &mut Target, port: i64| {
+            target.connect(Some(port))
+        });
+    engine
+        .register_type_with_name::<Socket>("Socket")
+        .register_fn("send", |socket: &mut Socket, data: &str| {
+            socket.send(data.as_bytes())
+        })
+        .register_fn("send", |socket: &mut Socket, data: Blob| {
+            socket.send(&data[..])
+        })
+        .register_fn("recv", Socket::recv)
+        .register_fn("recv_line", Socket::recv_line)
+        .register_fn("tls", Socket::tls)
+        .register_fn("close", Socket::close);
+    engine
+}
+
+fn script_error(message: impl Into<String>) -> Box<EvalAltResult> {
+    message.into().into()
+}
+
+/// What `check` may connect to, until the check's deadline
+#[derive(Clone)]
+struct Target {
+    addr: SocketAddr,
+    handle: Handle,
+    deadline: Instant,
+}
+
+impl Target {
+    /// Time left for an operation, at most `timeout`
+    fn time_left(deadline: Instant, timeout: Duration) -> ScriptResult<Duration> {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(script_error("check timed out"));
+        }
+        Ok(left.min(timeout))
+    }
+
+    /// Connects to the probed port, or another port of the host
+    fn connect(&mut self, port: Option<i64>) -> ScriptResult<Socket> {
+        let mut addr = self.addr;
+        if let Some(port) = port {
+            addr.set_port(u16::try_from(port).map_err(|_| script_error("invalid port"))?);
+        }
+        let timeout = Self::time_left(self.deadline, unsafe { CONNECT_TIMEOUT })?;
+        let stream = self
+            .handle
+            .block_on(run_with_timeout(timeout, TcpStream::connect(addr)))
+            .ok_or_else(|| script_error("connect timed out"))?
+            .map_err(|e| script_error(e.to_string()))?;
+        Ok(Socket {
+            connection: Arc::new(Mutex::new(Connection {
+                stream: Stream::Tcp(stream),
+                pending: Vec::new(),
+            })),
+            handle: self.handle.clone(),
+            deadline: self.deadline,
+        })
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    Closed,
+}
+
+struct Connection {
+    stream: Stream,
+    /// Received data not returned yet, after a line
+    pending: Vec<u8>,
+}
+
+/// A connection of a script, cloned along with the script's values
+#[derive(Clone)]
+struct Socket {
+    connection: Arc<Mutex<Connection>>,
+    handle: Handle,
+    deadline: Instant,
+}
+
+impl Socket {
+    fn send(&mut self, data: &[u8]) -> ScriptResult<()> {
+        let timeout = Target::time_left(self.deadline, unsafe { READ_TIMEOUT })?;
+        let mut connection = self.connection.lock().expect("Dead thread");
+        let written = self.handle.block_on(run_with_timeout(timeout, async {
+            match connection.stream {
+                Stream::Tcp(ref mut stream) => stream.write_all(data).await,
+                Stream::Tls(ref mut stream) => stream.write_all(data).await,
+                Stream::Closed => Err(io::ErrorKind::NotConnected.into()),
+            }
+        }));
+        match written {
+            Some(result) => result.map_err(|e| script_error(e.to_string())),
+            None => Err(script_error("send timed out")),
+        }
+    }
+
+    /// Reads what the connection received, waiting up to `timeout` milliseconds for it,
+    /// empty on timeout or once the connection is closed
+    fn read(
+        connection: &mut Connection,
+        handle: &Handle,
+        timeout: Duration,
+    ) -> ScriptResult<Vec<u8>> {
+        if !connection.pending.is_empty() {
+            return Ok(std::mem::take(&mut connection.pending));
+        }
+        let mut data = vec![0u8; MAX_RECV_SIZE];
+        let read = handle.block_on(run_with_timeout(timeout, async {
+            match connection.stream {
+                Stream::Tcp(ref mut stream) => stream.read(&mut data[..]).await,
+                Stream::Tls(ref mut stream) => stream.read(&mut data[..]).await,
+                Stream::Closed => Ok(0),
+            }
+        }));
+        match read {
+            Some(Ok(size)) => {
+                data.truncate(size);
+                Ok(data)
+            }
+            Some(Err(e)) => Err(script_error(e.to_string())),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn recv(&mut self, timeout: i64) -> ScriptResult<Blob> {
+        let timeout =
+            Target::time_left(self.deadline, Duration::from_millis(timeout.max(0) as u64))?;
+        let mut connection = self.connection.lock().expect("Dead thread");
+        Self::read(&mut connection, &self.handle, timeout)
+    }
+
+    /// Reads a line, without its end of line, empty when none came within `timeout`
+    fn recv_line(&mut self, timeout: i64) -> ScriptResult<String> {
+        let timeout = Duration::from_millis(timeout.max(0) as u64);
+        let mut connection = self.connection.lock().expect("Dead thread");
+        let mut line = Vec::new();
+        loop {
+            let data = Self::read(
+                &mut connection,
+                &self.handle,
+                Target::time_left(self.deadline, timeout)?,
+            )?;
+            if data.is_empty() {
+                break;
+            }
+            match data.iter().position(|b| *b == b'\n') {
+                Some(end) => {
+                    line.extend_from_slice(&data[..end]);
+                    connection.pending = data[end + 1..].to_vec();
+                    break;
+                }
+                None if line.len() + data.len() > MAX_DATA_SIZE => {
+                    return Err(script_error("line too long"));
+                }
+                None => line.extend_from_slice(&data[..]),
+            }
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(String::from_utf8_lossy(&line[..]).into_owned())
+    }
+
+    /// Upgrades the connection to TLS, returns whether the handshake succeeded
+    fn tls(&mut self) -> ScriptResult<bool> {
+        let timeout = Target::time_left(self.deadline, unsafe { READ_TIMEOUT })?;
+        let mut connection = self.connection.lock().expect("Dead thread");
+        let stream = match std::mem::replace(&mut connection.stream, Stream::Closed) {
+            Stream::Tcp(stream) => stream,
+            stream => {
+                connection.stream = stream;
+                return Err(script_error("not a plain TCP connection"));
+            }
+        };
+        connection.pending.clear();
+        let server_name = unsafe { SERVER_NAMES }.first().map(|name| name.as_str());
+        let upgraded = self.handle.block_on(run_with_timeout(
+            timeout,
+            TlsProbe::connect_over(stream, server_name, &[]),
+        ));
+        match upgraded.flatten() {
+            Some(stream) => {
+                connection.stream = Stream::Tls(Box::new(stream));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn close(&mut self) {
+        self.connection.lock().expect("Dead thread").stream = Stream::Closed;
+    }
+}
+
+fn severity(name: &str) -> Option<Severity> {
+    match name {
+        "low" => Some(Severity::Low),
+        "medium" => Some(Severity::Medium),
+        "high" => Some(Severity::High),
+        "critical" => Some(Severity::Critical),
+        _ => None,
+    }
+}
+
+/// Converts a finding returned by a script: `#{ key, value, severity }` or `[key, value]`
+fn finding(item: Dynamic) -> Option<Finding> {
+    if item.is_array() {
+        let item = item.cast::<Array>();
+        return match &item[..] {
+            [key, value] => Some(Finding::new(key.to_string(), value.to_string())),
+            _ => None,
+        };
+    }
+    let item = item.try_cast::<Map>()?;
+    let finding = Finding::new(item.get("key")?.to_string(), item.get("value")?.to_string());
+    match item
+        .get("severity")
+        .and_then(|name| severity(&name.to_string()))
+    {
+        Some(severity) => Some(finding.with_severity(severity)),
+        None => Some(finding),
+    }
+}
+
+impl Script {
+    fn call<T: Clone + Send + Sync + 'static>(
+        &self,
+        name: &str,
+        args: impl rhai::FuncArgs,
+    ) -> ScriptResult<T> {
+        engine().call_fn(&mut Scope::new(), &self.ast, name, args)
+    }
+
+    fn defines(ast: &AST, name: &str, params: usize) -> bool {
+        ast.iter_functions()
+            .any(|function| function.name == name && function.params.len() == params)
+    }
+
+    fn compile(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let ast = engine().compile(source).map_err(|e| e.to_string())?;
+        for (function, params) in [("name", 0), ("ports", 0), ("check", 1)] {
+            if !Self::defines(&ast, function, params) {
+                return Err(format!("missing function {}", function));
+            }
+        }
+
+        let mut script = Self {
+            name: "",
+            ports: Vec::new(),
+            rarity: 5,
+            accepts_banner: Self::defines(&ast, "accepts_banner", 1),
+            ast,
+        };
+        let name: String = script.call("name", ()).map_err(|e| e.to_string())?;
+        script.name = Box::leak(name.into_boxed_str());
+        let ports: Array = script.call("ports", ()).map_err(|e| e.to_string())?;
+        script.ports = ports
+            .into_iter()
+            .map(|port| port.as_int().ok().and_then(|port| u16::try_from(port).ok()))
+            .collect::<Option<_>>()
+            .ok_or("ports() should return an array of port numbers")?;
+        if Self::defines(&script.ast, "rarity", 0) {
+            let rarity: i64 = script.call("rarity", ()).map_err(|e| e.to_string())?;
+            script.rarity = rarity.clamp(1, 9) as u8;
+        }
+        Ok(script)
+    }
+
+    /// Runs `check`, from a thread which may block
+    fn check(&self, addr: SocketAddr, handle: Handle) -> io::Result<ProbeStatus> {
+        let target = Target {
+            addr,
+            handle,
+            deadline: Instant::now() + unsafe { READ_TIMEOUT },
+        };
+        let result: Dynamic = self
+            .call("check", (target,))
+            .map_err(|e| io::Error::other(format!("{}: {}", self.name, e)))?;
+        if result.is_unit() {
+            return Ok(ProbeStatus::Unknown);
+        }
+
+        let mut result = result.try_cast::<Map>().ok_or_else(|| {
+            io::Error::other(format!("{}: check() should return () or a map", self.name))
+        })?;
+        let protocol = result
+            .remove("protocol")
+            .map(|protocol| protocol.to_string())
+            .unwrap_or_else(|| self.name.to_owned());
+        let findings = result
+            .remove("findings")
+            .and_then(|findings| findings.try_cast::<Array>())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(finding)
+            .collect();
+        Ok(ProbeStatus::Found(Service::new(protocol, findings)))
+    }
+}
+
+/// Loads the scripts of a directory as probes, in the order of their file names
+pub fn load(dir: &Path) -> io::Result<Vec<BoxedProbe>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == SCRIPT_EXTENSION) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| match Script::compile(path) {
+            Ok(script) => Ok(Box::new(ScriptProbe(Arc::new(script))) as BoxedProbe),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )),
+        })
+        .collect()
+}
+
+impl Probe for ScriptProbe {
+    fn name(&self) -> &'static str {
+        self.0.name
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        self.0.ports.contains(&port)
+    }
+
+    fn rarity(&self) -> u8 {
+        self.0.rarity
+    }
+
+    fn accepts_banner(&self, banner: &[u8]) -> bool {
+        self.0.accepts_banner
+            && banner_engine()
+                .call_fn::<bool>(
+                    &mut Scope::new(),
+                    &self.0.ast,
+                    "accepts_banner",
+                    (banner.to_vec(),),
+                )
+                .unwrap_or(false)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        let script = self.0.clone();
+        Box::pin(async move {
+            let handle = Handle::current();
+            tokio::task::spawn_blocking(move || script.check(peer_addr, handle))
+                .await
+                .map_err(io::Error::other)?
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn pop3_script() -> BoxedProbe {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scripts");
+        load(&dir)
+            .unwrap()
+            .into_iter()
+            .find(|probe| probe.name() == "pop3")
+            .unwrap()
+    }
+
+    /// Serves a POP3 greeting and capabilities without STLS to one client
+    async fn pop3_fixture() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            writer.write_all(b"+OK POP3 ready\r\n").await.unwrap();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let answer: &[u8] = match line.as_str() {
+                    "CAPA" => b"+OK\r\nTOP\r\nUSER\r\n.\r\n",
+                    "QUIT" => b"+OK bye\r\n",
+                    _ => b"-ERR\r\n",
+                };
+                writer.write_all(answer).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pop3_script_finds_the_fixture_service() {
+        let probe = pop3_script();
+        assert!(probe.accepts_banner(b"+OK POP3 ready\r\n"));
+        assert!(!probe.accepts_banner(b"220 smtp ready\r\n"));
+
+        let addr = pop3_fixture().await;
+        let status = probe.check(addr).await.unwrap();
+        assert_eq!(
+            status,
+            ProbeStatus::Found(Service::new(
+                "POP3",
+                vec![
+                    Finding::new("capabilities", "TOP USER"),
+                    Finding::new("STARTTLS", "not supported").with_severity(Severity::Medium),
+                ]
+            ))
+        );
+    }
+}
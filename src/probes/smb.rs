@@ -0,0 +1,350 @@
+//! SMB servers tell their dialects, signing policy and GUID while negotiating, and
+//! their names through the NTLM challenge of an anonymous session setup.
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{ber, epm::format_guid, ntlm, Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const SMB1_MAGIC: &[u8] = b"\xffSMB";
+const SMB2_MAGIC: &[u8] = b"\xfeSMB";
+
+const SMB1_COM_NEGOTIATE: u8 = 0x72;
+
+const SMB2_NEGOTIATE: u16 = 0x0;
+const SMB2_SESSION_SETUP: u16 = 0x1;
+
+const SMB2_HEADER_SIZE: usize = 64;
+
+const DIALECT_311: u16 = 0x0311;
+
+const DIALECTS: [(u16, &str); 5] = [
+    (0x0202, "2.0.2"),
+    (0x0210, "2.1"),
+    (0x0300, "3.0"),
+    (0x0302, "3.0.2"),
+    (DIALECT_311, "3.1.1"),
+];
+
+const SIGNING_ENABLED: u16 = 0x1;
+const SIGNING_REQUIRED: u16 = 0x2;
+
+const CAPABILITIES: [(u32, &str); 7] = [
+    (0x01, "DFS"),
+    (0x02, "LEASING"),
+    (0x04, "LARGE_MTU"),
+    (0x08, "MULTI_CHANNEL"),
+    (0x10, "PERSISTENT_HANDLES"),
+    (0x20, "DIRECTORY_LEASING"),
+    (0x40, "ENCRYPTION"),
+];
+
+const CONTEXT_PREAUTH_INTEGRITY: u16 = 0x1;
+const CONTEXT_ENCRYPTION: u16 = 0x2;
+const HASH_SHA512: u16 = 0x1;
+const CIPHER_AES128_CCM: u16 = 0x1;
+const CIPHER_AES128_GCM: u16 = 0x2;
+
+/// SPNEGO and NTLMSSP object identifiers, DER encoded
+const OID_SPNEGO: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+const OID_NTLMSSP: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a];
+
+/// Seconds between 1601-01-01, origin of Windows FILETIMEs, and the UNIX epoch
+const FILETIME_UNIX_OFFSET: i64 = 11_644_473_600;
+
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+pub struct SmbProbe;
+
+/// What the server chose while negotiating
+struct Negotiation {
+    dialect: u16,
+    security_mode: u16,
+    guid: String,
+    capabilities: u32,
+    system_time: u64,
+}
+
+fn dialect_name(dialect: u16) -> String {
+    DIALECTS
+        .iter()
+        .find(|(d, _)| *d == dialect)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("0x{:04x}", dialect))
+}
+
+fn smb2_header(command: u16, message_id: u64) -> Vec<u8> {
+    let mut header = SMB2_MAGIC.to_vec();
+    header.extend_from_slice(&(SMB2_HEADER_SIZE as u16).to_le_bytes());
+    // credit charge, status
+    header.extend_from_slice(&[0u8; 6]);
+    header.extend_from_slice(&command.to_le_bytes());
+    // credits requested
+    header.extend_from_slice(&31u16.to_le_bytes());
+    // flags, next command
+    header.extend_from_slice(&[0u8; 8]);
+    header.extend_from_slice(&message_id.to_le_bytes());
+    // process id, tree id, session id and signature
+    header.extend_from_slice(&[0u8; 32]);
+    header
+}
+
+/// SMB2 NEGOTIATE request, with the negotiate contexts mandatory for SMB 3.1.1
+fn negotiate_request(dialects: &[u16]) -> Vec<u8> {
+    let with_contexts = dialects.contains(&DIALECT_311);
+    let mut message = smb2_header(SMB2_NEGOTIATE, 0);
+    message.extend_from_slice(&36u16.to_le_bytes());
+    message.extend_from_slice(&(dialects.len() as u16).to_le_bytes());
+    message.extend_from_slice(&SIGNING_ENABLED.to_le_bytes());
+    // reserved, capabilities
+    message.extend_from_slice(&[0u8; 6]);
+    message.extend_from_slice(&rand::random::<[u8; 16]>());
+    let contexts_offset_index = message.len();
+    message.extend_from_slice(&[0u8; 8]);
+    for dialect in dialects.iter() {
+        message.extend_from_slice(&dialect.to_le_bytes());
+    }
+    if !with_contexts {
+        return message;
+    }
+
+    let mut preauth = Vec::new();
+    preauth.extend_from_slice(&1u16.to_le_bytes());
+    preauth.extend_from_slice(&32u16.to_le_bytes());
+    preauth.extend_from_slice(&HASH_SHA512.to_le_bytes());
+    preauth.extend_from_slice(&rand::random::<[u8; 32]>());
+    let mut encryption = Vec::new();
+    encryption.extend_from_slice(&2u16.to_le_bytes());
+    encryption.extend_from_slice(&CIPHER_AES128_GCM.to_le_bytes());
+    encryption.extend_from_slice(&CIPHER_AES128_CCM.to_le_bytes());
+
+    let contexts = [
+        (CONTEXT_PREAUTH_INTEGRITY, preauth),
+        (CONTEXT_ENCRYPTION, encryption),
+    ];
+    for (index, (kind, data)) in contexts.iter().enumerate() {
+        // contexts are 8 bytes aligned
+        message.resize(message.len().next_multiple_of(8), 0);
+        if index == 0 {
+            let offset = (message.len() as u32).to_le_bytes();
+            message[contexts_offset_index..contexts_offset_index + 4].copy_from_slice(&offset);
+            let count = (contexts.len() as u16).to_le_bytes();
+            message[contexts_offset_index + 4..contexts_offset_index + 6].copy_from_slice(&count);
+        }
+        message.extend_from_slice(&kind.to_le_bytes());
+        message.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        message.extend_from_slice(&[0u8; 4]);
+        message.extend_from_slice(&data[..]);
+    }
+    message
+}
+
+/// SMB2 SESSION_SETUP request carrying an NTLM NEGOTIATE message in SPNEGO
+fn session_setup_request() -> Vec<u8> {
+    let mech_types = ber::encode(
+        0xa0,
+        &ber::encode(ber::SEQUENCE, &ber::encode(ber::OID, OID_NTLMSSP)),
+    );
+    let mech_token = ber::encode(
+        0xa2,
+        &ber::encode(ber::OCTET_STRING, &ntlm::negotiate_message()),
+    );
+    let init = ber::encode(
+        0xa0,
+        &ber::encode(ber::SEQUENCE, &[mech_types, mech_token].concat()),
+    );
+    let token = ber::encode(0x60, &[ber::encode(ber::OID, OID_SPNEGO), init].concat());
+
+    let mut message = smb2_header(SMB2_SESSION_SETUP, 1);
+    message.extend_from_slice(&25u16.to_le_bytes());
+    // flags, security mode
+    message.extend_from_slice(&[0, SIGNING_ENABLED as u8]);
+    // capabilities, channel
+    message.extend_from_slice(&[0u8; 8]);
+    message.extend_from_slice(&((SMB2_HEADER_SIZE + 24) as u16).to_le_bytes());
+    message.extend_from_slice(&(token.len() as u16).to_le_bytes());
+    // previous session id
+    message.extend_from_slice(&[0u8; 8]);
+    message.extend_from_slice(&token[..]);
+    message
+}
+
+/// SMB1 NEGOTIATE request, only offering the "NT LM 0.12" dialect
+fn smb1_negotiate_request() -> Vec<u8> {
+    let mut message = SMB1_MAGIC.to_vec();
+    message.push(SMB1_COM_NEGOTIATE);
+    // status
+    message.extend_from_slice(&[0u8; 4]);
+    // flags (case insensitive paths), flags2 (unicode, NT errors, long names)
+    message.extend_from_slice(&[0x18, 0x01, 0xc8]);
+    // pid high, security features, reserved
+    message.extend_from_slice(&[0u8; 12]);
+    // tree id, process id, user id, multiplex id
+    message.extend_from_slice(&[0xff, 0xff, 0xfe, 0xff, 0, 0, 0, 0]);
+    let dialects = b"\x02NT LM 0.12\0";
+    message.push(0);
+    message.extend_from_slice(&(dialects.len() as u16).to_le_bytes());
+    message.extend_from_slice(dialects);
+    message
+}
+
+impl SmbProbe {
+    /// Sends a message in a NetBIOS session packet and reads the answer
+    async fn exchange(stream: &mut TcpStream, message: &[u8]) -> io::Result<Vec<u8>> {
+        let mut packet = (message.len() as u32).to_be_bytes().to_vec();
+        packet.extend_from_slice(message);
+        stream.write_all(&packet[..]).await?;
+
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header[..]).await?;
+        let length = u32::from_be_bytes(header) as usize;
+        if header[0] != 0 || length > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a NetBIOS session packet",
+            ));
+        }
+        let mut message = vec![0u8; length];
+        stream.read_exact(&mut message[..]).await?;
+        Ok(message)
+    }
+
+    fn parse_negotiate(message: &[u8]) -> Option<Negotiation> {
+        if !message.starts_with(SMB2_MAGIC) {
+            return None;
+        }
+        let body = message.get(SMB2_HEADER_SIZE..SMB2_HEADER_SIZE + 48)?;
+        let u16_at = |index: usize| u16::from_le_bytes([body[index], body[index + 1]]);
+        let mut system_time = [0u8; 8];
+        system_time.copy_from_slice(&body[40..48]);
+        Some(Negotiation {
+            security_mode: u16_at(2),
+            dialect: u16_at(4),
+            guid: format_guid(&body[8..24]),
+            capabilities: u32::from_le_bytes([body[24], body[25], body[26], body[27]]),
+            system_time: u64::from_le_bytes(system_time),
+        })
+    }
+
+    async fn negotiate(
+        peer_addr: SocketAddr,
+        dialects: &[u16],
+    ) -> io::Result<(TcpStream, Option<Negotiation>)> {
+        let mut stream = TcpStream::connect(&peer_addr).await?;
+        let response = Self::exchange(&mut stream, &negotiate_request(dialects)).await?;
+        Ok((stream, Self::parse_negotiate(&response[..])))
+    }
+
+    async fn smb1_enabled(peer_addr: SocketAddr) -> bool {
+        let response = match TcpStream::connect(&peer_addr).await {
+            Ok(mut stream) => Self::exchange(&mut stream, &smb1_negotiate_request()).await,
+            Err(e) => Err(e),
+        };
+        match response {
+            // status 0, at least one parameter word and the dialect index 0
+            Ok(response) => {
+                response.starts_with(SMB1_MAGIC)
+                    && response.get(4) == Some(&SMB1_COM_NEGOTIATE)
+                    && response.get(5..9) == Some(&[0, 0, 0, 0])
+                    && response.get(32).is_some_and(|count| *count > 0)
+                    && response.get(33..35) == Some(&[0, 0])
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn negotiation_findings(negotiation: &Negotiation) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let signing = if negotiation.security_mode & SIGNING_REQUIRED != 0 {
+            "required"
+        } else if negotiation.security_mode & SIGNING_ENABLED != 0 {
+            "enabled, not required"
+        } else {
+            "disabled"
+        };
+        findings.push(Finding::new("signing", signing));
+        findings.push(Finding::new("server guid", negotiation.guid.clone()));
+        let names: Vec<&str> = CAPABILITIES
+            .iter()
+            .filter(|(flag, _)| negotiation.capabilities & flag != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        findings.push(Finding::new("capabilities", names.join(", ")));
+
+        if negotiation.system_time != 0 {
+            let seconds = (negotiation.system_time / 10_000_000) as i64 - FILETIME_UNIX_OFFSET;
+            let time_format = time::format_description::parse(
+                "[year]-[month]-[day] [hour]:[minute]:[second] UTC",
+            )
+            .unwrap();
+            if let Ok(time) = time::OffsetDateTime::from_unix_timestamp(seconds) {
+                findings.push(Finding::new(
+                    "system time",
+                    time.format(&time_format).unwrap(),
+                ));
+            }
+        }
+        findings
+    }
+}
+
+impl Probe for SmbProbe {
+    fn name(&self) -> &'static str {
+        "smb"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 445)
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let all_dialects: Vec<u16> = DIALECTS.iter().map(|(dialect, _)| *dialect).collect();
+            let (mut stream, negotiation) = Self::negotiate(peer_addr, &all_dialects[..]).await?;
+            let negotiation = match negotiation {
+                Some(negotiation) => negotiation,
+                None => return Ok(ProbeStatus::Unknown),
+            };
+
+            // An anonymous session setup, stopped after the server's challenge
+            let challenge = Self::exchange(&mut stream, &session_setup_request()).await;
+            drop(stream);
+
+            // Servers answer with the highest dialect they share with us, so each one
+            // is tried on its own
+            let mut supported = Vec::new();
+            for (dialect, name) in DIALECTS.iter() {
+                if let Ok((_, Some(answer))) = Self::negotiate(peer_addr, &[*dialect]).await {
+                    if answer.dialect == *dialect {
+                        supported.push(*name);
+                    }
+                }
+            }
+
+            let mut findings = vec![
+                Finding::new("dialect", dialect_name(negotiation.dialect)),
+                Finding::new("dialects", supported.join(", ")),
+                Finding::new(
+                    "smb1",
+                    if Self::smb1_enabled(peer_addr).await {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    },
+                ),
+            ];
+            findings.extend(Self::negotiation_findings(&negotiation));
+            if let Some(ntlm_findings) = challenge
+                .ok()
+                .and_then(|response| ntlm::challenge_findings(&response[..]))
+            {
+                findings.extend(ntlm_findings);
+            }
+
+            Ok(ProbeStatus::Found(Service::new("SMB", findings)))
+        })
+    }
+}
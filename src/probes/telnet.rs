@@ -0,0 +1,239 @@
+//! Telnet servers start by negotiating options with IAC commands, we refuse what they ask
+//! for, except echo and suppress go ahead, until they show their login prompt.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+use crate::utils::run_with_timeout;
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const OPTION_ECHO: u8 = 1;
+const OPTION_SUPPRESS_GO_AHEAD: u8 = 3;
+
+const OPTIONS: [(u8, &str); 15] = [
+    (0, "binary"),
+    (1, "echo"),
+    (3, "suppress go ahead"),
+    (5, "status"),
+    (6, "timing mark"),
+    (24, "terminal type"),
+    (31, "window size"),
+    (32, "terminal speed"),
+    (33, "remote flow control"),
+    (34, "linemode"),
+    (35, "x display location"),
+    (36, "environment"),
+    (37, "authentication"),
+    (38, "encryption"),
+    (39, "new environment"),
+];
+
+/// Time without data after which the server is waiting for us
+const IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+const MAX_DATA_SIZE: usize = 8 * 1024;
+const MAX_PROMPT_LEN: usize = 256;
+
+pub struct TelnetProbe;
+
+/// State of the options negotiation
+#[derive(Default)]
+struct Negotiation {
+    /// Data not parsed yet, an incomplete command
+    pending: Vec<u8>,
+    text: Vec<u8>,
+    /// Options the server asked us to enable
+    asked: Vec<u8>,
+    /// Options the server offered to enable
+    offered: Vec<u8>,
+    /// Commands already answered, as (command, option)
+    answered: Vec<(u8, u8)>,
+}
+
+impl Negotiation {
+    /// Parses data sent by the server, returns our answers
+    fn feed(&mut self, data: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(data);
+        let pending = std::mem::take(&mut self.pending);
+        let mut answers = Vec::new();
+        let mut index = 0;
+        while index < pending.len() {
+            if pending[index] != IAC {
+                self.text.push(pending[index]);
+                index += 1;
+                continue;
+            }
+            let (command, option) = match (pending.get(index + 1), pending.get(index + 2)) {
+                (None, _) => break,
+                (Some(&IAC), _) => {
+                    self.text.push(IAC);
+                    index += 2;
+                    continue;
+                }
+                (Some(&SB), _) => {
+                    // Skip the subnegotiation up to IAC SE
+                    match pending[index..]
+                        .windows(2)
+                        .position(|window| window == [IAC, SE])
+                    {
+                        Some(end) => index += end + 2,
+                        None => break,
+                    }
+                    continue;
+                }
+                (Some(&command), Some(&option)) if (WILL..=DONT).contains(&command) => {
+                    (command, option)
+                }
+                (Some(&command), None) if (WILL..=DONT).contains(&command) => break,
+                // Commands without option (NOP, GA, ...)
+                (Some(_), _) => {
+                    index += 2;
+                    continue;
+                }
+            };
+            index += 3;
+
+            let answer = match command {
+                DO => {
+                    if !self.asked.contains(&option) {
+                        self.asked.push(option);
+                    }
+                    WONT
+                }
+                WILL => {
+                    if !self.offered.contains(&option) {
+                        self.offered.push(option);
+                    }
+                    if matches!(option, OPTION_ECHO | OPTION_SUPPRESS_GO_AHEAD) {
+                        DO
+                    } else {
+                        DONT
+                    }
+                }
+                // Disabling is acknowledged only if enabled, which we never are
+                _ => continue,
+            };
+            if !self.answered.contains(&(command, option)) {
+                self.answered.push((command, option));
+                answers.extend_from_slice(&[IAC, answer, option]);
+            }
+        }
+        self.pending = pending[index..].to_vec();
+        answers
+    }
+
+    /// The text sent by the server, on a single line
+    fn prompt(&self) -> String {
+        let mut text = String::new();
+        let mut chars = String::from_utf8_lossy(&self.text).into_owned();
+        // Drop the terminal's escape sequences (colors, ...), up to their final letter
+        while let Some(start) = chars.find("\x1b[") {
+            text.push_str(&chars[..start]);
+            let sequence = &chars[start + 2..];
+            let end = sequence
+                .find(|c: char| c.is_ascii_alphabetic())
+                .map_or(sequence.len(), |end| end + 1);
+            chars = sequence[end..].to_owned();
+        }
+        text.push_str(&chars);
+        let text: String = text
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+        match text.char_indices().nth(MAX_PROMPT_LEN) {
+            Some((end, _)) => format!("{}...", &text[..end]),
+            None => text,
+        }
+    }
+
+    /// Whether the server is waiting for a user name or a command
+    fn shows_prompt(&self) -> bool {
+        let text = String::from_utf8_lossy(&self.text);
+        text.trim_end().ends_with([':', '>', '#', '$', '%'])
+    }
+}
+
+fn option_names(options: &[u8]) -> String {
+    let names: Vec<String> = options
+        .iter()
+        .map(|option| match OPTIONS.iter().find(|(o, _)| o == option) {
+            Some((_, name)) => name.to_string(),
+            None => format!("option {}", option),
+        })
+        .collect();
+    names.join(", ")
+}
+
+impl Probe for TelnetProbe {
+    fn name(&self) -> &'static str {
+        "telnet"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 23 | 2323)
+    }
+
+    fn accepts_banner(&self, banner: &[u8]) -> bool {
+        matches!(banner, [IAC, command, ..] if (WILL..=DONT).contains(command))
+    }
+
+    fn expects_banner(&self) -> bool {
+        true
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            let mut negotiation = Negotiation::default();
+            let mut data = vec![0u8; 1024];
+            let mut received = 0;
+            while received < MAX_DATA_SIZE && !negotiation.shows_prompt() {
+                let size = match run_with_timeout(IDLE_TIMEOUT, stream.read(&mut data[..])).await {
+                    Some(Ok(size)) if size > 0 => size,
+                    Some(Err(e)) if received == 0 => return Err(e),
+                    _ => break,
+                };
+                received += size;
+                let answers = negotiation.feed(&data[..size]);
+                if !answers.is_empty() {
+                    stream.write_all(&answers[..]).await?;
+                }
+            }
+            if negotiation.asked.is_empty() && negotiation.offered.is_empty() {
+                return Ok(ProbeStatus::Unknown);
+            }
+
+            let mut findings = Vec::new();
+            if !negotiation.offered.is_empty() {
+                findings.push(Finding::new(
+                    "server options",
+                    option_names(&negotiation.offered),
+                ));
+            }
+            if !negotiation.asked.is_empty() {
+                findings.push(Finding::new(
+                    "requested options",
+                    option_names(&negotiation.asked),
+                ));
+            }
+            let prompt = negotiation.prompt();
+            if !prompt.is_empty() {
+                findings.push(Finding::new("prompt", prompt));
+            }
+
+            Ok(ProbeStatus::Found(Service::new("Telnet", findings)))
+        })
+    }
+}
@@ -1,29 +1,193 @@
-use std::net::SocketAddr;
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
 
-use super::{Probe, ProbeCheckFuture, ProbeStatus};
+use super::{
+    grpc::GrpcProbe,
+    http::{HttpProbe, Scheme},
+    Finding, Probe, ProbeCheckFuture, ProbeStatus, Service,
+};
 
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 use tokio::net::TcpStream;
 
-use tokio_native_tls::{native_tls::TlsConnector as NativeTlsConnector, TlsConnector};
+use tokio_native_tls::{native_tls::TlsConnector as NativeTlsConnector, TlsConnector, TlsStream};
+use x509_parser::{
+    certificate::X509Certificate,
+    error::X509Error,
+    extensions::{DistributionPointName, GeneralName, ParsedExtension},
+    objects::{oid2sn, oid_registry},
+    oid_registry::OID_PKIX_ACCESS_DESCRIPTOR_OCSP,
+    public_key::PublicKey,
+};
+
+use crate::defaults::{
+    CERT_EXPIRY_WARN_DAYS, CONNECT_TIMEOUT, READ_TIMEOUT, SERVER_NAMES, TLS_ALPN_SWEEP,
+    TLS_FINGERPRINTS, TLS_JARM, TLS_SNI_ENUMERATION,
+};
+use crate::utils::run_with_timeout;
+
+mod handshake;
+mod jarm;
+
+use handshake::ServerHello;
 
 pub struct TlsProbe;
 
+/// Upper bound on the extra handshakes done to try other server names
+const MAX_SNI_ATTEMPTS: usize = 16;
+
+/// Steps of the check run one after the other, each within the read timeout: fetching the
+/// certificates (2), JARM, trying other names (2), ALPN (2), then gRPC and HTTP/2, HTTP
+/// taking as long as its requests need
+const SEQUENTIAL_STEPS: u32 = 9;
+
+/// Runs a step of the check within the read timeout, None when it failed or took too long
+async fn step<O>(future: impl Future<Output = Option<O>>) -> Option<O> {
+    run_with_timeout(unsafe { READ_TIMEOUT }, future)
+        .await
+        .flatten()
+}
+
+/// Protocols offered one at a time through ALPN, to list those the server accepts
+const ALPN_PROTOCOLS: [&str; 16] = [
+    "h2",
+    "http/1.1",
+    "http/1.0",
+    "spdy/3.1",
+    "acme-tls/1",
+    "dot",
+    "imap",
+    "pop3",
+    "managesieve",
+    "ftp",
+    "xmpp-client",
+    "xmpp-server",
+    "mqtt",
+    "postgresql",
+    "ntske/1",
+    "sunrpc",
+];
+
+/// ClientHello record of a default handshake, without server name
+pub(super) fn client_hello_record() -> Vec<u8> {
+    handshake::ClientHello::new().to_record()
+}
+
+fn fingerprint<D: Digest>(der: &[u8]) -> String {
+    D::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Loads a database of known JARM/JA3S fingerprints.
+///
+/// Each line holds a hash followed by a free-form label, `#` starts a comment.
+pub fn load_fingerprint_database(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut fingerprints = Vec::new();
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (hash, label) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        fingerprints.push((hash.to_owned(), label.trim().to_owned()));
+    }
+    Ok(fingerprints)
+}
+
+fn oid_name(oid: &x509_parser::der_parser::oid::Oid) -> String {
+    oid2sn(oid, oid_registry())
+        .map(|s| s.to_owned())
+        .unwrap_or_else(|_| oid.to_id_string())
+}
+
+fn general_name_to_string(name: &GeneralName) -> String {
+    match name {
+        GeneralName::DNSName(dns) => format!("DNS:{}", dns),
+        GeneralName::RFC822Name(email) => format!("email:{}", email),
+        GeneralName::URI(uri) => uri.to_string(),
+        GeneralName::IPAddress(ip) => match ip.len() {
+            4 => format!("IP:{}", Ipv4Addr::from(<[u8; 4]>::try_from(*ip).unwrap())),
+            16 => format!("IP:{}", Ipv6Addr::from(<[u8; 16]>::try_from(*ip).unwrap())),
+            _ => name.to_string(),
+        },
+        name => name.to_string(),
+    }
+}
+
+fn general_names_to_string(names: &[GeneralName]) -> String {
+    names
+        .iter()
+        .map(general_name_to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl TlsProbe {
-    fn show_cert(der: &[u8]) {
+    fn describe_key(cert: &X509Certificate) -> String {
+        let spki = cert.public_key();
+        match spki.parsed() {
+            Ok(PublicKey::RSA(rsa)) => format!("RSA {} bits", rsa.key_size()),
+            Ok(PublicKey::EC(ec)) => {
+                let curve = spki
+                    .algorithm
+                    .parameters
+                    .as_ref()
+                    .and_then(|p| p.as_oid().ok())
+                    .map(|oid| oid_name(&oid))
+                    .unwrap_or_else(|| "unknown curve".into());
+                format!("EC {} ({} bits)", curve, ec.key_size())
+            }
+            Ok(key) => format!(
+                "{} {} bits",
+                oid_name(&spki.algorithm.algorithm),
+                key.key_size()
+            ),
+            Err(_) => oid_name(&spki.algorithm.algorithm),
+        }
+    }
+
+    /// Whether a certificate is signed by its own key, not only issued by its subject
+    fn self_signed(cert: &X509Certificate) -> &'static str {
+        if cert.subject().as_raw() != cert.issuer().as_raw() {
+            return "no";
+        }
+        match cert.verify_signature(None) {
+            Ok(()) => "yes",
+            Err(X509Error::SignatureUnsupportedAlgorithm) => {
+                "issuer == subject (signature algorithm not supported)"
+            }
+            Err(_) => "no (issuer == subject, signature does not verify)",
+        }
+    }
+
+    fn cert_findings(index: usize, der: &[u8]) -> Vec<Finding> {
         let cert = match x509_parser::parse_x509_certificate(der) {
             Ok((_rest, value)) => value,
             Err(_) => {
-                return;
+                return Vec::new();
             }
         };
         let time_format = time::format_description::parse(
             "[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]",
         )
         .unwrap();
-        println!("      - issuer : {}", cert.issuer());
-        println!("      - subject: {}", cert.subject());
-        println!(
-            "      - dates  : between {} and {} ({})",
+        let mut findings = vec![
+            Finding::new("certificate", format!("#{}", index)),
+            Finding::new("subject", cert.subject().to_string()),
+            Finding::new("issuer", cert.issuer().to_string()),
+            Finding::new("serial", cert.raw_serial_as_string()),
+        ];
+        let dates = format!(
+            "between {} and {} ({})",
             cert.validity()
                 .not_before
                 .to_datetime()
@@ -40,6 +204,326 @@ impl TlsProbe {
                 "invalid"
             }
         );
+        findings.push(Finding::new("dates", dates));
+        if let Some(left) = cert.validity().time_to_expiration() {
+            let days = left.whole_days();
+            let warn_days = unsafe { CERT_EXPIRY_WARN_DAYS };
+            findings.push(Finding::new(
+                "expires in",
+                format!(
+                    "{} days{}",
+                    days,
+                    if days < warn_days as i64 {
+                        " (EXPIRES SOON)"
+                    } else {
+                        ""
+                    }
+                ),
+            ));
+        }
+        findings.push(Finding::new("key", Self::describe_key(&cert)));
+        findings.push(Finding::new(
+            "signature",
+            oid_name(&cert.signature_algorithm.algorithm),
+        ));
+        findings.push(Finding::new("sha1", fingerprint::<Sha1>(der)));
+        findings.push(Finding::new("sha256", fingerprint::<Sha256>(der)));
+        findings.push(Finding::new("self-signed", Self::self_signed(&cert)));
+
+        for ext in cert.extensions() {
+            match ext.parsed_extension() {
+                ParsedExtension::SubjectAlternativeName(san) => {
+                    findings.push(Finding::new(
+                        "alt names",
+                        general_names_to_string(&san.general_names[..]),
+                    ));
+                }
+                ParsedExtension::KeyUsage(ku) => {
+                    findings.push(Finding::new("key usage", ku.to_string()));
+                }
+                ParsedExtension::ExtendedKeyUsage(eku) => {
+                    let mut usages = Vec::new();
+                    if eku.any {
+                        usages.push("any".to_owned());
+                    }
+                    if eku.server_auth {
+                        usages.push("serverAuth".to_owned());
+                    }
+                    if eku.client_auth {
+                        usages.push("clientAuth".to_owned());
+                    }
+                    if eku.code_signing {
+                        usages.push("codeSigning".to_owned());
+                    }
+                    if eku.email_protection {
+                        usages.push("emailProtection".to_owned());
+                    }
+                    if eku.time_stamping {
+                        usages.push("timeStamping".to_owned());
+                    }
+                    if eku.ocsp_signing {
+                        usages.push("OCSPSigning".to_owned());
+                    }
+                    usages.extend(eku.other.iter().map(oid_name));
+                    findings.push(Finding::new("ext usage", usages.join(", ")));
+                }
+                ParsedExtension::BasicConstraints(bc) => {
+                    let mut constraints = format!("CA:{}", bc.ca);
+                    if let Some(path_len) = bc.path_len_constraint {
+                        constraints.push_str(&format!(", pathlen:{}", path_len));
+                    }
+                    findings.push(Finding::new("constraints", constraints));
+                }
+                ParsedExtension::AuthorityInfoAccess(aia) => {
+                    for desc in aia.iter() {
+                        if desc.access_method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP {
+                            findings.push(Finding::new(
+                                "ocsp",
+                                general_name_to_string(&desc.access_location),
+                            ));
+                        }
+                    }
+                }
+                ParsedExtension::CRLDistributionPoints(points) => {
+                    for point in points.iter() {
+                        if let Some(DistributionPointName::FullName(names)) =
+                            &point.distribution_point
+                        {
+                            findings.push(Finding::new("crl", general_names_to_string(&names[..])));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        findings
+    }
+
+    /// Exact DNS names of the certificate, usable as SNI candidates
+    fn dns_names(der: &[u8]) -> Vec<String> {
+        let cert = match x509_parser::parse_x509_certificate(der) {
+            Ok((_rest, value)) => value,
+            Err(_) => {
+                return Vec::new();
+            }
+        };
+        match cert.subject_alternative_name() {
+            Ok(Some(san)) => san
+                .value
+                .general_names
+                .iter()
+                .filter_map(|n| match n {
+                    GeneralName::DNSName(dns) if !dns.contains('*') => Some(dns.to_string()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Retrieves the whole presented chain, only possible when the server accepts TLS 1.2 or
+    /// below as later versions encrypt the Certificate message.
+    async fn get_chain(
+        peer_addr: SocketAddr,
+        server_name: Option<&str>,
+    ) -> Option<handshake::ServerFlight> {
+        let mut stream = TcpStream::connect(&peer_addr).await.ok()?;
+        let mut hello = handshake::ClientHello::new();
+        if let Some(server_name) = server_name {
+            hello = hello.with_server_name(server_name);
+        }
+        handshake::exchange(&mut stream, &hello).await.ok()
+    }
+
+    /// Establishes a TLS session, whatever the certificate presented, offering `alpn`
+    /// protocols when not empty
+    pub(super) async fn connect(
+        peer_addr: SocketAddr,
+        server_name: Option<&str>,
+        alpn: &[&str],
+    ) -> Option<TlsStream<TcpStream>> {
+        let stream = TcpStream::connect(&peer_addr).await.ok()?;
+        Self::connect_over(stream, server_name, alpn).await
+    }
+
+    /// Establishes a TLS session over a connection where the protocol asked for it
+    pub(super) async fn connect_over(
+        stream: TcpStream,
+        server_name: Option<&str>,
+        alpn: &[&str],
+    ) -> Option<TlsStream<TcpStream>> {
+        let connector: TlsConnector = NativeTlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .use_sni(server_name.is_some())
+            .request_alpns(alpn)
+            .build()
+            .expect("Cannot build TLS connector")
+            .into();
+
+        let domain = server_name.unwrap_or("localhost");
+        connector.connect(domain, stream).await.ok()
+    }
+
+    /// Falls back on a regular handshake, for servers only speaking TLS 1.3 whose
+    /// Certificate message is encrypted: OpenSSL hands us the chain once it is decrypted
+    #[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+    async fn get_peer_chain(
+        peer_addr: SocketAddr,
+        server_name: Option<&str>,
+    ) -> Option<Vec<Vec<u8>>> {
+        use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+
+        let server_name = server_name.map(str::to_owned);
+        let (connect_timeout, read_timeout) = unsafe { (CONNECT_TIMEOUT, READ_TIMEOUT) };
+        // OpenSSL's streams are blocking, bounded by the socket's timeouts
+        tokio::task::spawn_blocking(move || {
+            let stream = std::net::TcpStream::connect_timeout(&peer_addr, connect_timeout).ok()?;
+            stream.set_read_timeout(Some(read_timeout)).ok()?;
+            stream.set_write_timeout(Some(read_timeout)).ok()?;
+            let mut builder = SslConnector::builder(SslMethod::tls_client()).ok()?;
+            builder.set_verify(SslVerifyMode::NONE);
+            let stream = builder
+                .build()
+                .configure()
+                .ok()?
+                .use_server_name_indication(server_name.is_some())
+                .verify_hostname(false)
+                .connect(server_name.as_deref().unwrap_or("localhost"), stream)
+                .ok()?;
+            let chain = stream.ssl().peer_cert_chain()?;
+            chain.iter().map(|cert| cert.to_der().ok()).collect()
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    /// Falls back on a regular handshake, which only gives us the leaf certificate where
+    /// the TLS library does not expose the chain
+    #[cfg(any(target_os = "windows", target_vendor = "apple"))]
+    async fn get_peer_chain(
+        peer_addr: SocketAddr,
+        server_name: Option<&str>,
+    ) -> Option<Vec<Vec<u8>>> {
+        let stream = Self::connect(peer_addr, server_name, &[]).await?;
+        let der = stream
+            .get_ref()
+            .peer_certificate()
+            .ok()
+            .flatten()
+            .and_then(|cert| cert.to_der().ok());
+        Some(der.into_iter().collect())
+    }
+
+    /// Returns the server certificates along with its ServerHello when we could parse it
+    async fn get_certificates(
+        peer_addr: SocketAddr,
+        server_name: Option<&str>,
+    ) -> Option<(Vec<Vec<u8>>, Option<ServerHello>)> {
+        let (certificates, server_hello) = match step(Self::get_chain(peer_addr, server_name)).await
+        {
+            Some(flight) => (flight.certificates, flight.server_hello),
+            None => (Vec::new(), None),
+        };
+        if !certificates.is_empty() {
+            return Some((certificates, server_hello));
+        }
+        let chain = step(Self::get_peer_chain(peer_addr, server_name)).await?;
+        Some((chain, server_hello))
+    }
+
+    /// Protocol the server selects among `protocols`, if any.
+    ///
+    /// TLS 1.3 servers send their choice encrypted, so we then fall back on a regular
+    /// handshake.
+    async fn negotiate_alpn(
+        peer_addr: SocketAddr,
+        server_name: Option<&str>,
+        protocols: &[&str],
+    ) -> Option<String> {
+        let mut hello = handshake::ClientHello::new().with_alpn(protocols);
+        if let Some(server_name) = server_name {
+            hello = hello.with_server_name(server_name);
+        }
+        let server_hello = step(async {
+            let mut stream = TcpStream::connect(&peer_addr).await.ok()?;
+            handshake::server_hello(&mut stream, &hello).await.ok()?
+        })
+        .await;
+        if let Some(server_hello) = server_hello {
+            if server_hello.version != handshake::TLS_1_3 {
+                return server_hello.alpn();
+            }
+        }
+
+        step(async {
+            let stream = Self::connect(peer_addr, server_name, protocols).await?;
+            let protocol = stream.get_ref().negotiated_alpn().ok().flatten()?;
+            String::from_utf8(protocol).ok()
+        })
+        .await
+    }
+
+    /// Returns the protocols accepted through ALPN and the one preferred by the server. Only
+    /// h2 is offered alone unless the sweep of every known protocol is enabled.
+    async fn alpn(
+        peer_addr: SocketAddr,
+        server_name: Option<&str>,
+    ) -> (Vec<String>, Option<String>) {
+        let offered = if unsafe { TLS_ALPN_SWEEP } {
+            &ALPN_PROTOCOLS[..]
+        } else {
+            &ALPN_PROTOCOLS[..1]
+        };
+        let preferred = Self::negotiate_alpn(peer_addr, server_name, &ALPN_PROTOCOLS[..]);
+        let each = futures::future::join_all(offered.iter().map(|protocol| {
+            Self::negotiate_alpn(peer_addr, server_name, std::slice::from_ref(protocol))
+        }));
+        let (preferred, each) = futures::future::join(preferred, each).await;
+        let accepted = offered
+            .iter()
+            .zip(each)
+            .filter(|(protocol, answer)| answer.as_deref() == Some(**protocol))
+            .map(|(protocol, _)| protocol.to_string())
+            .collect();
+        (accepted, preferred)
+    }
+
+    /// JA3S: md5 of "version,cipher,extensions"
+    fn ja3s(server_hello: &ServerHello) -> String {
+        let extensions = server_hello
+            .extensions
+            .iter()
+            .map(|(t, _)| t.to_string())
+            .collect::<Vec<_>>()
+            .join("-");
+        let ja3s = format!(
+            "{},{},{}",
+            server_hello.legacy_version, server_hello.cipher_suite, extensions
+        );
+        Md5::digest(ja3s.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn fingerprint_findings(kind: &str, hash: &str) -> Vec<Finding> {
+        let mut findings = vec![Finding::new(kind, hash)];
+        for (known, label) in unsafe { TLS_FINGERPRINTS } {
+            if known.eq_ignore_ascii_case(hash) {
+                findings.push(Finding::new(format!("known {}", kind), label.as_str()));
+            }
+        }
+        findings
+    }
+
+    fn chain_findings(chain: &[Vec<u8>]) -> Vec<Finding> {
+        chain
+            .iter()
+            .enumerate()
+            .flat_map(|(index, der)| Self::cert_findings(index, &der[..]))
+            .collect()
     }
 }
 
@@ -49,32 +533,152 @@ impl Probe for TlsProbe {
     }
 
     fn is_prefered_port(&self, port: u16) -> bool {
-        match port {
-            443 | 465 | 636 | 993 | 995 | 8443 => true,
-            _ => false,
-        }
+        matches!(port, 443 | 465 | 636 | 993 | 995 | 8443)
+    }
+
+    fn rarity(&self) -> u8 {
+        1
+    }
+
+    fn timeout(&self) -> Duration {
+        let read_timeout = unsafe { READ_TIMEOUT };
+        read_timeout * SEQUENTIAL_STEPS + HttpProbe::scheme_timeout()
     }
 
     fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
         Box::pin(async move {
-            let connector: TlsConnector = NativeTlsConnector::builder()
-                .danger_accept_invalid_certs(true)
-                .danger_accept_invalid_hostnames(true)
-                .build()
-                .expect("Cannot build TLS connector")
-                .into();
-
-            let stream = TcpStream::connect(&peer_addr).await?;
-            Ok(match connector.connect("localhost", stream).await {
-                Ok(stream) => {
-                    if let Some(cert) = stream.get_ref().peer_certificate().ok().flatten() {
-                        if let Ok(der) = cert.to_der() {
-                            Self::show_cert(&der[..]);
-                        }
+            let enumerate_names = unsafe { TLS_SNI_ENUMERATION };
+            let mut names: Vec<String> = unsafe { SERVER_NAMES }.to_vec();
+            if enumerate_names {
+                for name in super::discovered_names() {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+
+            let first_name = names.first().cloned();
+            let (chain, server_hello) =
+                match Self::get_certificates(peer_addr, first_name.as_deref()).await {
+                    Some(answer) => answer,
+                    None => {
+                        return Ok(ProbeStatus::Unknown);
+                    }
+                };
+            let mut findings = Vec::new();
+            if let Some(ref name) = first_name {
+                findings.push(Finding::new("server name", name.as_str()));
+            }
+            findings.extend(Self::chain_findings(&chain[..]));
+
+            if let Some(ref server_hello) = server_hello {
+                findings.extend(Self::fingerprint_findings(
+                    "ja3s",
+                    &Self::ja3s(server_hello),
+                ));
+            }
+            if unsafe { TLS_JARM } {
+                let jarm_name = first_name
+                    .clone()
+                    .unwrap_or_else(|| peer_addr.ip().to_string());
+                let jarm = jarm::fingerprint(peer_addr, &jarm_name).await;
+                findings.extend(Self::fingerprint_findings("jarm", &jarm));
+            }
+
+            // Names found in the certificate may be served by other virtual hosts
+            if let (true, Some(leaf)) = (enumerate_names, chain.first()) {
+                for name in Self::dns_names(&leaf[..]) {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+            let others = if first_name.is_some() {
+                &names[1..]
+            } else {
+                &names[..]
+            };
+            let others = &others[..others.len().min(MAX_SNI_ATTEMPTS)];
+
+            let answers = futures::future::join_all(
+                others
+                    .iter()
+                    .map(|name| Self::get_certificates(peer_addr, Some(name.as_str()))),
+            )
+            .await;
+
+            let leaf_fingerprint = chain.first().map(|der| fingerprint::<Sha256>(&der[..]));
+            let mut same = Vec::new();
+            for (name, answer) in others.iter().zip(answers) {
+                let answer = match answer {
+                    Some((answer, _)) if !answer.is_empty() => answer,
+                    _ => continue,
+                };
+                if Some(fingerprint::<Sha256>(&answer[0][..])) == leaf_fingerprint {
+                    same.push(name.as_str());
+                } else {
+                    findings.push(Finding::new(
+                        "server name",
+                        format!("{} yields a different certificate", name),
+                    ));
+                    findings.extend(Self::chain_findings(&answer[..]));
+                }
+            }
+            if !same.is_empty() {
+                findings.push(Finding::new("same certificate for", same.join(", ")));
+            }
+
+            let (accepted, preferred) = Self::alpn(peer_addr, first_name.as_deref()).await;
+            if !accepted.is_empty() {
+                findings.push(Finding::new("alpn", accepted.join(", ")));
+            }
+            if let Some(preferred) = preferred {
+                findings.push(Finding::new("alpn preferred", preferred));
+            }
+            let service = Service::new("TLS", findings);
+
+            // Look for HTTP inside the TLS session, gRPC coming first as it is the more
+            // specific
+            let http1 = run_with_timeout(
+                HttpProbe::scheme_timeout(),
+                HttpProbe::check_scheme(peer_addr, Scheme::Https, first_name.as_deref()),
+            )
+            .await
+            .and_then(Result::ok)
+            .flatten();
+            let mut inner = Vec::new();
+            if accepted.iter().any(|protocol| protocol == "h2") {
+                let grpc = step(async {
+                    GrpcProbe::check_scheme(peer_addr, Scheme::Https, first_name.as_deref())
+                        .await
+                        .ok()
+                        .flatten()
+                })
+                .await;
+                match grpc {
+                    Some(grpc) => {
+                        inner.push(grpc);
+                        inner.extend(http1);
+                    }
+                    None => {
+                        let h2 = step(async {
+                            HttpProbe::check_h2(peer_addr, Scheme::Https, first_name.as_deref())
+                                .await
+                                .ok()
+                                .flatten()
+                        })
+                        .await;
+                        inner.extend(HttpProbe::merge_h2(http1, h2));
                     }
-                    ProbeStatus::Recognized
                 }
-                Err(_) => ProbeStatus::Unknown,
+            } else {
+                inner.extend(http1);
+            }
+
+            Ok(if inner.is_empty() {
+                ProbeStatus::Found(service)
+            } else {
+                ProbeStatus::Layered(service, inner)
             })
         })
     }
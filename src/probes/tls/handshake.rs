@@ -0,0 +1,482 @@
+use std::io;
+
+use rand::RngCore;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const HANDSHAKE_SERVER_HELLO: u8 = 2;
+const HANDSHAKE_CERTIFICATE: u8 = 11;
+const HANDSHAKE_SERVER_HELLO_DONE: u8 = 14;
+
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXTENSION_EC_POINT_FORMATS: u16 = 0x000b;
+const EXTENSION_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+pub const EXTENSION_ALPN: u16 = 0x0010;
+const EXTENSION_SUPPORTED_VERSIONS: u16 = 0x002b;
+const EXTENSION_RENEGOTIATION_INFO: u16 = 0xff01;
+
+pub const TLS_1_0: u16 = 0x0301;
+pub const TLS_1_2: u16 = 0x0303;
+pub const TLS_1_3: u16 = 0x0304;
+
+/// Upper bound on the handshake data we accept from a server
+const MAX_HANDSHAKE_SIZE: usize = 256 * 1024;
+
+/// Cipher suites usable with TLS 1.2 and below, most common first
+const DEFAULT_CIPHER_SUITES: [u16; 24] = [
+    0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc023, 0xc027, 0xc024, 0xc028, 0xc009, 0xc013,
+    0xc00a, 0xc014, 0x009e, 0x009f, 0x0067, 0x006b, 0x0033, 0x0039, 0x009c, 0x009d, 0x002f, 0x0035,
+];
+
+const DEFAULT_GROUPS: [u16; 4] = [0x001d, 0x0017, 0x0018, 0x0019];
+
+const DEFAULT_SIGNATURE_ALGORITHMS: [u16; 11] = [
+    0x0403, 0x0503, 0x0603, 0x0804, 0x0805, 0x0806, 0x0401, 0x0501, 0x0601, 0x0203, 0x0201,
+];
+
+/// A ClientHello we fully control, byte for byte
+#[derive(Debug, Clone)]
+pub struct ClientHello {
+    /// version of the record layer carrying the hello
+    pub record_version: u16,
+    /// version advertised in the hello itself
+    pub version: u16,
+    pub cipher_suites: Vec<u16>,
+    /// raw extensions, sent in this order
+    pub extensions: Vec<(u16, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerHello {
+    /// version field of the hello itself
+    pub legacy_version: u16,
+    /// negotiated version, taking supported_versions into account
+    pub version: u16,
+    pub cipher_suite: u16,
+    /// raw extensions, in the order the server sent them
+    pub extensions: Vec<(u16, Vec<u8>)>,
+}
+
+/// Everything the server sent in the clear in reply to our ClientHello
+#[derive(Debug, Default)]
+pub struct ServerFlight {
+    pub server_hello: Option<ServerHello>,
+    /// DER certificates, leaf first
+    pub certificates: Vec<Vec<u8>>,
+}
+
+fn push_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u24(buf: &mut Vec<u8>, value: usize) {
+    buf.extend_from_slice(&(value as u32).to_be_bytes()[1..]);
+}
+
+fn u16_list(values: &[u16]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(2 + values.len() * 2);
+    push_u16(&mut buf, (values.len() * 2) as u16);
+    for v in values {
+        push_u16(&mut buf, *v);
+    }
+    buf
+}
+
+impl ClientHello {
+    /// Builds a TLS 1.2 hello most servers will answer with their certificate chain
+    pub fn new() -> Self {
+        Self {
+            record_version: TLS_1_0,
+            version: TLS_1_2,
+            cipher_suites: DEFAULT_CIPHER_SUITES.to_vec(),
+            extensions: vec![
+                (EXTENSION_SUPPORTED_GROUPS, u16_list(&DEFAULT_GROUPS[..])),
+                (EXTENSION_EC_POINT_FORMATS, vec![1, 0]),
+                (
+                    EXTENSION_SIGNATURE_ALGORITHMS,
+                    u16_list(&DEFAULT_SIGNATURE_ALGORITHMS[..]),
+                ),
+                (EXTENSION_RENEGOTIATION_INFO, vec![0]),
+            ],
+        }
+    }
+
+    /// Adds a server_name extension (SNI) in front of the others
+    pub fn with_server_name(mut self, name: &str) -> Self {
+        let name = name.as_bytes();
+        let mut data = Vec::with_capacity(name.len() + 5);
+        push_u16(&mut data, (name.len() + 3) as u16);
+        data.push(0);
+        push_u16(&mut data, name.len() as u16);
+        data.extend_from_slice(name);
+        self.extensions.insert(0, (EXTENSION_SERVER_NAME, data));
+        self
+    }
+
+    /// Adds an application_layer_protocol_negotiation extension (ALPN)
+    pub fn with_alpn(mut self, protocols: &[&str]) -> Self {
+        let mut list = Vec::new();
+        for protocol in protocols {
+            list.push(protocol.len() as u8);
+            list.extend_from_slice(protocol.as_bytes());
+        }
+        let mut data = Vec::with_capacity(list.len() + 2);
+        push_u16(&mut data, list.len() as u16);
+        data.extend_from_slice(&list[..]);
+        self.extensions.push((EXTENSION_ALPN, data));
+        self
+    }
+
+    /// Serializes the hello in a single handshake record
+    pub fn to_record(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(512);
+        push_u16(&mut body, self.version);
+        let mut random = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut random[..]);
+        body.extend_from_slice(&random[..]);
+        let mut session_id = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut session_id[..]);
+        body.push(session_id.len() as u8);
+        body.extend_from_slice(&session_id[..]);
+        body.extend_from_slice(&u16_list(&self.cipher_suites[..]));
+        // Only the null compression method
+        body.extend_from_slice(&[1, 0]);
+
+        let extensions_len: usize = self.extensions.iter().map(|(_, d)| d.len() + 4).sum();
+        push_u16(&mut body, extensions_len as u16);
+        for (ext_type, data) in &self.extensions {
+            push_u16(&mut body, *ext_type);
+            push_u16(&mut body, data.len() as u16);
+            body.extend_from_slice(&data[..]);
+        }
+
+        let mut record = Vec::with_capacity(body.len() + 9);
+        record.push(CONTENT_TYPE_HANDSHAKE);
+        push_u16(&mut record, self.record_version);
+        push_u16(&mut record, (body.len() + 4) as u16);
+        record.push(HANDSHAKE_CLIENT_HELLO);
+        push_u24(&mut record, body.len());
+        record.extend_from_slice(&body[..]);
+        record
+    }
+}
+
+impl Default for ClientHello {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Minimal big-endian reader over a byte slice
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.bytes(3)
+            .map(|b| u32::from_be_bytes([0, b[0], b[1], b[2]]) as usize)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl ServerHello {
+    /// Protocol selected by the server through ALPN
+    pub fn alpn(&self) -> Option<String> {
+        let (_, data) = self.extensions.iter().find(|(t, _)| *t == EXTENSION_ALPN)?;
+        let mut r = Reader(&data[..]);
+        let _list_len = r.u16()?;
+        let len = r.u8()? as usize;
+        String::from_utf8(r.bytes(len)?.to_vec()).ok()
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut r = Reader(data);
+        let legacy_version = r.u16()?;
+        let mut version = legacy_version;
+        let _random = r.bytes(32)?;
+        let session_id_len = r.u8()? as usize;
+        let _session_id = r.bytes(session_id_len)?;
+        let cipher_suite = r.u16()?;
+        let _compression = r.u8()?;
+        let mut extensions = Vec::new();
+        if !r.is_empty() {
+            let len = r.u16()? as usize;
+            let mut exts = Reader(r.bytes(len)?);
+            while !exts.is_empty() {
+                let ext_type = exts.u16()?;
+                let ext_len = exts.u16()? as usize;
+                let ext_data = exts.bytes(ext_len)?;
+                if ext_type == EXTENSION_SUPPORTED_VERSIONS && ext_data.len() == 2 {
+                    version = u16::from_be_bytes([ext_data[0], ext_data[1]]);
+                }
+                extensions.push((ext_type, ext_data.to_vec()));
+            }
+        }
+
+        Some(Self {
+            legacy_version,
+            version,
+            cipher_suite,
+            extensions,
+        })
+    }
+}
+
+fn parse_certificates(data: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut r = Reader(data);
+    let len = r.u24()?;
+    let mut list = Reader(r.bytes(len)?);
+    let mut certificates = Vec::new();
+    while !list.is_empty() {
+        let cert_len = list.u24()?;
+        certificates.push(list.bytes(cert_len)?.to_vec());
+    }
+    Some(certificates)
+}
+
+/// Sends `hello` and collects the server's cleartext reply up to ServerHelloDone.
+///
+/// With TLS 1.3 everything after the ServerHello is encrypted, so the flight
+/// then only holds the ServerHello.
+pub async fn exchange(stream: &mut TcpStream, hello: &ClientHello) -> io::Result<ServerFlight> {
+    stream.write_all(&hello.to_record()[..]).await?;
+    read_flight(stream, true).await
+}
+
+/// Sends `hello` and only waits for the ServerHello
+pub async fn server_hello(
+    stream: &mut TcpStream,
+    hello: &ClientHello,
+) -> io::Result<Option<ServerHello>> {
+    stream.write_all(&hello.to_record()[..]).await?;
+    Ok(read_flight(stream, false).await?.server_hello)
+}
+
+async fn read_flight(stream: &mut TcpStream, until_done: bool) -> io::Result<ServerFlight> {
+    let mut flight = ServerFlight::default();
+    let mut handshake = Vec::new();
+    let mut total = 0usize;
+
+    loop {
+        let mut header = [0u8; 5];
+        if let Err(e) = stream.read_exact(&mut header[..]).await {
+            if flight.server_hello.is_some() {
+                return Ok(flight);
+            }
+            return Err(e);
+        }
+        let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+        total += length;
+        if header[1] != 0x03 || total > MAX_HANDSHAKE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a TLS record",
+            ));
+        }
+        let mut fragment = vec![0u8; length];
+        stream.read_exact(&mut fragment[..]).await?;
+
+        match header[0] {
+            CONTENT_TYPE_HANDSHAKE => handshake.extend_from_slice(&fragment[..]),
+            _ => return Ok(flight),
+        }
+
+        // Consume every complete handshake message
+        loop {
+            let mut r = Reader(&handshake[..]);
+            let (msg_type, msg_len) = match (r.u8(), r.u24()) {
+                (Some(t), Some(l)) if r.0.len() >= l => (t, l),
+                _ => break,
+            };
+            let body = r.bytes(msg_len).expect("length checked above").to_vec();
+            handshake.drain(..msg_len + 4);
+
+            match msg_type {
+                HANDSHAKE_SERVER_HELLO => {
+                    let server_hello = ServerHello::parse(&body[..]).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "Invalid ServerHello")
+                    })?;
+                    let is_tls13 = server_hello.version == TLS_1_3;
+                    flight.server_hello = Some(server_hello);
+                    if is_tls13 || !until_done {
+                        return Ok(flight);
+                    }
+                }
+                HANDSHAKE_CERTIFICATE => {
+                    if let Some(certificates) = parse_certificates(&body[..]) {
+                        flight.certificates = certificates;
+                    }
+                }
+                HANDSHAKE_SERVER_HELLO_DONE => return Ok(flight),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    fn server_hello_body() -> Vec<u8> {
+        let mut body = Vec::new();
+        push_u16(&mut body, TLS_1_2);
+        body.extend_from_slice(&[7u8; 32]);
+        body.push(0);
+        push_u16(&mut body, 0x1301);
+        body.push(0);
+        let alpn = [0, 3, 2, b'h', b'2'];
+        push_u16(&mut body, 6 + 4 + alpn.len() as u16);
+        push_u16(&mut body, EXTENSION_SUPPORTED_VERSIONS);
+        push_u16(&mut body, 2);
+        push_u16(&mut body, TLS_1_3);
+        push_u16(&mut body, EXTENSION_ALPN);
+        push_u16(&mut body, alpn.len() as u16);
+        body.extend_from_slice(&alpn[..]);
+        body
+    }
+
+    /// Handshake messages in a single record
+    fn record(messages: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut fragment = Vec::new();
+        for (msg_type, body) in messages {
+            fragment.push(*msg_type);
+            push_u24(&mut fragment, body.len());
+            fragment.extend_from_slice(body);
+        }
+        let mut record = vec![CONTENT_TYPE_HANDSHAKE];
+        push_u16(&mut record, TLS_1_2);
+        push_u16(&mut record, fragment.len() as u16);
+        record.extend_from_slice(&fragment[..]);
+        record
+    }
+
+    /// Sends `data` to the first client, then closes the connection
+    async fn flight_from(data: Vec<u8>) -> io::Result<ServerFlight> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.write_all(&data[..]).await;
+        });
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        read_flight(&mut stream, true).await
+    }
+
+    #[test]
+    fn parses_server_hellos() {
+        let hello = ServerHello::parse(&server_hello_body()[..]).unwrap();
+        assert_eq!(hello.legacy_version, TLS_1_2);
+        assert_eq!(hello.version, TLS_1_3);
+        assert_eq!(hello.cipher_suite, 0x1301);
+        assert_eq!(hello.alpn().as_deref(), Some("h2"));
+    }
+
+    #[test]
+    fn rejects_truncated_server_hellos() {
+        let body = server_hello_body();
+        // Extensions are optional, the hello may end right after the compression method
+        let without_extensions = 2 + 32 + 1 + 2 + 1;
+        for size in (0..body.len()).filter(|size| *size != without_extensions) {
+            assert!(ServerHello::parse(&body[..size]).is_none(), "size {}", size);
+        }
+
+        let mut oversized = body.clone();
+        oversized[without_extensions..without_extensions + 2].copy_from_slice(&[0xff, 0xff]);
+        assert!(ServerHello::parse(&oversized[..]).is_none());
+
+        let mut hello = ServerHello::parse(&body[..]).unwrap();
+        hello.extensions = vec![(EXTENSION_ALPN, vec![0, 3, 200, b'h'])];
+        assert_eq!(hello.alpn(), None);
+    }
+
+    #[test]
+    fn rejects_truncated_certificate_lists() {
+        let mut list = Vec::new();
+        for certificate in [&b"leaf"[..], &b"issuer"[..]] {
+            push_u24(&mut list, certificate.len());
+            list.extend_from_slice(certificate);
+        }
+        let mut data = Vec::new();
+        push_u24(&mut data, list.len());
+        data.extend_from_slice(&list[..]);
+        assert_eq!(
+            parse_certificates(&data[..]),
+            Some(vec![b"leaf".to_vec(), b"issuer".to_vec()])
+        );
+
+        for size in 0..data.len() {
+            assert!(parse_certificates(&data[..size]).is_none(), "size {}", size);
+        }
+        let mut oversized = data.clone();
+        oversized[3..6].copy_from_slice(&[0xff, 0xff, 0xff]);
+        assert!(parse_certificates(&oversized[..]).is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_flights_up_to_server_hello_done() {
+        let mut hello = server_hello_body();
+        hello.truncate(2 + 32 + 1 + 2 + 1);
+        let mut certificates = Vec::new();
+        push_u24(&mut certificates, 7);
+        push_u24(&mut certificates, 4);
+        certificates.extend_from_slice(b"leaf");
+        let data = record(&[
+            (HANDSHAKE_SERVER_HELLO, &hello[..]),
+            (HANDSHAKE_CERTIFICATE, &certificates[..]),
+            (HANDSHAKE_SERVER_HELLO_DONE, &[]),
+        ]);
+
+        let flight = flight_from(data).await.unwrap();
+        assert_eq!(flight.server_hello.unwrap().version, TLS_1_2);
+        assert_eq!(flight.certificates, vec![b"leaf".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_and_oversized_flights() {
+        // The record announces more than the server sends
+        let mut truncated = record(&[(HANDSHAKE_SERVER_HELLO, &server_hello_body()[..])]);
+        truncated.truncate(truncated.len() - 10);
+        assert!(flight_from(truncated).await.is_err());
+
+        // A handshake message never ending, spread over records of the largest size
+        let mut oversized = vec![HANDSHAKE_CERTIFICATE, 0xff, 0xff, 0xff];
+        oversized.resize(u16::MAX as usize, 0);
+        let mut data = Vec::new();
+        for _ in 0..=MAX_HANDSHAKE_SIZE / oversized.len() {
+            data.push(CONTENT_TYPE_HANDSHAKE);
+            push_u16(&mut data, TLS_1_2);
+            push_u16(&mut data, oversized.len() as u16);
+            data.extend_from_slice(&oversized[..]);
+        }
+        let error = flight_from(data).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,421 @@
+//! JARM active TLS server fingerprinting, following the reference
+//! implementation from <https://github.com/salesforce/jarm>.
+//!
+//! Ten ClientHellos differing in versions, cipher and extension ordering are
+//! sent over separate connections, and the answers are folded into a 62
+//! characters fuzzy hash.
+
+use std::net::SocketAddr;
+
+use rand::seq::SliceRandom;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+
+use super::handshake::{self, ClientHello, ServerHello, EXTENSION_ALPN};
+
+const TLS_1_1: u16 = 0x0302;
+
+const GREASE_VALUES: [u16; 16] = [
+    0x0a0a, 0x1a1a, 0x2a2a, 0x3a3a, 0x4a4a, 0x5a5a, 0x6a6a, 0x7a7a, 0x8a8a, 0x9a9a, 0xaaaa, 0xbaba,
+    0xcaca, 0xdada, 0xeaea, 0xfafa,
+];
+
+const ALL_CIPHERS: [u16; 69] = [
+    0x0016, 0x0033, 0x0067, 0xc09e, 0xc0a2, 0x009e, 0x0039, 0x006b, 0xc09f, 0xc0a3, 0x009f, 0x0045,
+    0x00be, 0x0088, 0x00c4, 0x009a, 0xc008, 0xc009, 0xc023, 0xc0ac, 0xc0ae, 0xc02b, 0xc00a, 0xc024,
+    0xc0ad, 0xc0af, 0xc02c, 0xc072, 0xc073, 0xcca9, 0x1302, 0x1301, 0xcc14, 0xc007, 0xc012, 0xc013,
+    0xc027, 0xc02f, 0xc014, 0xc028, 0xc030, 0xc060, 0xc061, 0xc076, 0xc077, 0xcca8, 0x1305, 0x1304,
+    0x1303, 0xcc13, 0xc011, 0x000a, 0x002f, 0x003c, 0xc09c, 0xc0a0, 0x009c, 0x0035, 0x003d, 0xc09d,
+    0xc0a1, 0x009d, 0x0041, 0x00ba, 0x0084, 0x00c0, 0x0007, 0x0004, 0x0005,
+];
+
+/// Order used to turn the selected cipher into a single byte of the hash
+const HASH_CIPHERS: [u16; 69] = [
+    0x0004, 0x0005, 0x0007, 0x000a, 0x0016, 0x002f, 0x0033, 0x0035, 0x0039, 0x003c, 0x003d, 0x0041,
+    0x0045, 0x0067, 0x006b, 0x0084, 0x0088, 0x009a, 0x009c, 0x009d, 0x009e, 0x009f, 0x00ba, 0x00be,
+    0x00c0, 0x00c4, 0xc007, 0xc008, 0xc009, 0xc00a, 0xc011, 0xc012, 0xc013, 0xc014, 0xc023, 0xc024,
+    0xc027, 0xc028, 0xc02b, 0xc02c, 0xc02f, 0xc030, 0xc060, 0xc061, 0xc072, 0xc073, 0xc076, 0xc077,
+    0xc09c, 0xc09d, 0xc09e, 0xc09f, 0xc0a0, 0xc0a1, 0xc0a2, 0xc0a3, 0xc0ac, 0xc0ad, 0xc0ae, 0xc0af,
+    0xcc13, 0xcc14, 0xcca8, 0xcca9, 0x1301, 0x1302, 0x1303, 0x1304, 0x1305,
+];
+
+const ALPNS: [&[u8]; 9] = [
+    b"http/0.9",
+    b"http/1.0",
+    b"http/1.1",
+    b"spdy/1",
+    b"spdy/2",
+    b"spdy/3",
+    b"h2",
+    b"h2c",
+    b"hq",
+];
+
+const RARE_ALPNS: [&[u8]; 7] = [
+    b"http/0.9",
+    b"http/1.0",
+    b"spdy/1",
+    b"spdy/2",
+    b"spdy/3",
+    b"h2c",
+    b"hq",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Order {
+    Forward,
+    Reverse,
+    TopHalf,
+    BottomHalf,
+    MiddleOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Versions {
+    /// supported_versions up to TLS 1.2
+    UpTo12,
+    /// supported_versions up to TLS 1.3
+    UpTo13,
+    /// no supported_versions extension
+    None,
+}
+
+struct Variant {
+    version: u16,
+    /// offer TLS 1.3 ciphers
+    tls13_ciphers: bool,
+    cipher_order: Order,
+    grease: bool,
+    rare_alpn: bool,
+    versions: Versions,
+    extension_order: Order,
+}
+
+const VARIANTS: [Variant; 10] = [
+    Variant {
+        version: handshake::TLS_1_2,
+        tls13_ciphers: true,
+        cipher_order: Order::Forward,
+        grease: false,
+        rare_alpn: false,
+        versions: Versions::UpTo12,
+        extension_order: Order::Reverse,
+    },
+    Variant {
+        version: handshake::TLS_1_2,
+        tls13_ciphers: true,
+        cipher_order: Order::Reverse,
+        grease: false,
+        rare_alpn: false,
+        versions: Versions::UpTo12,
+        extension_order: Order::Forward,
+    },
+    Variant {
+        version: handshake::TLS_1_2,
+        tls13_ciphers: true,
+        cipher_order: Order::TopHalf,
+        grease: false,
+        rare_alpn: false,
+        versions: Versions::None,
+        extension_order: Order::Forward,
+    },
+    Variant {
+        version: handshake::TLS_1_2,
+        tls13_ciphers: true,
+        cipher_order: Order::BottomHalf,
+        grease: false,
+        rare_alpn: true,
+        versions: Versions::None,
+        extension_order: Order::Forward,
+    },
+    Variant {
+        version: handshake::TLS_1_2,
+        tls13_ciphers: true,
+        cipher_order: Order::MiddleOut,
+        grease: true,
+        rare_alpn: true,
+        versions: Versions::None,
+        extension_order: Order::Reverse,
+    },
+    Variant {
+        version: TLS_1_1,
+        tls13_ciphers: true,
+        cipher_order: Order::Forward,
+        grease: false,
+        rare_alpn: false,
+        versions: Versions::None,
+        extension_order: Order::Forward,
+    },
+    Variant {
+        version: handshake::TLS_1_3,
+        tls13_ciphers: true,
+        cipher_order: Order::Forward,
+        grease: false,
+        rare_alpn: false,
+        versions: Versions::UpTo13,
+        extension_order: Order::Reverse,
+    },
+    Variant {
+        version: handshake::TLS_1_3,
+        tls13_ciphers: true,
+        cipher_order: Order::Reverse,
+        grease: false,
+        rare_alpn: false,
+        versions: Versions::UpTo13,
+        extension_order: Order::Forward,
+    },
+    Variant {
+        version: handshake::TLS_1_3,
+        tls13_ciphers: false,
+        cipher_order: Order::Forward,
+        grease: false,
+        rare_alpn: false,
+        versions: Versions::UpTo13,
+        extension_order: Order::Forward,
+    },
+    Variant {
+        version: handshake::TLS_1_3,
+        tls13_ciphers: true,
+        cipher_order: Order::MiddleOut,
+        grease: true,
+        rare_alpn: false,
+        versions: Versions::UpTo13,
+        extension_order: Order::Reverse,
+    },
+];
+
+fn reorder<T: Copy>(items: &[T], order: Order) -> Vec<T> {
+    let len = items.len();
+    match order {
+        Order::Forward => items.to_vec(),
+        Order::Reverse => items.iter().rev().copied().collect(),
+        Order::BottomHalf => items[(len / 2 + len % 2)..].to_vec(),
+        Order::TopHalf => {
+            let mut output = Vec::with_capacity(len / 2 + 1);
+            if len % 2 == 1 {
+                output.push(items[len / 2]);
+            }
+            output.extend(reorder(
+                &reorder(items, Order::Reverse)[..],
+                Order::BottomHalf,
+            ));
+            output
+        }
+        Order::MiddleOut => {
+            let middle = len / 2;
+            let mut output = Vec::with_capacity(len);
+            if len % 2 == 1 {
+                output.push(items[middle]);
+                for i in 1..=middle {
+                    output.push(items[middle + i]);
+                    output.push(items[middle - i]);
+                }
+            } else {
+                for i in 1..=middle {
+                    output.push(items[middle - 1 + i]);
+                    output.push(items[middle - i]);
+                }
+            }
+            output
+        }
+    }
+}
+
+fn random_grease() -> u16 {
+    *GREASE_VALUES
+        .choose(&mut rand::thread_rng())
+        .expect("GREASE_VALUES is not empty")
+}
+
+fn build_hello(variant: &Variant, server_name: &str) -> ClientHello {
+    let ciphers: Vec<u16> = ALL_CIPHERS
+        .iter()
+        .copied()
+        .filter(|c| variant.tls13_ciphers || *c >> 8 != 0x13)
+        .collect();
+    let mut cipher_suites = reorder(&ciphers[..], variant.cipher_order);
+    if variant.grease {
+        cipher_suites.insert(0, random_grease());
+    }
+
+    let mut extensions: Vec<(u16, Vec<u8>)> = Vec::with_capacity(14);
+    if variant.grease {
+        extensions.push((random_grease(), Vec::new()));
+    }
+
+    let name = server_name.as_bytes();
+    let mut sni = Vec::with_capacity(name.len() + 5);
+    sni.extend_from_slice(&((name.len() + 3) as u16).to_be_bytes());
+    sni.push(0);
+    sni.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    sni.extend_from_slice(name);
+    extensions.push((0x0000, sni));
+
+    // extended_master_secret, max_fragment_length, renegotiation_info
+    extensions.push((0x0017, Vec::new()));
+    extensions.push((0x0001, vec![0x01]));
+    extensions.push((0xff01, vec![0x00]));
+    extensions.push((
+        0x000a,
+        vec![0x00, 0x08, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18, 0x00, 0x19],
+    ));
+    extensions.push((0x000b, vec![0x01, 0x00]));
+    // session_ticket
+    extensions.push((0x0023, Vec::new()));
+
+    let alpns = if variant.rare_alpn {
+        &RARE_ALPNS[..]
+    } else {
+        &ALPNS[..]
+    };
+    let alpns = reorder(alpns, variant.extension_order);
+    let mut alpn_list = Vec::new();
+    for alpn in alpns {
+        alpn_list.push(alpn.len() as u8);
+        alpn_list.extend_from_slice(alpn);
+    }
+    let mut alpn = Vec::with_capacity(alpn_list.len() + 2);
+    alpn.extend_from_slice(&(alpn_list.len() as u16).to_be_bytes());
+    alpn.extend_from_slice(&alpn_list[..]);
+    extensions.push((EXTENSION_ALPN, alpn));
+
+    extensions.push((
+        0x000d,
+        vec![
+            0x00, 0x12, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05, 0x01,
+            0x08, 0x06, 0x06, 0x01, 0x02, 0x01,
+        ],
+    ));
+
+    let mut shares = Vec::with_capacity(42);
+    if variant.grease {
+        shares.extend_from_slice(&random_grease().to_be_bytes());
+        shares.extend_from_slice(&[0x00, 0x01, 0x00]);
+    }
+    shares.extend_from_slice(&[0x00, 0x1d, 0x00, 0x20]);
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key[..]);
+    shares.extend_from_slice(&key[..]);
+    let mut key_share = Vec::with_capacity(shares.len() + 2);
+    key_share.extend_from_slice(&(shares.len() as u16).to_be_bytes());
+    key_share.extend_from_slice(&shares[..]);
+    extensions.push((0x0033, key_share));
+
+    // psk_key_exchange_modes
+    extensions.push((0x002d, vec![0x01, 0x01]));
+
+    if variant.version == handshake::TLS_1_3 || variant.versions == Versions::UpTo12 {
+        let versions: &[u16] = if variant.versions == Versions::UpTo12 {
+            &[0x0301, 0x0302, 0x0303]
+        } else {
+            &[0x0301, 0x0302, 0x0303, 0x0304]
+        };
+        let mut versions = reorder(versions, variant.extension_order);
+        if variant.grease {
+            versions.insert(0, random_grease());
+        }
+        let mut data = Vec::with_capacity(versions.len() * 2 + 1);
+        data.push((versions.len() * 2) as u8);
+        for v in versions {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        extensions.push((0x002b, data));
+    }
+
+    let (record_version, version) = match variant.version {
+        handshake::TLS_1_3 => (handshake::TLS_1_0, handshake::TLS_1_2),
+        v => (v, v),
+    };
+
+    ClientHello {
+        record_version,
+        version,
+        cipher_suites,
+        extensions,
+    }
+}
+
+/// The "cipher|version|alpn|extensions" summary of one answer
+fn summarize(server_hello: Option<&ServerHello>) -> String {
+    let server_hello = match server_hello {
+        Some(server_hello) => server_hello,
+        None => {
+            return "|||".into();
+        }
+    };
+    let alpn = server_hello
+        .extensions
+        .iter()
+        .find(|(t, _)| *t == EXTENSION_ALPN)
+        .and_then(|(_, data)| data.get(3..))
+        .map(|proto| String::from_utf8_lossy(proto).into_owned())
+        .unwrap_or_default();
+    let extensions = server_hello
+        .extensions
+        .iter()
+        .map(|(t, _)| format!("{:04x}", t))
+        .collect::<Vec<_>>()
+        .join("-");
+    format!(
+        "{:04x}|{:04x}|{}|{}",
+        server_hello.cipher_suite, server_hello.legacy_version, alpn, extensions
+    )
+}
+
+fn hash(answers: &[String]) -> String {
+    if answers.iter().all(|a| a == "|||") {
+        return "0".repeat(62);
+    }
+
+    let mut fuzzy_hash = String::with_capacity(62);
+    let mut alpns_and_extensions = String::new();
+    for answer in answers {
+        let mut components = answer.split('|');
+        let cipher = components.next().unwrap_or_default();
+        let version = components.next().unwrap_or_default();
+        alpns_and_extensions.push_str(components.next().unwrap_or_default());
+        alpns_and_extensions.push_str(components.next().unwrap_or_default());
+
+        if cipher.is_empty() {
+            fuzzy_hash.push_str("00");
+        } else {
+            let index = HASH_CIPHERS
+                .iter()
+                .position(|c| format!("{:04x}", c) == cipher)
+                .unwrap_or(HASH_CIPHERS.len());
+            fuzzy_hash.push_str(&format!("{:02x}", index + 1));
+        }
+
+        match version.get(3..4).and_then(|v| v.parse::<usize>().ok()) {
+            Some(minor) if minor < 6 => fuzzy_hash.push(b"abcdef"[minor] as char),
+            _ => fuzzy_hash.push('0'),
+        }
+    }
+
+    let digest = Sha256::digest(alpns_and_extensions.as_bytes());
+    for b in &digest[..16] {
+        fuzzy_hash.push_str(&format!("{:02x}", b));
+    }
+    fuzzy_hash
+}
+
+async fn probe(peer_addr: SocketAddr, hello: ClientHello) -> String {
+    let answer = super::step(async {
+        let mut stream = TcpStream::connect(&peer_addr).await.ok()?;
+        handshake::server_hello(&mut stream, &hello).await.ok()?
+    })
+    .await;
+    summarize(answer.as_ref())
+}
+
+/// Computes the JARM fingerprint of the server
+pub async fn fingerprint(peer_addr: SocketAddr, server_name: &str) -> String {
+    let answers = futures::future::join_all(
+        VARIANTS
+            .iter()
+            .map(|variant| probe(peer_addr, build_hello(variant, server_name))),
+    )
+    .await;
+    hash(&answers[..])
+}
@@ -0,0 +1,139 @@
+//! VNC servers greet clients with their RFB protocol version, then offer the security
+//! types a client may pick, "None" granting access to the desktop without password.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+const VERSION_PREFIX: &[u8] = b"RFB ";
+const VERSION_LEN: usize = 12;
+
+const SECURITY_INVALID: u8 = 0;
+const SECURITY_NONE: u8 = 1;
+
+/// Security types, from the IANA registry
+const SECURITY_TYPES: [(u8, &str); 14] = [
+    (1, "None"),
+    (2, "VNC Authentication"),
+    (5, "RA2"),
+    (6, "RA2ne"),
+    (16, "Tight"),
+    (17, "Ultra"),
+    (18, "TLS"),
+    (19, "VeNCrypt"),
+    (20, "GTK-VNC SASL"),
+    (21, "MD5 hash authentication"),
+    (22, "Colin Dean xvp"),
+    (30, "Apple Remote Desktop"),
+    (113, "UltraVNC MS Logon II"),
+    (129, "RealVNC"),
+];
+
+const MAX_REASON_SIZE: usize = 1024;
+
+pub struct VncProbe;
+
+fn security_type_name(security_type: u8) -> String {
+    match SECURITY_TYPES.iter().find(|(t, _)| *t == security_type) {
+        Some((_, name)) => format!("{} ({})", name, security_type),
+        None => security_type.to_string(),
+    }
+}
+
+/// Parses a `RFB xxx.yyy\n` version as (major, minor)
+fn protocol_version(version: &[u8]) -> Option<(u16, u16)> {
+    let version = std::str::from_utf8(version.strip_prefix(VERSION_PREFIX)?).ok()?;
+    let (major, minor) = version.trim_end().split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+impl VncProbe {
+    async fn read_reason(stream: &mut TcpStream) -> Option<String> {
+        let len = stream.read_u32().await.ok()? as usize;
+        let mut reason = vec![0u8; len.min(MAX_REASON_SIZE)];
+        stream.read_exact(&mut reason[..]).await.ok()?;
+        Some(String::from_utf8_lossy(&reason).into_owned())
+    }
+}
+
+impl Probe for VncProbe {
+    fn name(&self) -> &'static str {
+        "vnc"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        matches!(port, 5900..=5910)
+    }
+
+    fn accepts_banner(&self, banner: &[u8]) -> bool {
+        banner.starts_with(VERSION_PREFIX)
+    }
+
+    fn expects_banner(&self) -> bool {
+        true
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            let mut version = [0u8; VERSION_LEN];
+            stream.read_exact(&mut version[..]).await?;
+            let (major, minor) = match protocol_version(&version[..]) {
+                Some(version) => version,
+                None => return Ok(ProbeStatus::Unknown),
+            };
+            let mut findings = vec![Finding::new("protocol", format!("{}.{}", major, minor))];
+
+            // Answer with the highest version we both know, odd minors are handled as the
+            // version below them (UltraVNC's 3.4 as 3.3, Apple's 3.889 as 3.8)
+            let version = match (major, minor) {
+                (3, 7) => "RFB 003.007\n",
+                (3, 8..) | (4.., _) => "RFB 003.008\n",
+                _ => "RFB 003.003\n",
+            };
+            stream.write_all(version.as_bytes()).await?;
+
+            let mut security_types = Vec::new();
+            let mut reason = None;
+            if version == "RFB 003.003\n" {
+                // The server picks the security type
+                match stream.read_u32().await? {
+                    0 => reason = Self::read_reason(&mut stream).await,
+                    security_type => security_types.push(security_type as u8),
+                }
+            } else {
+                let count = stream.read_u8().await? as usize;
+                if count == 0 {
+                    reason = Self::read_reason(&mut stream).await;
+                } else {
+                    security_types.resize(count, SECURITY_INVALID);
+                    stream.read_exact(&mut security_types[..]).await?;
+                }
+            }
+
+            if let Some(reason) = reason {
+                findings.push(Finding::new("refused", reason));
+            }
+            if !security_types.is_empty() {
+                let names: Vec<String> = security_types
+                    .iter()
+                    .map(|t| security_type_name(*t))
+                    .collect();
+                findings.push(Finding::new("security types", names.join(", ")));
+                findings.push(Finding::new(
+                    "auth",
+                    if security_types.contains(&SECURITY_NONE) {
+                        "not required"
+                    } else {
+                        "required"
+                    },
+                ));
+            }
+
+            Ok(ProbeStatus::Found(Service::new("VNC", findings)))
+        })
+    }
+}
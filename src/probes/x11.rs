@@ -0,0 +1,131 @@
+//! X11 servers answer a connection setup without authorization data with their vendor
+//! and screens when access control is disabled (`xhost +`), or with the reason of the
+//! refusal.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{Finding, Probe, ProbeCheckFuture, ProbeStatus, Service};
+
+/// Little endian byte order
+const BYTE_ORDER_LSB_FIRST: u8 = b'l';
+const PROTOCOL_MAJOR_VERSION: u16 = 11;
+const PROTOCOL_MINOR_VERSION: u16 = 0;
+
+const SETUP_FAILED: u8 = 0;
+const SETUP_SUCCESS: u8 = 1;
+const SETUP_AUTHENTICATE: u8 = 2;
+
+const DISPLAY_BASE_PORT: u16 = 6000;
+const MAX_DISPLAYS: u16 = 64;
+
+/// Size of the setup reply, without its additional data
+const SETUP_HEADER_SIZE: usize = 8;
+/// Fixed part of a successful setup, before the vendor
+const SETUP_FIXED_SIZE: usize = 32;
+
+pub struct X11Probe;
+
+fn setup_request() -> Vec<u8> {
+    let mut request = vec![BYTE_ORDER_LSB_FIRST, 0];
+    request.extend_from_slice(&PROTOCOL_MAJOR_VERSION.to_le_bytes());
+    request.extend_from_slice(&PROTOCOL_MINOR_VERSION.to_le_bytes());
+    // no authorization protocol name nor data
+    request.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    request
+}
+
+/// Release numbers of X.Org are the version, as major * 10000000 + minor * 100000 + ...
+fn release_name(vendor: &str, release: u32) -> String {
+    if vendor.starts_with("The X.Org Foundation") {
+        format!(
+            "{}.{}.{}",
+            release / 10_000_000,
+            release / 100_000 % 100,
+            release / 1000 % 100
+        )
+    } else {
+        release.to_string()
+    }
+}
+
+impl Probe for X11Probe {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn is_prefered_port(&self, port: u16) -> bool {
+        (DISPLAY_BASE_PORT..DISPLAY_BASE_PORT + MAX_DISPLAYS).contains(&port)
+    }
+
+    fn rarity(&self) -> u8 {
+        8
+    }
+
+    fn check(&self, peer_addr: SocketAddr) -> ProbeCheckFuture {
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(&peer_addr).await?;
+            stream.write_all(&setup_request()[..]).await?;
+
+            let mut header = [0u8; SETUP_HEADER_SIZE];
+            stream.read_exact(&mut header[..]).await?;
+            let status = header[0];
+            let major = u16::from_le_bytes([header[2], header[3]]);
+            let minor = u16::from_le_bytes([header[4], header[5]]);
+            let additional_size = u16::from_le_bytes([header[6], header[7]]) as usize * 4;
+            if major != PROTOCOL_MAJOR_VERSION
+                || !matches!(status, SETUP_FAILED | SETUP_SUCCESS | SETUP_AUTHENTICATE)
+            {
+                return Ok(ProbeStatus::Unknown);
+            }
+            let mut additional = vec![0u8; additional_size];
+            stream.read_exact(&mut additional[..]).await?;
+
+            let mut findings = vec![Finding::new("protocol", format!("{}.{}", major, minor))];
+            match status {
+                SETUP_SUCCESS => {
+                    if additional.len() < SETUP_FIXED_SIZE {
+                        return Ok(ProbeStatus::Unknown);
+                    }
+                    let release = u32::from_le_bytes([
+                        additional[0],
+                        additional[1],
+                        additional[2],
+                        additional[3],
+                    ]);
+                    let vendor_len = u16::from_le_bytes([additional[16], additional[17]]) as usize;
+                    let screens = additional[20];
+                    let vendor = additional
+                        .get(SETUP_FIXED_SIZE..SETUP_FIXED_SIZE + vendor_len)
+                        .map(|vendor| String::from_utf8_lossy(vendor).into_owned())
+                        .unwrap_or_default();
+
+                    findings.push(Finding::new("access", "granted"));
+                    findings.push(Finding::new("auth", "not required"));
+                    findings.push(Finding::new("release", release_name(&vendor, release)));
+                    findings.push(Finding::new("vendor", vendor));
+                    findings.push(Finding::new("screens", screens.to_string()));
+                }
+                _ => {
+                    // The reason's length is only given on failures, it is padded otherwise
+                    let reason = match status {
+                        SETUP_FAILED => additional.get(..header[1] as usize),
+                        _ => Some(&additional[..]),
+                    }
+                    .map(|reason| {
+                        String::from_utf8_lossy(reason)
+                            .trim_end_matches(['\0', '\n'])
+                            .to_owned()
+                    })
+                    .unwrap_or_default();
+                    findings.push(Finding::new("access", format!("refused ({})", reason)));
+                    findings.push(Finding::new("auth", "required"));
+                }
+            }
+
+            Ok(ProbeStatus::Found(Service::new("X11", findings)))
+        })
+    }
+}
@@ -1,10 +1,10 @@
 use std::io;
 use std::net::IpAddr;
 
-use tokio::io::AsyncReadExt;
+use futures::channel::mpsc;
 use tokio::net::TcpStream;
 
-use crate::defaults::{CONNECT_TIMEOUT, READ_TIMEOUT};
+use crate::defaults::CONNECT_TIMEOUT;
 use crate::port::{Port, PortStatus, PortsList};
 use crate::utils::{run_with_timeout, Semaphore};
 
@@ -18,18 +18,29 @@ impl TcpScanner {
         Self { ports }
     }
 
-    pub async fn scan(&self, ip: IpAddr, verbose: bool) -> io::Result<Vec<Port>> {
+    /// Scans the ports, sending the open ones to `open_ports` as soon as they are found
+    pub async fn scan(
+        &self,
+        ip: IpAddr,
+        verbose: bool,
+        open_ports: mpsc::UnboundedSender<Port>,
+    ) -> io::Result<Vec<Port>> {
         // TODO: shuffle array
         let semaphore = Semaphore::new(512);
         let mut results = Vec::with_capacity(self.ports.len());
 
         for port in self.ports.iter() {
             let ticket = semaphore.acquire().await;
+            let open_ports = open_ports.clone();
             results.push(tokio::spawn(async move {
-                let port = test_port(ip.clone(), port).await;
+                let port = test_port(ip, port).await;
                 drop(ticket);
-                if verbose && port.is_open() {
-                    eprintln!("Port {} is opened.", port.num);
+                if port.is_open() {
+                    if verbose {
+                        eprintln!("Port {} is opened.", port.num);
+                    }
+                    // The services' check may be over already
+                    let _ = open_ports.unbounded_send(port.clone());
                 }
                 port
             }));
@@ -40,19 +51,12 @@ impl TcpScanner {
     }
 }
 
+/// Tells whether a port is open, without waiting for a banner: service detection reads it
 async fn test_port(ip: IpAddr, port: u16) -> Port {
     let connect_timeout = unsafe { CONNECT_TIMEOUT };
-    let read_timeout = unsafe { READ_TIMEOUT };
 
     let status = match run_with_timeout(connect_timeout, TcpStream::connect((ip, port))).await {
-        Some(Ok(mut s)) => {
-            let mut buf = Vec::with_capacity(1024);
-            let banner = match run_with_timeout(read_timeout, s.read_buf(&mut buf)).await {
-                Some(Ok(n)) if n > 0 => Some(buf),
-                _ => None,
-            };
-            PortStatus::Opened { banner }
-        }
+        Some(Ok(_)) => PortStatus::Opened { banner: None },
         Some(Err(ref e)) => {
             if e.kind() == io::ErrorKind::ConnectionRefused {
                 PortStatus::Closed